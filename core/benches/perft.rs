@@ -0,0 +1,44 @@
+//! Compares perft speed of chess boards stored as bitboards and as an array of squares. Criterion
+//! reports throughput in leaf nodes per second
+//!
+//! Run with `cargo bench -p giga_core --features mailbox`
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use giga_core::board::Board;
+use giga_core::game::Kind;
+
+/// Positions and the depth each one is searched to
+const POSITIONS: [(&str, &str, u32); 3] = [
+    (
+        "start",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        4,
+    ),
+    (
+        "kiwipete",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        3,
+    ),
+    ("endgame", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 5),
+];
+
+fn perft(c: &mut Criterion) {
+    let mut group = c.benchmark_group("perft");
+    group.sample_size(10);
+    for (name, fen, depth) in POSITIONS.iter() {
+        let board = Board::from_fen(Kind::Chess, fen).unwrap();
+        let mailbox = board.to_mailbox();
+        group.throughput(Throughput::Elements(board.perft(*depth)));
+        group.bench_with_input(BenchmarkId::new("bitboard", name), depth, |b, depth| {
+            b.iter(|| board.perft(*depth))
+        });
+        group.bench_with_input(BenchmarkId::new("mailbox", name), depth, |b, depth| {
+            b.iter(|| mailbox.perft(*depth))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, perft);
+criterion_main!(benches);
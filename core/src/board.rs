@@ -1 +1,1124 @@
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+use std::fmt;
 
+use crate::fairy::WinCondition;
+use crate::game::{ColorKind, GameEndCause, Kind, RawMove, RawSquarePosition, Variant};
+
+mod bitboard;
+mod chess;
+mod chess960;
+mod contrasting;
+mod fairy;
+mod fen;
+mod perft;
+mod zobrist;
+
+pub use chess960::{
+    chess960_back_rank, chess960_index_from_seed, CHESS960_POSITIONS, STANDARD_CHESS960_INDEX,
+};
+pub use zobrist::{ZobristKeys, MAX_SIDE_LEN, POLYGLOT_KEY_COUNT};
+
+/// The char used to indicate an empty square in a board string
+pub const EMPTY_SQUARE_CHAR: char = '.';
+
+/// The char used to separate ranks in a board string
+pub const RANK_SEPARATOR: char = '/';
+
+/// The halfmove clock at which the game is drawn without either player claiming it
+pub const SEVENTY_FIVE_MOVE_HALFMOVES: u32 = 150;
+
+/// The kinds of pieces that can be placed on a board. Which pieces are used (and the chars that
+/// represent them) depends on the game kind being played
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PieceKind {
+    King,
+    Queen,
+    Rook,
+    Bishop,
+    Knight,
+    Pawn,
+    Elephant,
+    Bear,
+    Horse,
+    Dragon,
+    Moose,
+    Weasel,
+
+    /// A piece of a fairy game, indexing the pieces of its definition. See the `fairy` module
+    Fairy(u8),
+}
+
+/// A piece of a particular color
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Piece {
+    pub kind: PieceKind,
+    pub color: ColorKind,
+}
+
+/// The side of the king a castling rook starts on
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CastleSide {
+    /// The rook is on a higher file than the king (O-O in chess)
+    King,
+    /// The rook is on a lower file than the king (O-O-O in chess)
+    Queen,
+}
+
+/// The rooks each color may still castle with.
+/// Rooks are stored by file so that Chess960 positions, where the rooks can start on any file, are
+/// handled the same as standard chess
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CastlingRights {
+    /// Indexed by color id then castle side
+    rook_files: [[Option<u32>; 2]; 2],
+}
+
+/// Additional information about how a move affects the board beyond moving the piece on `src` to
+/// `dst`
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MoveKind {
+    /// A regular move or capture
+    Normal,
+
+    /// A pawn captures the pawn that just moved two squares past it. The captured pawn is not on
+    /// `dst`
+    EnPassant,
+
+    /// The king castles with the rook on `rook_src`. `dst` is the square the king ends on
+    Castle { rook_src: RawSquarePosition },
+
+    /// A pawn reaches the last rank and becomes the contained piece
+    Promotion(PieceKind),
+}
+
+/// A move on a particular board. Unlike `game::RawMove` this contains everything needed to apply
+/// the move without looking anything up
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub src: RawSquarePosition,
+    pub dst: RawSquarePosition,
+    pub kind: MoveKind,
+}
+
+pub type MoveList = SmallVec<[Move; 64]>;
+
+/// How a board stores its pieces. Chess boards use bitboards so that moves can be generated
+/// quickly, other game kinds store each square. `Board::to_mailbox` stores a chess board square by
+/// square too, to compare the two
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Squares {
+    Mailbox(Vec<Option<Piece>>),
+    Bitboards(Box<bitboard::Bitboards>),
+}
+
+/// What `Board::make_move` needs to take a move back with `Board::unmake_move`. Only valid for the
+/// board and move it was returned for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Undo {
+    /// The piece that moved, as it was before any promotion
+    moved: Piece,
+    captured: Option<Piece>,
+    to_move: ColorKind,
+    castling: CastlingRights,
+    en_passant: Option<RawSquarePosition>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    zobrist: u64,
+}
+
+/// A board for any game kind. The size of the board and the pieces that may be placed on it are
+/// determined by `kind`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(from = "zobrist::SerializedBoard")]
+pub struct Board {
+    kind: Kind,
+
+    /// The contents of each square, indexed by `RawSquarePosition`
+    squares: Squares,
+
+    /// The color whose turn it is
+    to_move: ColorKind,
+
+    castling: CastlingRights,
+
+    /// The square a pawn skipped over on the last move, if the last move was a double pawn push
+    en_passant: Option<RawSquarePosition>,
+
+    /// The number of moves since the last capture or pawn move
+    halfmove_clock: u32,
+
+    /// Starts at 1 and is incremented after the last color moves
+    fullmove_number: u32,
+
+    /// The Zobrist key of the position, without the en passant part. Kept up to date by every
+    /// method that changes the position
+    #[serde(skip)]
+    zobrist: u64,
+}
+
+/// The reasons a board string can fail to parse
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The number of ranks in the string did not match the side length of the board
+    RankCount { expected: u32, found: u32 },
+
+    /// A rank had the wrong number of squares. `rank` is 0 based, starting from the bottom
+    RankLength {
+        rank: u32,
+        expected: u32,
+        found: u32,
+    },
+
+    /// A char did not correspond to any piece in the game kind being parsed
+    UnknownPiece(char),
+
+    /// A required FEN field was missing
+    MissingField(&'static str),
+
+    /// The side to move was not 'w' or 'b'
+    InvalidSideToMove(String),
+
+    /// A castling right char did not refer to a rook on the back rank of its color
+    InvalidCastling(char),
+
+    /// The en passant target was not a square on the board behind a pawn that could just have
+    /// moved two squares past it
+    InvalidEnPassant(String),
+
+    /// One of the move counters was not a valid number
+    InvalidNumber(String),
+}
+
+/// Returns the pieces used by a game kind mapped to the chars that represent them.
+/// The chars are for the first color. The second color uses the lowercase version of the same char
+fn piece_chars(kind: Kind) -> &'static [(PieceKind, char)] {
+    match kind {
+        Kind::Chess => &[
+            (PieceKind::King, 'K'),
+            (PieceKind::Queen, 'Q'),
+            (PieceKind::Rook, 'R'),
+            (PieceKind::Bishop, 'B'),
+            (PieceKind::Knight, 'N'),
+            (PieceKind::Pawn, 'P'),
+        ],
+        Kind::ContrastingChess => &[
+            (PieceKind::King, 'K'),
+            (PieceKind::Elephant, 'E'),
+            (PieceKind::Bear, 'B'),
+            (PieceKind::Horse, 'H'),
+            (PieceKind::Dragon, 'D'),
+            (PieceKind::Moose, 'M'),
+            (PieceKind::Weasel, 'W'),
+        ],
+        Kind::Fairy(fairy) => fairy.game().piece_chars(),
+    }
+}
+
+/// Returns the starting position of a game kind as a board string
+fn start_position(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Chess => "rnbqkbnr/pppppppp/......../......../......../......../PPPPPPPP/RNBQKBNR",
+        Kind::ContrastingChess => concat!(
+            "bmdhekhdmb/ewwwwwwwwe/........../........../........../",
+            "........../........../........../EWWWWWWWWE/BMDHEKHDMB"
+        ),
+        Kind::Fairy(fairy) => fairy.game().board_string(),
+    }
+}
+
+impl CastleSide {
+    fn index(self) -> usize {
+        match self {
+            CastleSide::King => 0,
+            CastleSide::Queen => 1,
+        }
+    }
+}
+
+impl CastlingRights {
+    /// No color may castle
+    pub fn none() -> CastlingRights {
+        CastlingRights::default()
+    }
+
+    /// Grants every color the right to castle with the outermost rook on each side of its king,
+    /// provided the king and rook are on that color's back rank
+    pub fn from_board(board: &Board) -> CastlingRights {
+        let mut rights = CastlingRights::none();
+        for color in [ColorKind::WHITE, ColorKind::BLACK].iter() {
+            let king = match board.king_square(*color) {
+                Some(king) => king,
+                None => continue,
+            };
+            let (king_file, king_rank) = board.file_rank(king);
+            if king_rank != board.back_rank(*color) {
+                continue;
+            }
+            for file in 0..board.side_len() {
+                let pos = board.square(file, king_rank).unwrap();
+                if board.get(pos) != Some(Piece::new(PieceKind::Rook, *color)) {
+                    continue;
+                }
+                if file < king_file && rights.get(*color, CastleSide::Queen).is_none() {
+                    rights.set(*color, CastleSide::Queen, Some(file));
+                } else if file > king_file {
+                    rights.set(*color, CastleSide::King, Some(file));
+                }
+            }
+        }
+        rights
+    }
+
+    /// Returns the file of the rook a color may castle with on a side, if any
+    pub fn get(&self, color: ColorKind, side: CastleSide) -> Option<u32> {
+        self.rook_files
+            .get(color.id() as usize)
+            .and_then(|sides| sides[side.index()])
+    }
+
+    pub fn set(&mut self, color: ColorKind, side: CastleSide, rook_file: Option<u32>) {
+        self.rook_files[color.id() as usize][side.index()] = rook_file;
+    }
+
+    /// Removes all castling rights for a color
+    pub fn clear(&mut self, color: ColorKind) {
+        self.rook_files[color.id() as usize] = [None, None];
+    }
+
+    /// Returns true if no color can castle
+    pub fn is_empty(&self) -> bool {
+        self.rook_files.iter().flatten().all(Option::is_none)
+    }
+}
+
+impl Move {
+    pub fn new(src: RawSquarePosition, dst: RawSquarePosition) -> Move {
+        Move {
+            src,
+            dst,
+            kind: MoveKind::Normal,
+        }
+    }
+
+    pub fn promotion(&self) -> Option<PieceKind> {
+        match self.kind {
+            MoveKind::Promotion(piece) => Some(piece),
+            _ => None,
+        }
+    }
+}
+
+impl PieceKind {
+    /// Returns true for pawns and the pieces that take their place in other game kinds. They
+    /// never move backwards, so moving one resets the halfmove clock like a capture does
+    pub fn is_pawn(self) -> bool {
+        matches!(self, PieceKind::Pawn | PieceKind::Weasel)
+    }
+
+    /// Returns the char used to represent this piece in a game kind, or None if this piece is not
+    /// part of the game
+    pub fn to_char(self, kind: Kind, color: ColorKind) -> Option<char> {
+        piece_chars(kind)
+            .iter()
+            .find(|(piece, _)| *piece == self)
+            .map(|(_, c)| match color.id() {
+                0 => *c,
+                _ => c.to_ascii_lowercase(),
+            })
+    }
+}
+
+impl Piece {
+    pub fn new(kind: PieceKind, color: ColorKind) -> Piece {
+        Piece { kind, color }
+    }
+
+    /// Parses a piece char for the given game kind. Uppercase chars belong to the first color and
+    /// lowercase chars to the second
+    pub fn from_char(kind: Kind, c: char) -> Option<Piece> {
+        let color = if c.is_ascii_uppercase() {
+            ColorKind::WHITE
+        } else {
+            ColorKind::BLACK
+        };
+        let upper = c.to_ascii_uppercase();
+        piece_chars(kind)
+            .iter()
+            .find(|(_, piece_char)| *piece_char == upper)
+            .map(|(piece, _)| Piece::new(*piece, color))
+    }
+
+    /// Returns the char representing this piece in a game kind
+    pub fn to_char(self, kind: Kind) -> char {
+        self.kind
+            .to_char(kind, self.color)
+            .expect("Piece is not used by this game kind")
+    }
+}
+
+impl Board {
+    /// Creates an empty board with the first color to move
+    pub fn empty(kind: Kind) -> Board {
+        let side_len = kind.side_len();
+        let mut board = Board {
+            kind,
+            squares: match kind {
+                Kind::Chess => Squares::Bitboards(Box::new(bitboard::Bitboards::empty())),
+                _ => Squares::Mailbox(vec![None; (side_len * side_len) as usize]),
+            },
+            to_move: ColorKind::WHITE,
+            castling: CastlingRights::none(),
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            zobrist: 0,
+        };
+        board.zobrist = board.key_without_en_passant(board.keys());
+        board
+    }
+
+    /// Creates a board with all pieces in their starting positions
+    pub fn start_position(kind: Kind) -> Board {
+        let mut board = Board::from_board_string(kind, start_position(kind))
+            .expect("Start position for game kind is invalid");
+        board.set_castling(CastlingRights::from_board(&board));
+        board
+    }
+
+    /// Changes this position to follow the rules of `variants`. With `Variant::NoCastling` every
+    /// castling right is taken away, and as rights are only ever lost by moving, no castling move
+    /// is possible for the rest of the game. `Variant::Chess960` only affects which position a
+    /// game starts from, see `Board::chess960`
+    pub fn apply_variants(&mut self, variants: &[Variant]) {
+        if variants.contains(&Variant::NoCastling) {
+            self.set_castling(CastlingRights::none());
+        }
+    }
+
+    /// Parses a board string as sent in `In::GameStart`.
+    /// A board string lists every rank from the top of the board (rank 8 in chess) to the bottom,
+    /// separated by '/'. Each rank contains exactly one char per square, either a game defined
+    /// piece char, or '.' for an empty square. Whitespace is ignored.
+    /// The returned board has the first color to move and no castling rights
+    pub fn from_board_string(kind: Kind, s: &str) -> Result<Board, ParseError> {
+        let side_len = kind.side_len();
+        let mut board = Board::empty(kind);
+
+        let ranks: Vec<&str> = s.trim().split(RANK_SEPARATOR).collect();
+        if ranks.len() as u32 != side_len {
+            return Err(ParseError::RankCount {
+                expected: side_len,
+                found: ranks.len() as u32,
+            });
+        }
+
+        for (i, rank_str) in ranks.iter().enumerate() {
+            let rank = side_len - 1 - i as u32;
+            let chars: Vec<char> = rank_str.chars().filter(|c| !c.is_whitespace()).collect();
+            if chars.len() as u32 != side_len {
+                return Err(ParseError::RankLength {
+                    rank,
+                    expected: side_len,
+                    found: chars.len() as u32,
+                });
+            }
+            for (file, c) in chars.into_iter().enumerate() {
+                if c == EMPTY_SQUARE_CHAR {
+                    continue;
+                }
+                let piece = Piece::from_char(kind, c).ok_or(ParseError::UnknownPiece(c))?;
+                let pos = board.square(file as u32, rank).unwrap();
+                board.set(pos, Some(piece));
+            }
+        }
+
+        Ok(board)
+    }
+
+    /// Renders this board as a board string. See `from_board_string` for the format
+    pub fn to_board_string(&self) -> String {
+        let side_len = self.side_len();
+        let mut result = String::with_capacity(((side_len + 1) * side_len) as usize);
+        for rank in (0..side_len).rev() {
+            for file in 0..side_len {
+                let pos = self.square(file, rank).unwrap();
+                result.push(match self.get(pos) {
+                    Some(piece) => piece.to_char(self.kind),
+                    None => EMPTY_SQUARE_CHAR,
+                });
+            }
+            if rank != 0 {
+                result.push(RANK_SEPARATOR);
+            }
+        }
+        result
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// The number of squares along one edge of this board
+    pub fn side_len(&self) -> u32 {
+        self.kind.side_len()
+    }
+
+    /// Returns the square at the given file and rank (both 0 based), or None if the square is off
+    /// the board
+    pub fn square(&self, file: u32, rank: u32) -> Option<RawSquarePosition> {
+        let side_len = self.side_len();
+        if file < side_len && rank < side_len {
+            Some(RawSquarePosition::new(rank * side_len + file))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the (file, rank) of a square
+    pub fn file_rank(&self, pos: RawSquarePosition) -> (u32, u32) {
+        let side_len = self.side_len();
+        (pos.index() % side_len, pos.index() / side_len)
+    }
+
+    /// Returns the algebraic name of a square, for example "e4". Ranks beyond 9 use multiple digits
+    pub fn square_name(&self, pos: RawSquarePosition) -> String {
+        let (file, rank) = self.file_rank(pos);
+        format!("{}{}", (b'a' + file as u8) as char, rank + 1)
+    }
+
+    /// Parses an algebraic square name such as "e4"
+    pub fn parse_square(&self, name: &str) -> Option<RawSquarePosition> {
+        let mut chars = name.chars();
+        let file = chars.next()?;
+        if !file.is_ascii_lowercase() {
+            return None;
+        }
+        let rank: u32 = chars.as_str().parse().ok()?;
+        if rank == 0 {
+            return None;
+        }
+        self.square(file as u32 - 'a' as u32, rank - 1)
+    }
+
+    /// Attempts to add file and rank to pos, returns Some(sum_pos) if the square is within the
+    /// bounds of the board
+    pub fn offset(
+        &self,
+        pos: RawSquarePosition,
+        file: i32,
+        rank: i32,
+    ) -> Option<RawSquarePosition> {
+        let (src_file, src_rank) = self.file_rank(pos);
+        let dst_file = src_file as i32 + file;
+        let dst_rank = src_rank as i32 + rank;
+        if dst_file < 0 || dst_rank < 0 {
+            return None;
+        }
+        self.square(dst_file as u32, dst_rank as u32)
+    }
+
+    /// Returns true if pos refers to a square on this board
+    pub fn contains(&self, pos: RawSquarePosition) -> bool {
+        (pos.index() as usize) < self.squares.len()
+    }
+
+    pub fn get(&self, pos: RawSquarePosition) -> Option<Piece> {
+        self.squares.get(pos.index() as usize)
+    }
+
+    /// Places a piece (or nothing) on a square, returning what was there before
+    pub fn set(&mut self, pos: RawSquarePosition, piece: Option<Piece>) -> Option<Piece> {
+        let old = self.squares.set(pos.index() as usize, piece);
+        self.hash_square(pos, old, piece);
+        old
+    }
+
+    /// The color whose turn it is
+    pub fn to_move(&self) -> ColorKind {
+        self.to_move
+    }
+
+    pub fn set_to_move(&mut self, color: ColorKind) {
+        self.hash_to_move(self.to_move, color);
+        self.to_move = color;
+    }
+
+    pub fn castling(&self) -> CastlingRights {
+        self.castling
+    }
+
+    pub fn set_castling(&mut self, castling: CastlingRights) {
+        self.hash_castling(self.castling, castling);
+        self.castling = castling;
+    }
+
+    /// The square a pawn skipped over on the last move, which an enemy pawn may capture onto
+    pub fn en_passant(&self) -> Option<RawSquarePosition> {
+        self.en_passant
+    }
+
+    pub fn set_en_passant(&mut self, en_passant: Option<RawSquarePosition>) {
+        self.en_passant = en_passant;
+    }
+
+    /// Returns true if a pawn of the color that moved last could just have skipped over `pos` with
+    /// a double step: `pos` is empty, two ranks in front of that color's back rank, and the pawn
+    /// stands right past it
+    fn is_possible_en_passant(&self, pos: RawSquarePosition) -> bool {
+        if !self.contains(pos) || self.get(pos).is_some() {
+            return false;
+        }
+        let color_count = self.kind.color_count();
+        let mover = ColorKind::new((self.to_move.id() + color_count - 1) % color_count);
+        let forward = self.forward(mover);
+        let (_, rank) = self.file_rank(pos);
+        rank as i32 == self.back_rank(mover) as i32 + 2 * forward
+            && self.offset(pos, 0, forward).and_then(|pawn| self.get(pawn))
+                == Some(Piece::new(PieceKind::Pawn, mover))
+    }
+
+    /// The number of moves since the last capture or pawn move
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    pub fn set_halfmove_clock(&mut self, halfmove_clock: u32) {
+        self.halfmove_clock = halfmove_clock;
+    }
+
+    /// The current move number, starting at 1
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    pub fn set_fullmove_number(&mut self, fullmove_number: u32) {
+        self.fullmove_number = fullmove_number;
+    }
+
+    /// Returns the color that moves after `color`
+    pub fn next_color(&self, color: ColorKind) -> ColorKind {
+        ColorKind::new((color.id() + 1) % self.kind.color_count())
+    }
+
+    /// The rank a color's pieces start on. The bottom rank for the first color and the top rank
+    /// for the second
+    pub fn back_rank(&self, color: ColorKind) -> u32 {
+        match color.id() {
+            0 => 0,
+            _ => self.side_len() - 1,
+        }
+    }
+
+    /// The direction a color's pawns move in, 1 for up the board and -1 for down
+    pub fn forward(&self, color: ColorKind) -> i32 {
+        match color.id() {
+            0 => 1,
+            _ => -1,
+        }
+    }
+
+    /// Returns the square of a color's king, or of its royal piece in fairy games, if it has one
+    pub fn king_square(&self, color: ColorKind) -> Option<RawSquarePosition> {
+        self.pieces()
+            .find(|(_, piece)| self.is_royal(piece.kind) && piece.color == color)
+            .map(|(pos, _)| pos)
+    }
+
+    /// Returns true for the pieces that win the game when checkmated (or captured, in some fairy
+    /// games)
+    pub fn is_royal(&self, piece: PieceKind) -> bool {
+        match self.kind {
+            Kind::Fairy(fairy) => fairy.game().piece(piece).is_some_and(|piece| piece.royal),
+            _ => piece == PieceKind::King,
+        }
+    }
+
+    /// Returns true if a move may leave the mover's royal pieces attacked, because the game is won
+    /// by capturing them rather than by checkmate
+    fn royal_capture(&self) -> bool {
+        match self.kind {
+            Kind::Fairy(fairy) => fairy.game().win_condition() == WinCondition::RoyalCapture,
+            _ => false,
+        }
+    }
+
+    /// Returns true if any piece of color `by` attacks `pos`
+    pub fn is_attacked(&self, pos: RawSquarePosition, by: ColorKind) -> bool {
+        if let Squares::Bitboards(bitboards) = &self.squares {
+            return bitboard::is_attacked(bitboards, pos, by);
+        }
+        match self.kind {
+            Kind::Chess => chess::is_attacked(self, pos, by),
+            Kind::ContrastingChess => contrasting::is_attacked(self, pos, by),
+            Kind::Fairy(fairy) => fairy::is_attacked(self, fairy.game(), pos, by),
+        }
+    }
+
+    /// Returns true if the king of the color to move is attacked
+    pub fn is_in_check(&self) -> bool {
+        match self.king_square(self.to_move) {
+            Some(king) => self.is_attacked(king, self.next_color(self.to_move)),
+            None => false,
+        }
+    }
+
+    /// Enumerates every legal move for the color to move
+    pub fn legal_moves(&self) -> MoveList {
+        let mut moves = MoveList::new();
+        if let Squares::Bitboards(bitboards) = &self.squares {
+            bitboard::legal_moves(self, bitboards, &mut moves);
+            return moves;
+        }
+        match self.kind {
+            Kind::Chess => chess::pseudo_legal_moves(self, &mut moves),
+            Kind::ContrastingChess => contrasting::pseudo_legal_moves(self, &mut moves),
+            Kind::Fairy(fairy) => fairy::pseudo_legal_moves(self, fairy.game(), &mut moves),
+        }
+        let mut scratch = self.clone();
+        moves.retain(|m| scratch.is_pseudo_legal_move_legal(*m));
+        moves
+    }
+
+    /// Returns true if making a pseudo legal move doesn't leave the mover's king in check
+    fn is_pseudo_legal_move_legal(&mut self, m: Move) -> bool {
+        if self.royal_capture() {
+            return true;
+        }
+        let color = self.to_move;
+        let undo = self.make_move(m);
+        let legal = match self.king_square(color) {
+            Some(king) => !self.is_attacked(king, self.to_move),
+            None => true,
+        };
+        self.unmake_move(m, undo);
+        legal
+    }
+
+    /// Looks up the legal move described by a raw move. Returns None if the move is illegal.
+    /// Castling may be given as the king moving to its destination square, or the king moving onto
+    /// the rook it castles with. Promotions must name the piece being promoted to, and moves that
+    /// aren't promotions must not
+    pub fn move_from_raw(&self, raw: &RawMove) -> Option<Move> {
+        if !self.contains(raw.src) || !self.contains(raw.dst) || raw.extra.is_some() {
+            return None;
+        }
+        self.legal_moves().into_iter().find(|m| {
+            if m.src != raw.src || m.promotion() != raw.promotion {
+                return false;
+            }
+            match m.kind {
+                MoveKind::Castle { rook_src } => m.dst == raw.dst || rook_src == raw.dst,
+                _ => m.dst == raw.dst,
+            }
+        })
+    }
+
+    /// Converts a move into the raw form sent over the protocol.
+    /// Castling is sent as the king moving to its destination when the king and rook start on
+    /// their standard squares, otherwise as the king moving onto the rook to avoid ambiguity
+    pub fn to_raw_move(&self, m: Move) -> RawMove {
+        match m.kind {
+            MoveKind::Castle { rook_src } => {
+                let (king_file, _) = self.file_rank(m.src);
+                let (rook_file, _) = self.file_rank(rook_src);
+                let standard =
+                    king_file == 4 && (rook_file == 0 || rook_file == self.side_len() - 1);
+                if standard {
+                    RawMove::new(m.src, m.dst)
+                } else {
+                    RawMove::new(m.src, rook_src)
+                }
+            }
+            MoveKind::Promotion(piece) => RawMove::with_promotion(m.src, m.dst, piece),
+            _ => RawMove::new(m.src, m.dst),
+        }
+    }
+
+    /// Makes a move on the board without checking for legality, then passes the turn to the next
+    /// color. Use `make_move` instead if the move will be taken back
+    pub fn apply_move(&mut self, m: Move) {
+        self.make_move(m);
+    }
+
+    /// Makes a move like `apply_move`, returning what `unmake_move` needs to take it back. Search
+    /// can walk the move tree on a single board this way, rather than copying it for every move
+    pub fn make_move(&mut self, m: Move) -> Undo {
+        let color = self.to_move;
+        let piece = self
+            .get(m.src)
+            .expect("Tried to apply a move from an empty square");
+        let mut undo = Undo {
+            moved: piece,
+            captured: None,
+            to_move: color,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            zobrist: self.zobrist,
+        };
+        self.set(m.src, None);
+        let mut captured = None;
+        let mut en_passant = None;
+
+        match m.kind {
+            MoveKind::Normal => {
+                captured = self.set(m.dst, Some(piece));
+                if piece.kind == PieceKind::Pawn {
+                    let (file, src_rank) = self.file_rank(m.src);
+                    let (_, dst_rank) = self.file_rank(m.dst);
+                    if (src_rank as i32 - dst_rank as i32).abs() == 2 {
+                        en_passant = self.square(file, (src_rank + dst_rank) / 2);
+                    }
+                }
+            }
+            MoveKind::EnPassant => {
+                let (dst_file, _) = self.file_rank(m.dst);
+                let (_, src_rank) = self.file_rank(m.src);
+                let captured_pos = self.square(dst_file, src_rank).unwrap();
+                captured = self.set(captured_pos, None);
+                self.set(m.dst, Some(piece));
+            }
+            MoveKind::Castle { rook_src } => {
+                let rook = self.set(rook_src, None);
+                self.set(m.dst, Some(piece));
+                self.set(self.castle_rook_dst(m, rook_src), rook);
+            }
+            MoveKind::Promotion(promoted) => {
+                captured = self.set(m.dst, Some(Piece::new(promoted, color)));
+            }
+        }
+
+        self.update_castling_rights(m.src, piece);
+        if let Some(captured) = captured {
+            self.update_castling_rights(m.dst, captured);
+        }
+
+        if piece.kind.is_pawn() || captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        self.en_passant = en_passant;
+        self.set_to_move(self.next_color(color));
+        if self.to_move == ColorKind::WHITE {
+            self.fullmove_number += 1;
+        }
+        undo.captured = captured;
+        undo
+    }
+
+    /// Takes back `m`, which must be the last move made, restoring the board to exactly how it
+    /// was before `make_move` returned `undo`
+    pub fn unmake_move(&mut self, m: Move, undo: Undo) {
+        match m.kind {
+            MoveKind::Normal => {
+                let piece = self.set(m.dst, undo.captured);
+                self.set(m.src, piece);
+            }
+            MoveKind::EnPassant => {
+                let piece = self.set(m.dst, None);
+                self.set(m.src, piece);
+                let (dst_file, _) = self.file_rank(m.dst);
+                let (_, src_rank) = self.file_rank(m.src);
+                self.set(self.square(dst_file, src_rank).unwrap(), undo.captured);
+            }
+            MoveKind::Castle { rook_src } => {
+                // In Chess960 the king and rook may land on each other's starting squares, so
+                // both are lifted before either is put back
+                let king = self.set(m.dst, None);
+                let rook = self.set(self.castle_rook_dst(m, rook_src), None);
+                self.set(rook_src, rook);
+                self.set(m.src, king);
+            }
+            MoveKind::Promotion(_) => {
+                self.set(m.dst, undo.captured);
+                self.set(m.src, Some(undo.moved));
+            }
+        }
+        self.to_move = undo.to_move;
+        self.castling = undo.castling;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.fullmove_number = undo.fullmove_number;
+        self.zobrist = undo.zobrist;
+    }
+
+    /// The square the rook castled with in `m` ends up on
+    fn castle_rook_dst(&self, m: Move, rook_src: RawSquarePosition) -> RawSquarePosition {
+        let (_, rank) = self.file_rank(m.dst);
+        let file = if rook_src.index() > m.src.index() {
+            chess::KINGSIDE_ROOK_DST_FILE
+        } else {
+            chess::QUEENSIDE_ROOK_DST_FILE
+        };
+        self.square(file, rank).unwrap()
+    }
+
+    /// Removes the castling rights that are lost when `piece` leaves (or is captured on) `pos`
+    fn update_castling_rights(&mut self, pos: RawSquarePosition, piece: Piece) {
+        if self.castling.is_empty() {
+            return;
+        }
+        let mut castling = self.castling;
+        match piece.kind {
+            PieceKind::King => castling.clear(piece.color),
+            PieceKind::Rook => {
+                let (file, rank) = self.file_rank(pos);
+                if rank != self.back_rank(piece.color) {
+                    return;
+                }
+                for side in [CastleSide::King, CastleSide::Queen].iter() {
+                    if castling.get(piece.color, *side) == Some(file) {
+                        castling.set(piece.color, *side, None);
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.set_castling(castling);
+    }
+
+    /// Returns why the game is over if the color to move has no legal moves, neither color has
+    /// the material to checkmate, or 75 moves have been made by each color without a capture or
+    /// pawn move. Repetitions depend on the moves that led here, and are detected by
+    /// `history::PositionHistory`
+    pub fn game_end(&self) -> Option<GameEndCause> {
+        if self.royal_capture() && self.king_square(self.to_move).is_none() {
+            Some(GameEndCause::RoyalCaptured)
+        } else if self.legal_moves().is_empty() {
+            if self.is_in_check() {
+                Some(GameEndCause::Checkmate)
+            } else {
+                Some(GameEndCause::Stalemate)
+            }
+        } else if self.insufficient_material() {
+            Some(GameEndCause::DeadPosition)
+        } else if self.halfmove_clock >= SEVENTY_FIVE_MOVE_HALFMOVES {
+            Some(GameEndCause::SeventyFiveMoveRule)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if no sequence of legal moves could end in checkmate, because neither color has
+    /// enough material left
+    pub fn insufficient_material(&self) -> bool {
+        if let Squares::Bitboards(bitboards) = &self.squares {
+            return bitboard::insufficient_material(bitboards);
+        }
+        match self.kind {
+            Kind::Chess => chess::insufficient_material(self),
+            Kind::ContrastingChess => contrasting::insufficient_material(self),
+            Kind::Fairy(fairy) => fairy::insufficient_material(self, fairy.game()),
+        }
+    }
+
+    /// Clears all pieces off the board
+    pub fn clear(&mut self) {
+        for pos in self.squares() {
+            self.set(pos, None);
+        }
+    }
+
+    /// Enumerates every square on the board
+    pub fn squares(&self) -> impl Iterator<Item = RawSquarePosition> {
+        (0..self.squares.len() as u32).map(RawSquarePosition::new)
+    }
+
+    /// Enumerates all the pieces on the board along with the squares they occupy
+    pub fn pieces(&self) -> impl Iterator<Item = (RawSquarePosition, Piece)> + '_ {
+        (0..self.squares.len()).filter_map(move |i| {
+            self.squares
+                .get(i)
+                .map(|piece| (RawSquarePosition::new(i as u32), piece))
+        })
+    }
+
+    /// Copies this board into storage with one entry per square, which `Kind::Chess` boards
+    /// otherwise only use with bitboards. Moves are then generated one square at a time, so that
+    /// the two can be compared. The copy isn't equal to this board with `==`
+    #[cfg(any(test, feature = "mailbox"))]
+    pub fn to_mailbox(&self) -> Board {
+        let squares = (0..self.squares.len())
+            .map(|i| self.squares.get(i))
+            .collect();
+        Board {
+            squares: Squares::Mailbox(squares),
+            ..self.clone()
+        }
+    }
+
+    /// Enumerates the pieces belonging to a single color
+    pub fn pieces_for_color(
+        &self,
+        color: ColorKind,
+    ) -> impl Iterator<Item = (RawSquarePosition, Piece)> + '_ {
+        self.pieces().filter(move |(_, piece)| piece.color == color)
+    }
+}
+
+impl Squares {
+    fn len(&self) -> usize {
+        match self {
+            Squares::Mailbox(squares) => squares.len(),
+            Squares::Bitboards(_) => 64,
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<Piece> {
+        match self {
+            Squares::Mailbox(squares) => squares[index],
+            Squares::Bitboards(bitboards) => bitboards.get(index),
+        }
+    }
+
+    fn set(&mut self, index: usize, piece: Option<Piece>) -> Option<Piece> {
+        match self {
+            Squares::Mailbox(squares) => std::mem::replace(&mut squares[index], piece),
+            Squares::Bitboards(bitboards) => bitboards.set(index, piece),
+        }
+    }
+}
+
+/// Squares serialize as a list of every square either way
+impl Serialize for Squares {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq((0..self.len()).map(|i| self.get(i)))
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_board_string())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::RankCount { expected, found } => {
+                write!(f, "Expected {} ranks but found {}", expected, found)
+            }
+            ParseError::RankLength {
+                rank,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Expected {} squares in rank {} but found {}",
+                expected,
+                rank + 1,
+                found
+            ),
+            ParseError::UnknownPiece(c) => write!(f, "Unknown piece char '{}'", c),
+            ParseError::MissingField(field) => write!(f, "Missing FEN field: {}", field),
+            ParseError::InvalidSideToMove(side) => write!(f, "Invalid side to move '{}'", side),
+            ParseError::InvalidCastling(c) => write!(f, "Invalid castling right '{}'", c),
+            ParseError::InvalidEnPassant(square) => {
+                write!(f, "Invalid en passant square '{}'", square)
+            }
+            ParseError::InvalidNumber(number) => write!(f, "Invalid move counter '{}'", number),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position_round_trip() {
+        let board = Board::start_position(Kind::Chess);
+        assert_eq!(board.to_board_string(), start_position(Kind::Chess));
+        assert_eq!(
+            board.get(board.square(4, 0).unwrap()),
+            Some(Piece::new(PieceKind::King, ColorKind::WHITE))
+        );
+        assert_eq!(
+            board.get(board.square(3, 7).unwrap()),
+            Some(Piece::new(PieceKind::Queen, ColorKind::BLACK))
+        );
+        assert_eq!(board.pieces_for_color(ColorKind::WHITE).count(), 16);
+        assert_eq!(board.pieces_for_color(ColorKind::BLACK).count(), 16);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let board = Board::start_position(Kind::Chess);
+        let json = serde_json::to_string(&board).unwrap();
+        assert_eq!(serde_json::from_str::<Board>(&json).unwrap(), board);
+    }
+
+    #[test]
+    fn unmake_restores_the_board() {
+        fn walk(board: &mut Board, depth: u32) {
+            if depth == 0 {
+                return;
+            }
+            for m in board.legal_moves() {
+                let before = board.clone();
+                let undo = board.make_move(m);
+                walk(board, depth - 1);
+                board.unmake_move(m, undo);
+                assert_eq!(*board, before, "{:?}", m);
+            }
+        }
+        // Castling, en passant and promotions, including Chess960 castling where the king and
+        // rook swap squares
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "1r3kr1/8/8/8/8/8/8/1R3KR1 w GBgb - 0 1",
+        ]
+        .iter()
+        {
+            walk(&mut Board::from_fen(Kind::Chess, fen).unwrap(), 2);
+        }
+    }
+
+    #[test]
+    fn no_castling() {
+        let mut board =
+            Board::from_fen(Kind::Chess, "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert!(board.parse_uci("e1g1").is_ok());
+        board.apply_variants(&[Variant::NoCastling]);
+        assert!(board.castling().is_empty());
+        assert!(board.parse_uci("e1g1").is_err());
+        assert!(board.parse_uci("e1c1").is_err());
+        assert_eq!(board.to_fen(), "r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1");
+
+        assert!(Kind::Chess.supports_variants(&[Variant::Chess960, Variant::NoCastling]));
+        assert!(!Kind::Chess.supports_variants(&[Variant::NoCastling, Variant::NoCastling]));
+        assert!(Kind::ContrastingChess.supports_variants(&[]));
+    }
+
+    #[test]
+    fn invalid_board_strings() {
+        assert_eq!(
+            Board::from_board_string(Kind::Chess, "......../........"),
+            Err(ParseError::RankCount {
+                expected: 8,
+                found: 2
+            })
+        );
+        let short_rank = "......./......../......../......../......../......../......../........";
+        assert_eq!(
+            Board::from_board_string(Kind::Chess, short_rank),
+            Err(ParseError::RankLength {
+                rank: 7,
+                expected: 8,
+                found: 7
+            })
+        );
+        let bad_piece = "x......./......../......../......../......../......../......../........";
+        assert_eq!(
+            Board::from_board_string(Kind::Chess, bad_piece),
+            Err(ParseError::UnknownPiece('x'))
+        );
+    }
+}
@@ -0,0 +1,717 @@
+//! Move generation for `Kind::Chess`. Chess boards store their pieces as bitboards, 64 bit sets
+//! with one bit per square, instead of an array of squares. Moves are generated for every piece at
+//! once with precomputed knight, king and pawn attack tables and magic bitboards for sliding
+//! pieces, and only legal moves are generated: pieces pinned to their king and moves that don't
+//! answer a check are filtered out with masks instead of making each move and looking for attacks
+//! on the king.
+//!
+//! Everything else, such as making moves, castling rights and Zobrist keys, is handled by `Board`
+//! the same way for every game kind
+
+use std::sync::OnceLock;
+
+use super::chess::{self, BISHOP_DIRECTIONS, ROOK_DIRECTIONS};
+use super::{Board, CastleSide, Move, MoveKind, MoveList, Piece, PieceKind};
+use crate::game::{ColorKind, RawSquarePosition};
+
+/// A set of squares. Bit `rank * 8 + file` is set for each square in the set, the same index as
+/// `RawSquarePosition`
+type Bitboard = u64;
+
+/// The dark squares, starting with a1
+const DARK_SQUARES: Bitboard = 0xaa55_aa55_aa55_aa55;
+
+/// Indices of each piece kind into `Bitboards::pieces`
+const PAWN: usize = 0;
+const KNIGHT: usize = 1;
+const BISHOP: usize = 2;
+const ROOK: usize = 3;
+const QUEEN: usize = 4;
+const KING: usize = 5;
+
+/// Multipliers that map every arrangement of the pieces that can block a rook on each square to a
+/// distinct index into its attack table, or to an index shared only with arrangements that have
+/// the same attacks. Found by trying random numbers until one worked
+const ROOK_MAGICS: [u64; 64] = [
+    0x4180_0080_20d4_c000,
+    0x0840_0020_0010_0040,
+    0x1200_0882_0040_2010,
+    0x4200_0410_c03a_0060,
+    0x3200_0820_a600_5014,
+    0x0a00_0826_0004_1011,
+    0x4100_0084_0200_4100,
+    0x0200_0401_0424_4082,
+    0x0101_8000_4000_8028,
+    0x2003_0040_0081_0022,
+    0x0002_0010_2042_0480,
+    0x2221_0009_0020_1001,
+    0x0200_8080_0800_0400,
+    0x1041_0002_0804_0100,
+    0x0404_00c8_0201_0410,
+    0x2240_8000_8000_4100,
+    0x0040_8080_0040_0030,
+    0x8000_8180_4000_2000,
+    0x0101_0500_2000_4014,
+    0x0080_8080_1000_0800,
+    0x0900_8280_0800_4400,
+    0x0000_8080_0200_0400,
+    0x0024_0400_0882_5001,
+    0x9800_0200_0844_890c,
+    0x1880_0048_4000_2000,
+    0x0020_1000_4000_2041,
+    0x4050_0020_2008_0402,
+    0x0000_0801_8010_0180,
+    0x0009_0011_0004_0800,
+    0x0000_0400_8002_0080,
+    0x8400_1854_0010_0a01,
+    0x1882_0042_0001_0084,
+    0x0040_8040_0080_0025,
+    0x0000_4000_8080_2004,
+    0x1020_0021_8180_1000,
+    0x680d_1120_4200_0a02,
+    0x1029_0008_0100_0410,
+    0x0102_8004_0080_0201,
+    0x0c04_1061_0400_0228,
+    0x2010_104a_8200_0423,
+    0x4000_9240_0021_8001,
+    0x2030_0040_2000_4014,
+    0x0104_1200_8042_0020,
+    0x0148_0800_1000_8080,
+    0x0410_0800_0501_0010,
+    0x1222_0030_0406_0008,
+    0x8000_0201_1814_0030,
+    0x1004_0c14_8142_0021,
+    0x0002_0040_8100_2200,
+    0x2801_0608_4820_8200,
+    0xc100_2000_8210_0880,
+    0x10d0_0080_0804_1180,
+    0x0100_8008_0004_0080,
+    0x2002_0200_8004_0080,
+    0x2aa0_1108_0230_8400,
+    0x0808_8411_1080_4200,
+    0x0524_2240_1080_0b01,
+    0x410a_2102_8040_0019,
+    0x0005_0008_4020_0011,
+    0x0010_0500_1000_0821,
+    0x0501_0010_0204_0801,
+    0x0011_002a_a804_0013,
+    0x0006_0008_5500_8c06,
+    0x0001_0000_8208_3041,
+];
+
+/// The same as `ROOK_MAGICS`, for bishops
+const BISHOP_MAGICS: [u64; 64] = [
+    0x0002_0831_0800_8100,
+    0x4020_020a_6047_0000,
+    0x0108_2821_0020_1009,
+    0x4011_0400_8800_2130,
+    0x0101_1040_0000_ac00,
+    0x0002_0812_4900_0080,
+    0x4201_0818_0424_0000,
+    0x4002_0041_0401_2041,
+    0x0000_4032_0801_1301,
+    0x000c_1822_2802_0124,
+    0x4000_0414_0414_4040,
+    0x0120_5104_0082_0010,
+    0x0004_0404_2000_1140,
+    0x8000_4082_2020_0121,
+    0x2201_40b2_0802_4000,
+    0x0411_0600_8404_0308,
+    0x1408_0020_88d0_0080,
+    0x0020_c418_024c_0040,
+    0x000c_2208_0722_2200,
+    0x4414_8018_0208_408c,
+    0x0002_0024_1202_0300,
+    0x08e1_0000_8060_0200,
+    0x8008_8041_0848_0202,
+    0x1232_0001_8484_4100,
+    0x0090_0800_1020_8110,
+    0x8012_8200_2024_0414,
+    0x0002_0200_4108_0200,
+    0xa040_0400_0602_0908,
+    0x0180_4040_2c01_0044,
+    0x0010_0900_1024_0100,
+    0x2004_8280_0908_2800,
+    0x0302_0084_0044_4804,
+    0x1488_04c0_0085_0808,
+    0x8004_1004_0003_2400,
+    0x0802_0a05_0022_0800,
+    0x0034_0401_081c_0100,
+    0x0140_5080_2002_0200,
+    0xa010_1000_4100_2400,
+    0x8605_4602_0111_9800,
+    0xa209_0042_1000_8a00,
+    0x008a_0904_4041_2202,
+    0x2020_9801_1025_08a4,
+    0x4400_0844_1000_0200,
+    0x000a_40c2_0081_580c,
+    0x000e_e0a0_0882_2900,
+    0x0b40_8080_8080_0500,
+    0x0220_0154_0090_0114,
+    0x0942_2801_0223_8904,
+    0x4001_0401_4440_8000,
+    0x00c0_2402_1884_0000,
+    0x0981_b820_9410_0018,
+    0x4020_c012_0a02_0450,
+    0x8004_0204_4504_0821,
+    0x0000_6024_104c_9000,
+    0x5805_5002_0821_1100,
+    0x0030_8102_0482_0002,
+    0xe019_0080_80a0_1000,
+    0x0000_4282_2809_0400,
+    0x9800_0900_3402_0800,
+    0x0032_9200_0084_0400,
+    0x1008_1014_2102_4412,
+    0x1409_6820_2491_3a08,
+    0x0000_2002_1052_4080,
+    0x0468_1000_a081_0208,
+];
+
+/// The pieces on a `Kind::Chess` board
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Bitboards {
+    /// The squares occupied by each piece, indexed by color id then `piece_index`
+    pieces: [[Bitboard; 6]; 2],
+
+    /// The squares occupied by each color, indexed by color id
+    colors: [Bitboard; 2],
+
+    /// The contents of each square, so that the piece on a square can be found without searching
+    /// every bitboard
+    squares: [Option<Piece>; 64],
+}
+
+/// The attacks of a sliding piece on one square. The attack table has an entry for every
+/// arrangement of the pieces on `mask`, indexed by `(blockers * magic) >> shift`
+struct Magic {
+    /// The squares that can block the piece, not counting the edge of the board, since a piece
+    /// on the edge can't block anything behind it
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+    /// Where this square's entries start in the attack table
+    offset: usize,
+}
+
+/// Everything that is precomputed once and shared by every board
+struct Tables {
+    knight: [Bitboard; 64],
+    king: [Bitboard; 64],
+    /// The squares a pawn attacks, indexed by color id then the pawn's square
+    pawn_attacks: [[Bitboard; 64]; 2],
+    rook_magics: Vec<Magic>,
+    rook_attacks: Vec<Bitboard>,
+    bishop_magics: Vec<Magic>,
+    bishop_attacks: Vec<Bitboard>,
+    /// The squares strictly between two squares on the same rank, file or diagonal, indexed by
+    /// `a * 64 + b`. Empty if they don't share a line
+    between: Vec<Bitboard>,
+    /// The whole rank, file or diagonal two squares share, indexed by `a * 64 + b`. Empty if they
+    /// don't share one
+    line: Vec<Bitboard>,
+}
+
+fn bit(square: usize) -> Bitboard {
+    1 << square
+}
+
+/// The squares in a bitboard, from a1 up
+fn squares(mut bitboard: Bitboard) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            return None;
+        }
+        let square = bitboard.trailing_zeros() as usize;
+        bitboard &= bitboard - 1;
+        Some(square)
+    })
+}
+
+fn piece_index(kind: PieceKind) -> usize {
+    match kind {
+        PieceKind::Pawn => PAWN,
+        PieceKind::Knight => KNIGHT,
+        PieceKind::Bishop => BISHOP,
+        PieceKind::Rook => ROOK,
+        PieceKind::Queen => QUEEN,
+        PieceKind::King => KING,
+        _ => unreachable!("Only chess pieces are placed on a bitboard"),
+    }
+}
+
+fn position(square: usize) -> RawSquarePosition {
+    RawSquarePosition::new(square as u32)
+}
+
+/// The square `(file, rank)` away from `square`, if it is on the board
+fn offset(square: usize, file: i32, rank: i32) -> Option<usize> {
+    let file = (square % 8) as i32 + file;
+    let rank = (square / 8) as i32 + rank;
+    if (0..8).contains(&file) && (0..8).contains(&rank) {
+        Some((rank * 8 + file) as usize)
+    } else {
+        None
+    }
+}
+
+fn steps(square: usize, offsets: &[(i32, i32)]) -> Bitboard {
+    offsets
+        .iter()
+        .filter_map(|(file, rank)| offset(square, *file, *rank))
+        .fold(0, |bitboard, square| bitboard | bit(square))
+}
+
+/// The squares a slider on `square` attacks when the squares in `occupied` are occupied, found
+/// one step at a time. Only used to fill the tables
+fn slide(square: usize, occupied: Bitboard, directions: &[(i32, i32)]) -> Bitboard {
+    let mut attacks = 0;
+    for (file, rank) in directions {
+        let mut current = offset(square, *file, *rank);
+        while let Some(next) = current {
+            attacks |= bit(next);
+            if occupied & bit(next) != 0 {
+                break;
+            }
+            current = offset(next, *file, *rank);
+        }
+    }
+    attacks
+}
+
+/// Builds the magic attack table of one sliding piece
+fn magics(directions: &[(i32, i32)], multipliers: &[u64; 64]) -> (Vec<Magic>, Vec<Bitboard>) {
+    let mut magics = Vec::with_capacity(64);
+    let mut table = Vec::new();
+    for (square, magic) in multipliers.iter().enumerate() {
+        // A slide that reaches the edge of the board stops there whether or not it is occupied
+        let mask = directions.iter().fold(0, |mask, (file, rank)| {
+            let mut mask = mask;
+            let mut current = offset(square, *file, *rank);
+            while let Some(next) = current {
+                current = offset(next, *file, *rank);
+                if current.is_some() {
+                    mask |= bit(next);
+                }
+            }
+            mask
+        });
+        let bits = mask.count_ones();
+        let entry = Magic {
+            mask,
+            magic: *magic,
+            shift: 64 - bits,
+            offset: table.len(),
+        };
+        table.resize(table.len() + (1 << bits), 0);
+        // Enumerate every subset of the mask
+        let mut blockers: Bitboard = 0;
+        loop {
+            table[entry.index(blockers)] = slide(square, blockers, directions);
+            blockers = blockers.wrapping_sub(mask) & mask;
+            if blockers == 0 {
+                break;
+            }
+        }
+        magics.push(entry);
+    }
+    (magics, table)
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut knight = [0; 64];
+        let mut king = [0; 64];
+        let mut pawn_attacks = [[0; 64]; 2];
+        let mut between = vec![0; 64 * 64];
+        let mut line = vec![0; 64 * 64];
+        for square in 0..64 {
+            knight[square] = steps(square, &chess::KNIGHT_OFFSETS);
+            king[square] = steps(square, &chess::KING_OFFSETS);
+            pawn_attacks[0][square] = steps(square, &[(-1, 1), (1, 1)]);
+            pawn_attacks[1][square] = steps(square, &[(-1, -1), (1, -1)]);
+            for (file, rank) in ROOK_DIRECTIONS.iter().chain(BISHOP_DIRECTIONS.iter()) {
+                let ray = slide(square, 0, &[(*file, *rank)]);
+                let opposite = slide(square, 0, &[(-*file, -*rank)]);
+                let mut passed = 0;
+                let mut current = offset(square, *file, *rank);
+                while let Some(next) = current {
+                    between[square * 64 + next] = passed;
+                    line[square * 64 + next] = ray | opposite | bit(square);
+                    passed |= bit(next);
+                    current = offset(next, *file, *rank);
+                }
+            }
+        }
+        let (rook_magics, rook_attacks) = magics(&ROOK_DIRECTIONS, &ROOK_MAGICS);
+        let (bishop_magics, bishop_attacks) = magics(&BISHOP_DIRECTIONS, &BISHOP_MAGICS);
+        Tables {
+            knight,
+            king,
+            pawn_attacks,
+            rook_magics,
+            rook_attacks,
+            bishop_magics,
+            bishop_attacks,
+            between,
+            line,
+        }
+    })
+}
+
+impl Magic {
+    fn index(&self, occupied: Bitboard) -> usize {
+        self.offset + ((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+impl Tables {
+    fn rook(&self, square: usize, occupied: Bitboard) -> Bitboard {
+        self.rook_attacks[self.rook_magics[square].index(occupied)]
+    }
+
+    fn bishop(&self, square: usize, occupied: Bitboard) -> Bitboard {
+        self.bishop_attacks[self.bishop_magics[square].index(occupied)]
+    }
+
+    fn between(&self, a: usize, b: usize) -> Bitboard {
+        self.between[a * 64 + b]
+    }
+
+    fn line(&self, a: usize, b: usize) -> Bitboard {
+        self.line[a * 64 + b]
+    }
+}
+
+impl Bitboards {
+    /// No pieces
+    pub fn empty() -> Bitboards {
+        Bitboards {
+            pieces: [[0; 6]; 2],
+            colors: [0; 2],
+            squares: [None; 64],
+        }
+    }
+
+    pub fn get(&self, square: usize) -> Option<Piece> {
+        self.squares[square]
+    }
+
+    /// Places a piece (or nothing) on a square, returning what was there before. Panics if the
+    /// piece isn't a chess piece of either color
+    pub fn set(&mut self, square: usize, piece: Option<Piece>) -> Option<Piece> {
+        let old = std::mem::replace(&mut self.squares[square], piece);
+        for piece in old.iter().chain(piece.iter()) {
+            let color = piece.color.id() as usize;
+            self.pieces[color][piece_index(piece.kind)] ^= bit(square);
+            self.colors[color] ^= bit(square);
+        }
+        old
+    }
+
+    fn occupied(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
+    fn king(&self, color: usize) -> Option<usize> {
+        let kings = self.pieces[color][KING];
+        if kings == 0 {
+            None
+        } else {
+            Some(kings.trailing_zeros() as usize)
+        }
+    }
+
+    /// Every piece of either color that attacks `square` when the squares in `occupied` are
+    /// occupied
+    fn attackers(&self, square: usize, occupied: Bitboard) -> Bitboard {
+        let tables = tables();
+        let [white, black] = self.pieces;
+        let both = |index: usize| white[index] | black[index];
+        (tables.pawn_attacks[1][square] & white[PAWN])
+            | (tables.pawn_attacks[0][square] & black[PAWN])
+            | (tables.knight[square] & both(KNIGHT))
+            | (tables.king[square] & both(KING))
+            | (tables.bishop(square, occupied) & (both(BISHOP) | both(QUEEN)))
+            | (tables.rook(square, occupied) & (both(ROOK) | both(QUEEN)))
+    }
+}
+
+/// Adds every legal move for the color to move to `moves`
+pub fn legal_moves(board: &Board, bitboards: &Bitboards, moves: &mut MoveList) {
+    let tables = tables();
+    let us = board.to_move().id() as usize;
+    let own = bitboards.colors[us];
+    let enemy = bitboards.colors[1 - us];
+    let occupied = own | enemy;
+    let king = bitboards.king(us);
+
+    // Where pieces other than the king may move to: anywhere but their own pieces, unless they
+    // have to block or capture a checking piece
+    let mut targets = !own;
+    // Pieces that can't leave the line between their king and an enemy slider
+    let mut pinned = 0;
+    if let Some(king) = king {
+        let without_king = occupied & !bit(king);
+        for dst in squares(tables.king[king] & !own) {
+            if bitboards.attackers(dst, without_king) & enemy == 0 {
+                moves.push(Move::new(position(king), position(dst)));
+            }
+        }
+
+        let checkers = bitboards.attackers(king, occupied) & enemy;
+        match checkers.count_ones() {
+            0 => add_castles(board, bitboards, king, moves),
+            1 => {
+                let checker = checkers.trailing_zeros() as usize;
+                targets &= checkers | tables.between(king, checker);
+            }
+            // Only the king can get out of a double check
+            _ => return,
+        }
+
+        let [_, _, bishops, rooks, queens, _] = bitboards.pieces[1 - us];
+        let snipers = (tables.rook(king, enemy) & (rooks | queens))
+            | (tables.bishop(king, enemy) & (bishops | queens));
+        for sniper in squares(snipers) {
+            let blockers = tables.between(king, sniper) & occupied;
+            if blockers.count_ones() == 1 {
+                pinned |= blockers & own;
+            }
+        }
+    }
+    // A pinned piece may only move along the line through its king
+    let allowed = |src: usize| match king {
+        Some(king) if pinned & bit(src) != 0 => targets & tables.line(king, src),
+        _ => targets,
+    };
+    let mut add = |src: usize, dsts: Bitboard| {
+        for dst in squares(dsts) {
+            moves.push(Move::new(position(src), position(dst)));
+        }
+    };
+
+    let [pawns, knights, bishops, rooks, queens, _] = bitboards.pieces[us];
+    // A pinned knight can never stay on the line it is pinned along
+    for src in squares(knights & !pinned) {
+        add(src, tables.knight[src] & targets);
+    }
+    for src in squares(bishops | queens) {
+        add(src, tables.bishop(src, occupied) & allowed(src));
+    }
+    for src in squares(rooks | queens) {
+        add(src, tables.rook(src, occupied) & allowed(src));
+    }
+    for src in squares(pawns) {
+        add_pawn_moves(board, bitboards, src, allowed(src), king, moves);
+    }
+}
+
+/// Returns true if any piece of color `by` attacks `pos`
+pub fn is_attacked(bitboards: &Bitboards, pos: RawSquarePosition, by: ColorKind) -> bool {
+    bitboards.attackers(pos.index() as usize, bitboards.occupied())
+        & bitboards.colors[by.id() as usize]
+        != 0
+}
+
+/// Returns true if neither color can possibly checkmate the other: bare kings, a king and a single
+/// minor piece against a bare king, or kings and any number of bishops that all stand on squares of
+/// the same color
+pub fn insufficient_material(bitboards: &Bitboards) -> bool {
+    let [white, black] = bitboards.pieces;
+    let both = |index: usize| white[index] | black[index];
+    if both(PAWN) | both(ROOK) | both(QUEEN) != 0 {
+        return false;
+    }
+    let knights = both(KNIGHT);
+    let bishops = both(BISHOP);
+    let same_colored_bishops =
+        knights == 0 && (bishops & DARK_SQUARES == 0 || bishops & !DARK_SQUARES == 0);
+    (knights | bishops).count_ones() <= 1 || same_colored_bishops
+}
+
+/// Adds the pushes and captures of the pawn on `src` that land on `allowed`, and its en passant
+/// capture if that doesn't leave `king` in check
+fn add_pawn_moves(
+    board: &Board,
+    bitboards: &Bitboards,
+    src: usize,
+    allowed: Bitboard,
+    king: Option<usize>,
+    moves: &mut MoveList,
+) {
+    let tables = tables();
+    let us = board.to_move().id() as usize;
+    let occupied = bitboards.occupied();
+    let (forward, start_rank, last_rank) = if us == 0 { (1, 1, 7) } else { (-1, 6, 0) };
+    let mut push = |dst: usize, kind: MoveKind| {
+        if dst / 8 == last_rank {
+            for piece in chess::PROMOTION_PIECES.iter() {
+                moves.push(Move {
+                    src: position(src),
+                    dst: position(dst),
+                    kind: MoveKind::Promotion(*piece),
+                });
+            }
+        } else {
+            moves.push(Move {
+                src: position(src),
+                dst: position(dst),
+                kind,
+            });
+        }
+    };
+
+    if let Some(single) = offset(src, 0, forward).filter(|dst| occupied & bit(*dst) == 0) {
+        if allowed & bit(single) != 0 {
+            push(single, MoveKind::Normal);
+        }
+        if src / 8 == start_rank {
+            if let Some(double) = offset(single, 0, forward) {
+                if (occupied | !allowed) & bit(double) == 0 {
+                    push(double, MoveKind::Normal);
+                }
+            }
+        }
+    }
+
+    let attacks = tables.pawn_attacks[us][src];
+    for dst in squares(attacks & bitboards.colors[1 - us] & allowed) {
+        push(dst, MoveKind::Normal);
+    }
+
+    let en_passant = match board.en_passant() {
+        Some(en_passant) => en_passant.index() as usize,
+        None => return,
+    };
+    if attacks & !occupied & bit(en_passant) == 0 {
+        return;
+    }
+    // The captured pawn and the capturing pawn both leave their rank at once, which can uncover an
+    // attack on the king no pin mask describes, so look for attacks directly
+    let captured = (src / 8) * 8 + en_passant % 8;
+    if let Some(king) = king {
+        let after = (occupied & !bit(src) & !bit(captured)) | bit(en_passant);
+        let attackers =
+            bitboards.attackers(king, after) & bitboards.colors[1 - us] & !bit(captured);
+        if attackers != 0 {
+            return;
+        }
+    }
+    push(en_passant, MoveKind::EnPassant);
+}
+
+/// Adds castling moves for the king on `king`, which isn't in check. Both standard and Chess960
+/// castling are handled: the king and rook always end on the same squares they would in standard
+/// chess, every square between where they start and end must be empty (other than the castling
+/// king and rook), and the king may not castle out of, through, or into check
+fn add_castles(board: &Board, bitboards: &Bitboards, king: usize, moves: &mut MoveList) {
+    let color = board.to_move();
+    let us = color.id() as usize;
+    let rank = if us == 0 { 0 } else { 7 };
+    if king / 8 != rank {
+        return;
+    }
+    let occupied = bitboards.occupied();
+    let enemy = bitboards.colors[1 - us];
+    let sides = [
+        (
+            CastleSide::King,
+            chess::KINGSIDE_KING_DST_FILE,
+            chess::KINGSIDE_ROOK_DST_FILE,
+        ),
+        (
+            CastleSide::Queen,
+            chess::QUEENSIDE_KING_DST_FILE,
+            chess::QUEENSIDE_ROOK_DST_FILE,
+        ),
+    ];
+    for (side, king_dst_file, rook_dst_file) in sides.iter() {
+        let rook = match board.castling().get(color, *side) {
+            Some(file) => rank * 8 + file as usize,
+            None => continue,
+        };
+        if bitboards.pieces[us][ROOK] & bit(rook) == 0 {
+            continue;
+        }
+        let king_dst = rank * 8 + *king_dst_file as usize;
+        let rook_dst = rank * 8 + *rook_dst_file as usize;
+        let span = |a: usize, b: usize| {
+            let (low, high) = (a.min(b), a.max(b));
+            (low..=high).fold(0, |bitboard, square| bitboard | bit(square))
+        };
+        let must_be_empty = (span(king, rook) | span(king, king_dst) | span(rook, rook_dst))
+            & !bit(king)
+            & !bit(rook);
+        if occupied & must_be_empty != 0 {
+            continue;
+        }
+        if squares(span(king, king_dst))
+            .any(|square| bitboards.attackers(square, occupied) & enemy != 0)
+        {
+            continue;
+        }
+        // In Chess960 the castling rook may have been shielding the king's destination
+        let after = (occupied & !bit(king) & !bit(rook)) | bit(king_dst) | bit(rook_dst);
+        if bitboards.attackers(king_dst, after) & enemy != 0 {
+            continue;
+        }
+        moves.push(Move {
+            src: position(king),
+            dst: position(king_dst),
+            kind: MoveKind::Castle {
+                rook_src: position(rook),
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Kind;
+
+    #[test]
+    fn magics_have_no_collisions() {
+        let tables = tables();
+        for square in 0..64 {
+            for (directions, magic, table) in [
+                (
+                    &ROOK_DIRECTIONS,
+                    &tables.rook_magics[square],
+                    &tables.rook_attacks,
+                ),
+                (
+                    &BISHOP_DIRECTIONS,
+                    &tables.bishop_magics[square],
+                    &tables.bishop_attacks,
+                ),
+            ]
+            .iter()
+            {
+                let mut blockers: Bitboard = 0;
+                loop {
+                    assert_eq!(
+                        table[magic.index(blockers)],
+                        slide(square, blockers, *directions)
+                    );
+                    blockers = blockers.wrapping_sub(magic.mask) & magic.mask;
+                    if blockers == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn en_passant_discovered_check() {
+        // Capturing en passant would take both pawns off the fifth rank, exposing the king to the
+        // rook
+        let board = Board::from_fen(Kind::Chess, "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1").unwrap();
+        let moves = board.legal_moves();
+        assert!(moves.iter().all(|m| m.kind != MoveKind::EnPassant));
+        // Only the king and the pawn's push
+        assert_eq!(moves.len(), 6);
+    }
+}
@@ -0,0 +1,453 @@
+//! Movement rules for standard chess and Chess960, one square at a time. Chess boards are stored
+//! as bitboards with their own move generator, see `bitboard`, so these rules are only used by
+//! `Board::to_mailbox` copies, and for the piece movements other game kinds borrow from chess
+
+use super::{Board, CastleSide, Move, MoveKind, MoveList, Piece, PieceKind};
+use crate::game::{ColorKind, RawSquarePosition};
+
+/// The file the king ends on after castling kingside
+pub const KINGSIDE_KING_DST_FILE: u32 = 6;
+/// The file the rook ends on after castling kingside
+pub const KINGSIDE_ROOK_DST_FILE: u32 = 5;
+/// The file the king ends on after castling queenside
+pub const QUEENSIDE_KING_DST_FILE: u32 = 2;
+/// The file the rook ends on after castling queenside
+pub const QUEENSIDE_ROOK_DST_FILE: u32 = 3;
+
+pub(super) const KNIGHT_OFFSETS: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+
+pub(super) const KING_OFFSETS: [(i32, i32); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+pub(super) const ROOK_DIRECTIONS: [(i32, i32); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+pub(super) const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+/// The pieces a pawn may promote to, in the order they are generated
+pub(super) const PROMOTION_PIECES: [PieceKind; 4] = [
+    PieceKind::Queen,
+    PieceKind::Rook,
+    PieceKind::Bishop,
+    PieceKind::Knight,
+];
+
+/// Adds the moves for every piece of the color to move to `moves`, ignoring checks
+pub fn pseudo_legal_moves(board: &Board, moves: &mut MoveList) {
+    let color = board.to_move();
+    let range = board.side_len();
+    for (pos, piece) in board.pieces_for_color(color) {
+        match piece.kind {
+            PieceKind::King => {
+                add_steps(board, pos, color, &KING_OFFSETS, moves);
+                add_castles(board, pos, color, moves);
+            }
+            PieceKind::Queen => {
+                add_slides(board, pos, color, &ROOK_DIRECTIONS, range, moves);
+                add_slides(board, pos, color, &BISHOP_DIRECTIONS, range, moves);
+            }
+            PieceKind::Rook => add_slides(board, pos, color, &ROOK_DIRECTIONS, range, moves),
+            PieceKind::Bishop => add_slides(board, pos, color, &BISHOP_DIRECTIONS, range, moves),
+            PieceKind::Knight => add_steps(board, pos, color, &KNIGHT_OFFSETS, moves),
+            PieceKind::Pawn => add_pawn_moves(board, pos, color, moves),
+            // Pieces from other game kinds can only be placed with `Board::set`
+            _ => {}
+        }
+    }
+}
+
+/// Returns true if any piece of color `by` attacks `pos`
+pub fn is_attacked(board: &Board, pos: RawSquarePosition, by: ColorKind) -> bool {
+    let is = |square: Option<RawSquarePosition>, kinds: &[PieceKind]| match square
+        .and_then(|square| board.get(square))
+    {
+        Some(piece) => piece.color == by && kinds.contains(&piece.kind),
+        None => false,
+    };
+
+    // Pawns attack diagonally forward, so look diagonally backwards from the attacked square
+    let pawn_rank = -board.forward(by);
+    if is(board.offset(pos, 1, pawn_rank), &[PieceKind::Pawn])
+        || is(board.offset(pos, -1, pawn_rank), &[PieceKind::Pawn])
+    {
+        return true;
+    }
+
+    let offsets_attack = |offsets: &[(i32, i32)], kind: PieceKind| {
+        offsets
+            .iter()
+            .any(|(file, rank)| is(board.offset(pos, *file, *rank), &[kind]))
+    };
+    if offsets_attack(&KNIGHT_OFFSETS, PieceKind::Knight)
+        || offsets_attack(&KING_OFFSETS, PieceKind::King)
+    {
+        return true;
+    }
+
+    let slides_attack = |directions: &[(i32, i32)], kinds: &[PieceKind]| {
+        directions.iter().any(|(file, rank)| {
+            let mut square = board.offset(pos, *file, *rank);
+            while let Some(current) = square {
+                if board.get(current).is_some() {
+                    return is(square, kinds);
+                }
+                square = board.offset(current, *file, *rank);
+            }
+            false
+        })
+    };
+    slides_attack(&ROOK_DIRECTIONS, &[PieceKind::Rook, PieceKind::Queen])
+        || slides_attack(&BISHOP_DIRECTIONS, &[PieceKind::Bishop, PieceKind::Queen])
+}
+
+/// Adds a move to each offset that is on the board and not occupied by a friendly piece
+pub(super) fn add_steps(
+    board: &Board,
+    pos: RawSquarePosition,
+    color: ColorKind,
+    offsets: &[(i32, i32)],
+    moves: &mut MoveList,
+) {
+    for (file, rank) in offsets {
+        if let Some(dst) = board.offset(pos, *file, *rank) {
+            match board.get(dst) {
+                Some(piece) if piece.color == color => {}
+                _ => moves.push(Move::new(pos, dst)),
+            }
+        }
+    }
+}
+
+/// Adds moves along each direction, at most `range` squares, until the edge of the board or a
+/// piece is hit. Enemy pieces can be captured
+pub(super) fn add_slides(
+    board: &Board,
+    pos: RawSquarePosition,
+    color: ColorKind,
+    directions: &[(i32, i32)],
+    range: u32,
+    moves: &mut MoveList,
+) {
+    for (file, rank) in directions {
+        let mut square = board.offset(pos, *file, *rank);
+        let mut distance = 1;
+        while let (Some(dst), true) = (square, distance <= range) {
+            match board.get(dst) {
+                Some(piece) => {
+                    if piece.color != color {
+                        moves.push(Move::new(pos, dst));
+                    }
+                    break;
+                }
+                None => moves.push(Move::new(pos, dst)),
+            }
+            square = board.offset(dst, *file, *rank);
+            distance += 1;
+        }
+    }
+}
+
+fn add_pawn_moves(board: &Board, pos: RawSquarePosition, color: ColorKind, moves: &mut MoveList) {
+    let forward = board.forward(color);
+    let (_, rank) = board.file_rank(pos);
+    let start_rank = (board.back_rank(color) as i32 + forward) as u32;
+    let last_rank = board.back_rank(board.next_color(color));
+
+    let mut push = |dst: RawSquarePosition, kind: MoveKind| {
+        let (_, dst_rank) = board.file_rank(dst);
+        if dst_rank == last_rank {
+            for piece in PROMOTION_PIECES.iter() {
+                moves.push(Move {
+                    src: pos,
+                    dst,
+                    kind: MoveKind::Promotion(*piece),
+                });
+            }
+        } else {
+            moves.push(Move {
+                src: pos,
+                dst,
+                kind,
+            });
+        }
+    };
+
+    if let Some(single) = board.offset(pos, 0, forward) {
+        if board.get(single).is_none() {
+            push(single, MoveKind::Normal);
+            if rank == start_rank {
+                if let Some(double) = board.offset(single, 0, forward) {
+                    if board.get(double).is_none() {
+                        push(double, MoveKind::Normal);
+                    }
+                }
+            }
+        }
+    }
+
+    for file in [-1, 1].iter() {
+        if let Some(dst) = board.offset(pos, *file, forward) {
+            match board.get(dst) {
+                Some(piece) if piece.color != color => push(dst, MoveKind::Normal),
+                Some(_) => {}
+                None if board.en_passant() == Some(dst) => push(dst, MoveKind::EnPassant),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Adds castling moves for the king on `king`. Both standard and Chess960 castling are handled:
+/// the king and rook always end on the same squares they would in standard chess, every square
+/// between where they start and end must be empty (other than the castling king and rook), and
+/// the king may not castle out of, through, or into check
+fn add_castles(board: &Board, king: RawSquarePosition, color: ColorKind, moves: &mut MoveList) {
+    let (king_file, rank) = board.file_rank(king);
+    if rank != board.back_rank(color) {
+        return;
+    }
+    let enemy = board.next_color(color);
+    let sides = [
+        (
+            CastleSide::King,
+            KINGSIDE_KING_DST_FILE,
+            KINGSIDE_ROOK_DST_FILE,
+        ),
+        (
+            CastleSide::Queen,
+            QUEENSIDE_KING_DST_FILE,
+            QUEENSIDE_ROOK_DST_FILE,
+        ),
+    ];
+    for (side, king_dst_file, rook_dst_file) in sides.iter() {
+        let rook_file = match board.castling().get(color, *side) {
+            Some(file) => file,
+            None => continue,
+        };
+        let rook = board.square(rook_file, rank).unwrap();
+        if board.get(rook) != Some(Piece::new(PieceKind::Rook, color)) {
+            continue;
+        }
+
+        let min = king_file
+            .min(rook_file)
+            .min(*king_dst_file)
+            .min(*rook_dst_file);
+        let max = king_file
+            .max(rook_file)
+            .max(*king_dst_file)
+            .max(*rook_dst_file);
+        let blocked = (min..=max).any(|file| {
+            let pos = board.square(file, rank).unwrap();
+            pos != king && pos != rook && board.get(pos).is_some()
+        });
+        if blocked {
+            continue;
+        }
+
+        let path_attacked = (king_file.min(*king_dst_file)..=king_file.max(*king_dst_file))
+            .any(|file| board.is_attacked(board.square(file, rank).unwrap(), enemy));
+        if path_attacked {
+            continue;
+        }
+
+        moves.push(Move {
+            src: king,
+            dst: board.square(*king_dst_file, rank).unwrap(),
+            kind: MoveKind::Castle { rook_src: rook },
+        });
+    }
+}
+
+/// Returns true if neither color can possibly checkmate the other: bare kings, a king and a single
+/// minor piece against a bare king, or kings and any number of bishops that all stand on squares of
+/// the same color
+pub fn insufficient_material(board: &Board) -> bool {
+    let mut minors = 0;
+    let mut knights = 0;
+    let mut bishop_square_colors = [false; 2];
+    for (pos, piece) in board.pieces() {
+        match piece.kind {
+            PieceKind::King => {}
+            PieceKind::Knight => {
+                minors += 1;
+                knights += 1;
+            }
+            PieceKind::Bishop => {
+                minors += 1;
+                let (file, rank) = board.file_rank(pos);
+                bishop_square_colors[((file + rank) % 2) as usize] = true;
+            }
+            _ => return false,
+        }
+    }
+    let same_colored_bishops =
+        knights == 0 && !(bishop_square_colors[0] && bishop_square_colors[1]);
+    minors <= 1 || same_colored_bishops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::CastlingRights;
+    use crate::game::{GameEndCause, Kind, RawMove};
+
+    fn square(board: &Board, name: &str) -> RawSquarePosition {
+        let bytes = name.as_bytes();
+        board
+            .square((bytes[0] - b'a') as u32, (bytes[1] - b'1') as u32)
+            .unwrap()
+    }
+
+    fn play(board: &mut Board, src: &str, dst: &str) {
+        let raw = RawMove::new(square(board, src), square(board, dst));
+        let m = board
+            .move_from_raw(&raw)
+            .unwrap_or_else(|| panic!("{}{} is illegal", src, dst));
+        board.apply_move(m);
+    }
+
+    #[test]
+    fn start_position_has_twenty_moves() {
+        let board = Board::start_position(Kind::Chess);
+        assert_eq!(board.legal_moves().len(), 20);
+    }
+
+    #[test]
+    fn fools_mate() {
+        let mut board = Board::start_position(Kind::Chess);
+        play(&mut board, "f2", "f3");
+        play(&mut board, "e7", "e5");
+        play(&mut board, "g2", "g4");
+        assert_eq!(board.game_end(), None);
+        play(&mut board, "d8", "h4");
+        assert!(board.is_in_check());
+        assert_eq!(board.game_end(), Some(GameEndCause::Checkmate));
+    }
+
+    #[test]
+    fn castling_and_en_passant() {
+        let mut board = Board::start_position(Kind::Chess);
+        for (src, dst) in [
+            ("e2", "e4"),
+            ("a7", "a6"),
+            ("e4", "e5"),
+            ("d7", "d5"),
+            ("e5", "d6"),
+            ("a6", "a5"),
+            ("g1", "f3"),
+            ("a5", "a4"),
+            ("f1", "e2"),
+            ("a4", "a3"),
+            ("e1", "g1"),
+        ]
+        .iter()
+        {
+            play(&mut board, src, dst);
+        }
+        assert_eq!(
+            board.to_board_string(),
+            "rnbqkbnr/.pp.pppp/...P..../......../......../p....N../PPPPBPPP/RNBQ.RK."
+        );
+        assert_eq!(
+            board.castling().get(ColorKind::WHITE, CastleSide::King),
+            None
+        );
+        assert_eq!(
+            board.castling().get(ColorKind::BLACK, CastleSide::King),
+            Some(7)
+        );
+    }
+
+    #[test]
+    fn pinned_pieces_cannot_move() {
+        let mut board = Board::from_board_string(
+            Kind::Chess,
+            "....k.../....r.../......../......../......../......../....N.../....K...",
+        )
+        .unwrap();
+        board.set_castling(CastlingRights::none());
+        let knight = square(&board, "e2");
+        assert!(board.legal_moves().iter().all(|m| m.src != knight));
+    }
+
+    #[test]
+    fn promotions() {
+        let board = Board::from_board_string(
+            Kind::Chess,
+            "......k./P......./......../......../......../......../......../....K...",
+        )
+        .unwrap();
+        let a7 = square(&board, "a7");
+        let promotions: Vec<_> = board
+            .legal_moves()
+            .into_iter()
+            .filter(|m| m.src == a7)
+            .filter_map(|m| m.promotion())
+            .collect();
+        assert_eq!(promotions, PROMOTION_PIECES.to_vec());
+
+        let a8 = square(&board, "a8");
+        assert_eq!(board.move_from_raw(&RawMove::new(a7, a8)), None);
+        assert_eq!(
+            board.move_from_raw(&RawMove::with_promotion(a7, a8, PieceKind::King)),
+            None
+        );
+        let underpromotion = board
+            .move_from_raw(&RawMove::with_promotion(a7, a8, PieceKind::Knight))
+            .unwrap();
+        assert_eq!(underpromotion.promotion(), Some(PieceKind::Knight));
+        assert_eq!(
+            board.to_raw_move(underpromotion),
+            RawMove::with_promotion(a7, a8, PieceKind::Knight)
+        );
+
+        let e1 = square(&board, "e1");
+        let e2 = square(&board, "e2");
+        assert_eq!(
+            board.move_from_raw(&RawMove::with_promotion(e1, e2, PieceKind::Queen)),
+            None
+        );
+    }
+
+    #[test]
+    fn insufficient_material() {
+        let dead =
+            |fen: &str| super::insufficient_material(&Board::from_fen(Kind::Chess, fen).unwrap());
+        assert!(dead("8/8/4k3/8/8/3K4/8/8 w - - 0 1"));
+        assert!(dead("8/8/4k3/8/8/3KN3/8/8 w - - 0 1"));
+        assert!(dead("8/4b3/4k3/8/8/3KB3/8/8 w - - 0 1"));
+        // Bishops on opposite colored squares can mate in theory
+        assert!(!dead("8/5b2/4k3/8/8/3KB3/8/8 w - - 0 1"));
+        assert!(!dead("8/8/4k3/8/8/3KNN2/8/8 w - - 0 1"));
+        assert!(!dead("8/8/4k3/8/8/3K4/4P3/8 w - - 0 1"));
+        assert_eq!(Board::start_position(Kind::Chess).game_end(), None);
+        let board = Board::from_fen(Kind::Chess, "8/8/4k3/8/8/3KB3/8/8 w - - 0 1").unwrap();
+        assert_eq!(board.game_end(), Some(GameEndCause::DeadPosition));
+    }
+
+    #[test]
+    fn seventy_five_move_rule() {
+        let board = Board::from_fen(Kind::Chess, "8/8/4k3/8/8/3K4/3R4/8 w - - 150 100").unwrap();
+        assert_eq!(board.game_end(), Some(GameEndCause::SeventyFiveMoveRule));
+        // Checkmate takes precedence
+        let board = Board::from_fen(Kind::Chess, "3k3R/8/3K4/8/8/8/8/8 b - - 150 100").unwrap();
+        assert_eq!(board.game_end(), Some(GameEndCause::Checkmate));
+    }
+}
@@ -0,0 +1,234 @@
+//! Chess960 start positions, numbered 0 to 959 with Scharnagl's scheme. The pawns are where they
+//! are in standard chess, and the pieces on the first rank are shuffled so that the bishops are on
+//! squares of opposite colors and the king is between the rooks. Black mirrors white's
+//! arrangement. Castling follows the regular rules, see `bitboard::add_castles`
+
+use super::{Board, CastlingRights, Piece, PieceKind};
+use crate::game::{ColorKind, Kind};
+
+/// The number of Chess960 start positions
+pub const CHESS960_POSITIONS: u32 = 960;
+
+/// The index of the standard chess start position
+pub const STANDARD_CHESS960_INDEX: u32 = 518;
+
+/// Where the two knights go among the five files left after placing the bishops and queen
+const KNIGHT_FILES: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+/// Returns the first rank pieces of Chess960 position `index`, from the a-file to the h-file, or
+/// None if the index is 960 or higher
+pub fn chess960_back_rank(index: u32) -> Option<[PieceKind; 8]> {
+    if index >= CHESS960_POSITIONS {
+        return None;
+    }
+    let mut files = [None; 8];
+    let mut n = index as usize;
+
+    // One bishop on a light square (b, d, f or h) and one on a dark square (a, c, e or g)
+    files[n % 4 * 2 + 1] = Some(PieceKind::Bishop);
+    n /= 4;
+    files[n % 4 * 2] = Some(PieceKind::Bishop);
+    n /= 4;
+
+    // The queen, then the knights, then the rook, king and rook on the files that are left
+    let mut place = |nth: usize, piece: PieceKind| {
+        let file = (0..8)
+            .filter(|file| files[*file].is_none())
+            .nth(nth)
+            .unwrap();
+        files[file] = Some(piece);
+    };
+    place(n % 6, PieceKind::Queen);
+    n /= 6;
+    let (first, second) = KNIGHT_FILES[n];
+    // Placing the first knight shifts the files after it down by one
+    place(second, PieceKind::Knight);
+    place(first, PieceKind::Knight);
+    for piece in [PieceKind::Rook, PieceKind::King, PieceKind::Rook].iter() {
+        place(0, *piece);
+    }
+
+    let mut back_rank = [PieceKind::Pawn; 8];
+    for (file, piece) in files.iter().enumerate() {
+        back_rank[file] = piece.unwrap();
+    }
+    Some(back_rank)
+}
+
+/// Picks a Chess960 position index from a seed. The same seed always picks the same position
+pub fn chess960_index_from_seed(seed: u64) -> u32 {
+    // SplitMix64, so that nearby seeds such as consecutive timestamps pick unrelated positions
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    ((z ^ (z >> 31)) % CHESS960_POSITIONS as u64) as u32
+}
+
+impl Board {
+    /// Creates the Chess960 start position with `index`, with both colors allowed to castle with
+    /// either rook. Returns None if the index is 960 or higher. Index 518 is the standard start
+    /// position
+    pub fn chess960(index: u32) -> Option<Board> {
+        let back_rank = chess960_back_rank(index)?;
+        let mut board = Board::empty(Kind::Chess);
+        for (file, piece) in back_rank.iter().enumerate() {
+            let file = file as u32;
+            for (color, rank, pawn_rank) in
+                [(ColorKind::WHITE, 0, 1), (ColorKind::BLACK, 7, 6)].iter()
+            {
+                board.set(
+                    board.square(file, *rank).unwrap(),
+                    Some(Piece::new(*piece, *color)),
+                );
+                board.set(
+                    board.square(file, *pawn_rank).unwrap(),
+                    Some(Piece::new(PieceKind::Pawn, *color)),
+                );
+            }
+        }
+        board.set_castling(CastlingRights::from_board(&board));
+        Some(board)
+    }
+
+    /// Creates the Chess960 start position picked by `seed`, see `chess960_index_from_seed`
+    pub fn chess960_from_seed(seed: u64) -> Board {
+        Board::chess960(chess960_index_from_seed(seed)).unwrap()
+    }
+
+    /// Returns the index of the Chess960 start position this board is, or None if it isn't one.
+    /// The move counters aren't compared
+    pub fn chess960_index(&self) -> Option<u32> {
+        if self.kind() != Kind::Chess {
+            return None;
+        }
+        let first_rank: Vec<_> = (0..8)
+            .map(|file| self.get(self.square(file, 0).unwrap()))
+            .collect();
+        let index = (0..CHESS960_POSITIONS).find(|index| {
+            let back_rank = chess960_back_rank(*index).unwrap();
+            back_rank
+                .iter()
+                .zip(first_rank.iter())
+                .all(|(piece, square)| *square == Some(Piece::new(*piece, ColorKind::WHITE)))
+        })?;
+        let start = Board::chess960(index).unwrap();
+        let same = self.squares().all(|pos| self.get(pos) == start.get(pos))
+            && self.castling() == start.castling()
+            && self.to_move() == start.to_move()
+            && self.en_passant().is_none();
+        if same {
+            Some(index)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn back_rank_string(index: u32) -> String {
+        let board = Board::chess960(index).unwrap();
+        (0..8)
+            .map(|file| {
+                board
+                    .get(board.square(file, 0).unwrap())
+                    .unwrap()
+                    .to_char(Kind::Chess)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn known_positions() {
+        assert_eq!(back_rank_string(0), "BBQNNRKR");
+        assert_eq!(back_rank_string(STANDARD_CHESS960_INDEX), "RNBQKBNR");
+        assert_eq!(back_rank_string(959), "RKRNNQBB");
+        assert_eq!(
+            Board::chess960(STANDARD_CHESS960_INDEX).unwrap(),
+            Board::start_position(Kind::Chess)
+        );
+        assert_eq!(Board::chess960(CHESS960_POSITIONS), None);
+    }
+
+    #[test]
+    fn every_position_is_valid_and_distinct() {
+        let mut seen = HashSet::new();
+        for index in 0..CHESS960_POSITIONS {
+            let rank = back_rank_string(index);
+            assert!(seen.insert(rank.clone()), "{} repeats {}", index, rank);
+
+            let bishops: Vec<_> = rank.match_indices('B').map(|(file, _)| file).collect();
+            assert_ne!(bishops[0] % 2, bishops[1] % 2, "{}", rank);
+            let king = rank.find('K').unwrap();
+            assert!(rank.find('R').unwrap() < king && king < rank.rfind('R').unwrap());
+
+            let board = Board::chess960(index).unwrap();
+            assert_eq!(board.chess960_index(), Some(index));
+            assert_eq!(
+                Board::from_fen(Kind::Chess, &board.to_fen()).unwrap(),
+                board
+            );
+        }
+    }
+
+    #[test]
+    fn seeds() {
+        assert_eq!(chess960_index_from_seed(7), chess960_index_from_seed(7));
+        let indices: HashSet<_> = (0..100).map(chess960_index_from_seed).collect();
+        assert!(indices.len() > 50);
+        assert_eq!(
+            Board::chess960_from_seed(7).chess960_index(),
+            Some(chess960_index_from_seed(7))
+        );
+    }
+
+    #[test]
+    fn castling_to_standard_squares() {
+        // Position 0 is BBQNNRKR. With the queen and knights gone, the king castles queenside from
+        // g1 to c1 and the rook jumps over it from f1 to d1
+        let mut board = Board::from_fen(
+            Kind::Chess,
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BB3RKR w KQkq - 0 1",
+        )
+        .unwrap();
+        board.apply_move(board.parse_uci("g1f1").unwrap());
+        assert_eq!(
+            board.to_fen(),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBKR3R b kq - 1 1"
+        );
+
+        // Castling kingside leaves the king where it is and only moves the rook
+        let mut board = Board::from_fen(
+            Kind::Chess,
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNN1KR w Kkq - 0 1",
+        )
+        .unwrap();
+        board.apply_move(board.parse_uci("g1h1").unwrap());
+        assert_eq!(
+            board.to_fen(),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRK1 b kq - 1 1"
+        );
+    }
+
+    #[test]
+    fn perft() {
+        // The knights on d1 and e1 have two moves each, and nothing can interact until move two
+        let board = Board::chess960(0).unwrap();
+        assert_eq!(board.perft(1), 20);
+        assert_eq!(board.perft(2), 400);
+    }
+}
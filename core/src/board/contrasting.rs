@@ -0,0 +1,350 @@
+//! Movement rules for Contrasting Chess, played on a 10x10 board. Every piece captures by moving
+//! onto an enemy piece the way it moves, except for the weasel:
+//!
+//! - The king steps one square in any direction
+//! - The elephant slides like a queen. It can't capture weasels, which block it instead
+//! - The bear slides diagonally, up to five squares
+//! - The horse leaps like a knight, jumping over anything in between
+//! - The dragon steps one square in any direction, or leaps exactly two squares in a straight or
+//!   diagonal line, jumping over the square in between
+//! - The moose slides orthogonally, up to three squares
+//! - The weasel steps one square straight forward onto an empty square, and captures one square
+//!   diagonally forward. It has no double step and there is no en passant. A weasel reaching the
+//!   last rank must promote to an elephant, bear, horse, dragon or moose
+//!
+//! Slides stop at the first piece in their way, while leaps and steps are only stopped by a
+//! friendly piece on the destination. There is no castling
+
+use super::chess::{
+    add_slides, add_steps, BISHOP_DIRECTIONS, KING_OFFSETS, KNIGHT_OFFSETS, ROOK_DIRECTIONS,
+};
+use super::{Board, Move, MoveKind, MoveList, PieceKind};
+use crate::game::{ColorKind, RawSquarePosition};
+
+/// The furthest a bear may slide in one move
+const BEAR_RANGE: u32 = 5;
+
+/// The furthest a moose may slide in one move
+const MOOSE_RANGE: u32 = 3;
+
+/// The squares a dragon can leap to, besides the ones next to it
+const DRAGON_LEAPS: [(i32, i32); 8] = [
+    (2, 0),
+    (2, 2),
+    (0, 2),
+    (-2, 2),
+    (-2, 0),
+    (-2, -2),
+    (0, -2),
+    (2, -2),
+];
+
+/// The pieces a weasel may promote to, in the order they are generated
+const PROMOTION_PIECES: [PieceKind; 5] = [
+    PieceKind::Elephant,
+    PieceKind::Bear,
+    PieceKind::Horse,
+    PieceKind::Dragon,
+    PieceKind::Moose,
+];
+
+/// Adds the moves for every piece of the color to move to `moves`, ignoring checks
+pub fn pseudo_legal_moves(board: &Board, moves: &mut MoveList) {
+    let color = board.to_move();
+    for (pos, piece) in board.pieces_for_color(color) {
+        match piece.kind {
+            PieceKind::King => add_steps(board, pos, color, &KING_OFFSETS, moves),
+            PieceKind::Elephant => add_elephant_moves(board, pos, color, moves),
+            PieceKind::Bear => add_slides(board, pos, color, &BISHOP_DIRECTIONS, BEAR_RANGE, moves),
+            PieceKind::Horse => add_steps(board, pos, color, &KNIGHT_OFFSETS, moves),
+            PieceKind::Dragon => {
+                add_steps(board, pos, color, &KING_OFFSETS, moves);
+                add_steps(board, pos, color, &DRAGON_LEAPS, moves);
+            }
+            PieceKind::Moose => add_slides(board, pos, color, &ROOK_DIRECTIONS, MOOSE_RANGE, moves),
+            PieceKind::Weasel => add_weasel_moves(board, pos, color, moves),
+            // Pieces from other game kinds can only be placed with `Board::set`
+            _ => {}
+        }
+    }
+}
+
+/// Returns true if any piece of color `by` attacks `pos`
+pub fn is_attacked(board: &Board, pos: RawSquarePosition, by: ColorKind) -> bool {
+    let is_weasel = board
+        .get(pos)
+        .is_some_and(|piece| piece.kind == PieceKind::Weasel);
+    board.pieces_for_color(by).any(|(src, piece)| {
+        let steps_to = |offsets: &[(i32, i32)]| {
+            offsets
+                .iter()
+                .any(|(file, rank)| board.offset(src, *file, *rank) == Some(pos))
+        };
+        let slides_to = |directions: &[(i32, i32)], range: u32| {
+            directions
+                .iter()
+                .any(|(file, rank)| slide_reaches(board, src, pos, *file, *rank, range))
+        };
+        match piece.kind {
+            PieceKind::King => steps_to(&KING_OFFSETS),
+            PieceKind::Elephant => {
+                let range = board.side_len();
+                !is_weasel
+                    && (slides_to(&ROOK_DIRECTIONS, range) || slides_to(&BISHOP_DIRECTIONS, range))
+            }
+            PieceKind::Bear => slides_to(&BISHOP_DIRECTIONS, BEAR_RANGE),
+            PieceKind::Horse => steps_to(&KNIGHT_OFFSETS),
+            PieceKind::Dragon => steps_to(&KING_OFFSETS) || steps_to(&DRAGON_LEAPS),
+            PieceKind::Moose => slides_to(&ROOK_DIRECTIONS, MOOSE_RANGE),
+            PieceKind::Weasel => {
+                let forward = board.forward(by);
+                steps_to(&[(1, forward), (-1, forward)])
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Returns true if only kings are left. Every other piece can still help to checkmate
+pub fn insufficient_material(board: &Board) -> bool {
+    board
+        .pieces()
+        .all(|(_, piece)| piece.kind == PieceKind::King)
+}
+
+/// Adds the slides of an elephant, which weasels block instead of being captured
+fn add_elephant_moves(
+    board: &Board,
+    pos: RawSquarePosition,
+    color: ColorKind,
+    moves: &mut MoveList,
+) {
+    let range = board.side_len();
+    add_slides(board, pos, color, &ROOK_DIRECTIONS, range, moves);
+    add_slides(board, pos, color, &BISHOP_DIRECTIONS, range, moves);
+    moves.retain(|m| {
+        m.src != pos
+            || !board
+                .get(m.dst)
+                .is_some_and(|piece| piece.kind == PieceKind::Weasel)
+    });
+}
+
+/// Returns true if a slide from `src` in one direction reaches `dst` within `range` squares,
+/// without passing over another piece
+fn slide_reaches(
+    board: &Board,
+    src: RawSquarePosition,
+    dst: RawSquarePosition,
+    file: i32,
+    rank: i32,
+    range: u32,
+) -> bool {
+    let mut square = board.offset(src, file, rank);
+    let mut distance = 1;
+    while let (Some(current), true) = (square, distance <= range) {
+        if current == dst {
+            return true;
+        }
+        if board.get(current).is_some() {
+            return false;
+        }
+        square = board.offset(current, file, rank);
+        distance += 1;
+    }
+    false
+}
+
+fn add_weasel_moves(board: &Board, pos: RawSquarePosition, color: ColorKind, moves: &mut MoveList) {
+    let forward = board.forward(color);
+    let last_rank = board.back_rank(board.next_color(color));
+
+    let mut push = |dst: RawSquarePosition| {
+        let (_, dst_rank) = board.file_rank(dst);
+        if dst_rank == last_rank {
+            for piece in PROMOTION_PIECES.iter() {
+                moves.push(Move {
+                    src: pos,
+                    dst,
+                    kind: MoveKind::Promotion(*piece),
+                });
+            }
+        } else {
+            moves.push(Move::new(pos, dst));
+        }
+    };
+
+    if let Some(dst) = board.offset(pos, 0, forward) {
+        if board.get(dst).is_none() {
+            push(dst);
+        }
+    }
+    for file in [-1, 1].iter() {
+        if let Some(dst) = board.offset(pos, *file, forward) {
+            if board.get(dst).is_some_and(|piece| piece.color != color) {
+                push(dst);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Piece;
+    use crate::game::Kind;
+
+    fn moves_from(board: &Board, square: &str) -> Vec<String> {
+        let mut moves: Vec<String> = board
+            .legal_moves()
+            .iter()
+            .filter(|m| board.square_name(m.src) == square)
+            .map(|m| board.square_name(m.dst))
+            .collect();
+        moves.sort();
+        moves
+    }
+
+    fn from_fen(fen: &str) -> Board {
+        Board::from_fen(Kind::ContrastingChess, fen).unwrap()
+    }
+
+    #[test]
+    fn start_position() {
+        let board = Board::start_position(Kind::ContrastingChess);
+        assert_eq!(board.side_len(), 10);
+        assert_eq!(
+            board.get(board.square(5, 0).unwrap()),
+            Some(Piece::new(PieceKind::King, ColorKind::WHITE))
+        );
+        assert_eq!(
+            board.get(board.square(4, 9).unwrap()),
+            Some(Piece::new(PieceKind::Elephant, ColorKind::BLACK))
+        );
+    }
+
+    #[test]
+    fn perft() {
+        let board = Board::start_position(Kind::ContrastingChess);
+        assert_eq!(board.perft(1), 44);
+        assert_eq!(board.perft(2), 1881);
+        assert_eq!(board.perft(3), 92039);
+    }
+
+    #[test]
+    fn king() {
+        let board = from_fen("5k4/10/10/10/10/10/10/10/10/5K4 w - - 0 1");
+        assert_eq!(moves_from(&board, "f1"), ["e1", "e2", "f2", "g1", "g2"]);
+    }
+
+    #[test]
+    fn elephant() {
+        let board = from_fen("5k4/10/10/10/4w5/10/10/4E2b2/10/K9 w - - 0 1");
+        let moves = moves_from(&board, "e3");
+        // The weasel on e6 can't be captured and blocks the file
+        assert!(moves.contains(&"e5".to_string()));
+        assert!(!moves.contains(&"e6".to_string()));
+        // The bear on h3 can be captured
+        assert!(moves.contains(&"h3".to_string()));
+        assert!(!moves.contains(&"i3".to_string()));
+        assert!(moves.contains(&"j8".to_string()));
+    }
+
+    #[test]
+    fn elephant_does_not_attack_weasels() {
+        let board = from_fen("10/10/10/10/10/10/10/10/k9/e3W1K3 w - - 0 1");
+        assert!(!board.is_attacked(board.square(4, 0).unwrap(), ColorKind::BLACK));
+        assert!(board.is_attacked(board.square(3, 0).unwrap(), ColorKind::BLACK));
+    }
+
+    #[test]
+    fn bear() {
+        let board = from_fen("5k4/10/10/10/10/10/10/2w7/10/B4K4 w - - 0 1");
+        assert_eq!(moves_from(&board, "a1"), ["b2", "c3"]);
+        let board = from_fen("5k4/10/10/10/10/10/10/10/10/B4K4 w - - 0 1");
+        assert_eq!(moves_from(&board, "a1"), ["b2", "c3", "d4", "e5", "f6"]);
+    }
+
+    #[test]
+    fn horse() {
+        // The horse jumps over the weasels around it, and can't land on its own pieces
+        let board = from_fen("5k4/10/10/10/10/10/10/1wW7/WW8/H4K4 w - - 0 1");
+        assert_eq!(moves_from(&board, "a1"), ["b3", "c2"]);
+        let board = from_fen("5k4/10/10/10/10/10/10/1W8/WW8/H4K4 w - - 0 1");
+        assert_eq!(moves_from(&board, "a1"), ["c2"]);
+    }
+
+    #[test]
+    fn dragon() {
+        // The dragon leaps over the weasels next to it
+        let board = from_fen("5k4/10/10/10/10/10/10/10/WWw7/D4K4 w - - 0 1");
+        assert_eq!(moves_from(&board, "a1"), ["a3", "b1", "c1", "c3"]);
+        let board = from_fen("5k4/10/10/10/10/10/10/10/10/D4K4 w - - 0 1");
+        assert_eq!(
+            moves_from(&board, "a1"),
+            ["a2", "a3", "b1", "b2", "c1", "c3"]
+        );
+    }
+
+    #[test]
+    fn moose() {
+        let board = from_fen("5k4/10/10/10/10/10/b9/10/10/M4K4 w - - 0 1");
+        assert_eq!(
+            moves_from(&board, "a1"),
+            ["a2", "a3", "a4", "b1", "c1", "d1"]
+        );
+        let board = from_fen("5k4/10/10/10/10/10/10/b9/10/M4K4 w - - 0 1");
+        assert_eq!(moves_from(&board, "a1"), ["a2", "a3", "b1", "c1", "d1"]);
+    }
+
+    #[test]
+    fn weasel() {
+        // Blocked straight ahead, but captures diagonally
+        let board = from_fen("5k4/10/10/10/10/10/3bmb4/4W5/10/5K4 w - - 0 1");
+        assert_eq!(moves_from(&board, "e3"), ["d4", "f4"]);
+        let board = from_fen("5k4/10/10/10/10/10/10/4W5/10/5K4 w - - 0 1");
+        assert_eq!(moves_from(&board, "e3"), ["e4"]);
+        // Black weasels move down the board
+        let board = from_fen("5k4/10/10/10/10/10/10/4w5/10/5K4 b - - 0 1");
+        assert_eq!(moves_from(&board, "e3"), ["e2"]);
+    }
+
+    #[test]
+    fn weasel_promotion() {
+        let mut board = from_fen("5k3m/8W1/10/10/10/10/10/10/10/5K4 w - - 0 1");
+        let promotions: Vec<_> = board
+            .legal_moves()
+            .iter()
+            .filter(|m| board.square_name(m.dst) == "j10")
+            .filter_map(|m| m.promotion())
+            .collect();
+        assert_eq!(promotions, PROMOTION_PIECES.to_vec());
+        assert_eq!(moves_from(&board, "i9").len(), 2 * PROMOTION_PIECES.len());
+
+        let m = board.parse_uci("i9i10h").unwrap();
+        let before = board.clone();
+        let undo = board.make_move(m);
+        assert_eq!(
+            board.get(board.square(8, 9).unwrap()),
+            Some(Piece::new(PieceKind::Horse, ColorKind::WHITE))
+        );
+        assert_eq!(board.halfmove_clock(), 0);
+        board.unmake_move(m, undo);
+        assert_eq!(board, before);
+    }
+
+    #[test]
+    fn weasels_check_diagonally() {
+        let board = from_fen("10/10/10/10/10/10/10/10/5w4/5K2k1 w - - 0 1");
+        assert!(!board.is_in_check());
+        let board = from_fen("10/10/10/10/10/10/10/10/4w5/5K2k1 w - - 0 1");
+        assert!(board.is_in_check());
+        assert!(moves_from(&board, "f1").contains(&"e2".to_string()));
+    }
+
+    #[test]
+    fn bare_kings_are_a_draw() {
+        assert!(from_fen("5k4/10/10/10/10/10/10/10/10/5K4 w - - 0 1").insufficient_material());
+        assert!(!from_fen("5k4/10/10/10/10/10/10/10/10/4MK4 w - - 0 1").insufficient_material());
+    }
+}
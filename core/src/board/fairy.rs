@@ -0,0 +1,245 @@
+//! Movement rules for fairy games, driven by the movements parsed from each piece's Betza
+//! notation. See the `fairy` module for how games are defined
+
+use super::{Board, Move, MoveKind, MoveList};
+use crate::fairy::{FairyGame, Movement};
+use crate::game::{ColorKind, RawSquarePosition};
+
+/// Adds the moves for every piece of the color to move to `moves`, ignoring checks
+pub fn pseudo_legal_moves(board: &Board, game: &FairyGame, moves: &mut MoveList) {
+    let color = board.to_move();
+    for (pos, piece) in board.pieces_for_color(color) {
+        let fairy_piece = match game.piece(piece.kind) {
+            Some(fairy_piece) => fairy_piece,
+            None => continue,
+        };
+        let first = moves.len();
+        for movement in usable(board, game, pos, &fairy_piece.movements) {
+            for (file, rank) in oriented(board, color, movement) {
+                let mut square = board.offset(pos, file, rank);
+                let mut distance = 1;
+                while let (Some(dst), true) = (square, distance <= movement.range) {
+                    match board.get(dst) {
+                        Some(other) => {
+                            if other.color != color && movement.captures {
+                                add_move(board, game, pos, dst, fairy_piece.promotes, moves);
+                            }
+                            break;
+                        }
+                        None if movement.moves => {
+                            add_move(board, game, pos, dst, fairy_piece.promotes, moves)
+                        }
+                        None => {}
+                    }
+                    square = board.offset(dst, file, rank);
+                    distance += 1;
+                }
+            }
+        }
+        // Movements may overlap, like a pawn's single step and its initial double step, so only
+        // keep the first of each move
+        let mut i = first;
+        while i < moves.len() {
+            if moves[first..i].contains(&moves[i]) {
+                moves.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Returns true if any piece of color `by` attacks `pos`
+pub fn is_attacked(board: &Board, game: &FairyGame, pos: RawSquarePosition, by: ColorKind) -> bool {
+    board.pieces_for_color(by).any(|(src, piece)| {
+        let fairy_piece = match game.piece(piece.kind) {
+            Some(fairy_piece) => fairy_piece,
+            None => return false,
+        };
+        usable(board, game, src, &fairy_piece.movements)
+            .filter(|movement| movement.captures)
+            .any(|movement| {
+                oriented(board, by, movement).any(|(file, rank)| {
+                    let mut square = board.offset(src, file, rank);
+                    let mut distance = 1;
+                    while let (Some(current), true) = (square, distance <= movement.range) {
+                        if current == pos {
+                            return true;
+                        }
+                        if board.get(current).is_some() {
+                            return false;
+                        }
+                        square = board.offset(current, file, rank);
+                        distance += 1;
+                    }
+                    false
+                })
+            })
+    })
+}
+
+/// Returns true if only royal pieces are left
+pub fn insufficient_material(board: &Board, game: &FairyGame) -> bool {
+    board.pieces().all(|(_, piece)| {
+        game.piece(piece.kind)
+            .is_none_or(|fairy_piece| fairy_piece.royal)
+    })
+}
+
+/// The movements a piece on `pos` may use. Initial movements are only usable if a piece of the
+/// same kind and color started the game on `pos`
+fn usable<'a>(
+    board: &'a Board,
+    game: &'a FairyGame,
+    pos: RawSquarePosition,
+    movements: &'a [Movement],
+) -> impl Iterator<Item = &'a Movement> + 'a {
+    let on_start = game.start_piece(pos.index() as usize) == board.get(pos);
+    movements
+        .iter()
+        .filter(move |movement| !movement.initial || on_start)
+}
+
+/// The offsets of a movement as seen by `color`. The second color moves down the board, so its
+/// offsets are turned around
+fn oriented<'a>(
+    board: &Board,
+    color: ColorKind,
+    movement: &'a Movement,
+) -> impl Iterator<Item = (i32, i32)> + 'a {
+    let forward = board.forward(color);
+    movement
+        .offsets
+        .iter()
+        .map(move |(file, rank)| (file * forward, rank * forward))
+}
+
+/// Adds a move, along with its promotions if a promoting piece enters the promotion zone. Entering
+/// the last rank has to promote, elsewhere in the zone not promoting is allowed too
+fn add_move(
+    board: &Board,
+    game: &FairyGame,
+    src: RawSquarePosition,
+    dst: RawSquarePosition,
+    promotes: bool,
+    moves: &mut MoveList,
+) {
+    let color = board.to_move();
+    let (_, rank) = board.file_rank(dst);
+    let ranks_from_end =
+        (board.back_rank(board.next_color(color)) as i32 - rank as i32).unsigned_abs();
+    let in_zone = promotes && ranks_from_end < game.promotion_ranks();
+    if !in_zone || ranks_from_end != 0 {
+        moves.push(Move::new(src, dst));
+    }
+    if in_zone {
+        for piece in game.promotion_pieces() {
+            moves.push(Move {
+                src,
+                dst,
+                kind: MoveKind::Promotion(*piece),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{Piece, PieceKind};
+    use crate::fairy::{self, GameDefinition, MINI_CHESS};
+    use crate::game::{GameEndCause, Kind, Variant};
+
+    const KING_HUNT: &str = r#"
+name = "King Hunt"
+side_len = 5
+start = "k4/5/5/5/K3R"
+win = "royal_capture"
+
+[[pieces]]
+name = "King"
+symbol = "K"
+betza = "K"
+royal = true
+
+[[pieces]]
+name = "Rook"
+symbol = "R"
+betza = "R"
+"#;
+
+    fn kind(definition: &str) -> Kind {
+        fairy::register(GameDefinition::from_toml(definition).unwrap()).unwrap()
+    }
+
+    fn moves_from(board: &Board, square: &str) -> Vec<String> {
+        let mut moves: Vec<String> = board
+            .legal_moves()
+            .iter()
+            .filter(|m| board.square_name(m.src) == square)
+            .map(|m| board.to_uci(*m).to_string())
+            .collect();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn start_position() {
+        let board = Board::start_position(kind(MINI_CHESS));
+        assert_eq!(board.side_len(), 6);
+        assert!(board
+            .pieces()
+            .all(|(_, piece)| matches!(piece.kind, PieceKind::Fairy(_))));
+        assert_eq!(board.to_fen(), "rnqknr/pppppp/6/6/PPPPPP/RNQKNR w - - 0 1");
+        // Six pawns with single and double steps, and two moves for each knight
+        assert_eq!(board.perft(1), 6 * 2 + 2 * 2);
+        assert_eq!(board.perft(2), 244);
+    }
+
+    #[test]
+    fn pawns() {
+        let kind = kind(MINI_CHESS);
+        let board = Board::from_fen(kind, "3k2/6/2p3/1P4/6/3K2 w - - 0 1").unwrap();
+        // Off its start square the pawn has no double step, and captures diagonally
+        assert_eq!(moves_from(&board, "b3"), ["b3b4", "b3c4"]);
+
+        let board = Board::from_fen(kind, "3k2/P5/6/6/6/3K2 w - - 0 1").unwrap();
+        assert_eq!(moves_from(&board, "a5"), ["a5a6n", "a5a6q", "a5a6r"]);
+    }
+
+    #[test]
+    fn checkmate() {
+        let kind = kind(MINI_CHESS);
+        let board = Board::from_fen(kind, "3k2/3Q2/3K2/6/6/6 b - - 0 1").unwrap();
+        assert!(board.is_in_check());
+        assert_eq!(board.game_end(), Some(GameEndCause::Checkmate));
+        let board = Board::from_fen(kind, "3k2/6/3K2/6/6/6 b - - 0 1").unwrap();
+        assert_eq!(board.game_end(), Some(GameEndCause::DeadPosition));
+    }
+
+    #[test]
+    fn castle_less_kinds_share_variants() {
+        // Games without castling can always be asked to have no castling, but never shuffled
+        for kind in [Kind::ContrastingChess, kind(MINI_CHESS)].iter() {
+            assert!(kind.supports_variants(&[Variant::NoCastling]), "{}", kind);
+            assert!(!kind.supports_variant(&Variant::Chess960), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn royal_capture() {
+        let kind = kind(KING_HUNT);
+        let mut board = Board::start_position(kind);
+        board.apply_move(board.parse_uci("e1e4").unwrap());
+        // Moving into an attack is allowed when royal pieces are captured instead of checkmated
+        assert_eq!(moves_from(&board, "a5"), ["a5a4", "a5b4", "a5b5"]);
+        board.apply_move(board.parse_uci("a5a4").unwrap());
+        assert_eq!(board.game_end(), None);
+        board.apply_move(board.parse_uci("e4a4").unwrap());
+        assert_eq!(
+            board.get(board.parse_square("a4").unwrap()),
+            Some(Piece::new(PieceKind::Fairy(1), ColorKind::WHITE))
+        );
+        assert_eq!(board.game_end(), Some(GameEndCause::RoyalCaptured));
+    }
+}
@@ -0,0 +1,381 @@
+//! Forsyth–Edwards Notation, including the X-FEN and Shredder-FEN castling extensions used for
+//! Chess960
+
+use super::{Board, CastleSide, CastlingRights, ParseError, Piece, PieceKind, RANK_SEPARATOR};
+use crate::game::{ColorKind, Kind};
+
+/// How castling rights are written when printing a FEN
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CastlingStyle {
+    /// KQkq when the castling rook is the outermost rook on its side of the king, otherwise the
+    /// file of the rook. Identical to standard FEN for standard chess positions
+    XFen,
+    /// Always the file of the rook, for example HAha
+    Shredder,
+}
+
+const SIDES: [CastleSide; 2] = [CastleSide::King, CastleSide::Queen];
+
+impl Board {
+    /// Parses a FEN string. The castling field may use standard (KQkq), X-FEN or Shredder-FEN
+    /// notation. The halfmove clock and fullmove number may be omitted, in which case they
+    /// default to 0 and 1.
+    /// Empty squares are counted with decimal numbers, so boards wider than 9 squares may use
+    /// multiple digits in a row
+    pub fn from_fen(kind: Kind, fen: &str) -> Result<Board, ParseError> {
+        let mut fields = fen.split_whitespace();
+        let placement = fields.next().ok_or(ParseError::MissingField("placement"))?;
+        let mut board = parse_placement(kind, placement)?;
+
+        match fields
+            .next()
+            .ok_or(ParseError::MissingField("side to move"))?
+        {
+            "w" => board.set_to_move(ColorKind::WHITE),
+            "b" => board.set_to_move(ColorKind::BLACK),
+            side => return Err(ParseError::InvalidSideToMove(side.to_owned())),
+        }
+
+        let castling = fields.next().ok_or(ParseError::MissingField("castling"))?;
+        let castling = parse_castling(&board, castling)?;
+        board.set_castling(castling);
+
+        let en_passant = fields
+            .next()
+            .ok_or(ParseError::MissingField("en passant"))?;
+        if en_passant != "-" {
+            // The square has to be behind a pawn of the side that just moved, which it skipped over
+            let square = board
+                .parse_square(en_passant)
+                .filter(|square| board.is_possible_en_passant(*square))
+                .ok_or_else(|| ParseError::InvalidEnPassant(en_passant.to_owned()))?;
+            board.set_en_passant(Some(square));
+        }
+
+        if let Some(halfmove_clock) = fields.next() {
+            board.set_halfmove_clock(parse_number(halfmove_clock)?);
+        }
+        if let Some(fullmove_number) = fields.next() {
+            board.set_fullmove_number(parse_number(fullmove_number)?);
+        }
+
+        Ok(board)
+    }
+
+    /// Prints this board as a FEN string, using X-FEN castling rights. For standard chess
+    /// positions this is the same as regular FEN
+    pub fn to_fen(&self) -> String {
+        self.format_fen(CastlingStyle::XFen)
+    }
+
+    /// Prints this board as a Shredder-FEN string, where castling rights are always given as the
+    /// file of the rook
+    pub fn to_shredder_fen(&self) -> String {
+        self.format_fen(CastlingStyle::Shredder)
+    }
+
+    fn format_fen(&self, style: CastlingStyle) -> String {
+        let mut fen = String::new();
+        let side_len = self.side_len();
+        for rank in (0..side_len).rev() {
+            let mut empty = 0;
+            for file in 0..side_len {
+                match self.get(self.square(file, rank).unwrap()) {
+                    Some(piece) => {
+                        if empty != 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece.to_char(self.kind()));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty != 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank != 0 {
+                fen.push(RANK_SEPARATOR);
+            }
+        }
+
+        fen.push(' ');
+        fen.push(match self.to_move().id() {
+            0 => 'w',
+            _ => 'b',
+        });
+
+        fen.push(' ');
+        let castling = self.format_castling(style);
+        if castling.is_empty() {
+            fen.push('-');
+        } else {
+            fen.push_str(&castling);
+        }
+
+        fen.push(' ');
+        match self.en_passant() {
+            Some(square) => fen.push_str(&self.square_name(square)),
+            None => fen.push('-'),
+        }
+
+        fen.push_str(&format!(
+            " {} {}",
+            self.halfmove_clock(),
+            self.fullmove_number()
+        ));
+        fen
+    }
+
+    fn format_castling(&self, style: CastlingStyle) -> String {
+        let mut result = String::new();
+        let outermost = CastlingRights::from_board(self);
+        for color in [ColorKind::WHITE, ColorKind::BLACK].iter() {
+            for side in SIDES.iter() {
+                let file = match self.castling().get(*color, *side) {
+                    Some(file) => file,
+                    None => continue,
+                };
+                let c =
+                    if style == CastlingStyle::XFen && outermost.get(*color, *side) == Some(file) {
+                        match side {
+                            CastleSide::King => 'K',
+                            CastleSide::Queen => 'Q',
+                        }
+                    } else {
+                        (b'A' + file as u8) as char
+                    };
+                result.push(match color.id() {
+                    0 => c,
+                    _ => c.to_ascii_lowercase(),
+                });
+            }
+        }
+        result
+    }
+}
+
+/// Parses the placement field of a FEN
+fn parse_placement(kind: Kind, placement: &str) -> Result<Board, ParseError> {
+    let side_len = kind.side_len();
+    let mut board = Board::empty(kind);
+
+    let ranks: Vec<&str> = placement.split(RANK_SEPARATOR).collect();
+    if ranks.len() as u32 != side_len {
+        return Err(ParseError::RankCount {
+            expected: side_len,
+            found: ranks.len() as u32,
+        });
+    }
+
+    for (i, rank_str) in ranks.iter().enumerate() {
+        let rank = side_len - 1 - i as u32;
+        let rank_length = |found| ParseError::RankLength {
+            rank,
+            expected: side_len,
+            found,
+        };
+        let mut file = 0;
+        let mut empty = 0;
+        for c in rank_str.chars() {
+            if let Some(digit) = c.to_digit(10) {
+                empty = empty * 10 + digit;
+                // Stop before a long run of digits can overflow
+                if file + empty > side_len {
+                    return Err(rank_length(file + empty));
+                }
+                continue;
+            }
+            file += empty;
+            empty = 0;
+            let piece = Piece::from_char(kind, c).ok_or(ParseError::UnknownPiece(c))?;
+            if file >= side_len {
+                return Err(rank_length(file + 1));
+            }
+            board.set(board.square(file, rank).unwrap(), Some(piece));
+            file += 1;
+        }
+        file += empty;
+        if file != side_len {
+            return Err(rank_length(file));
+        }
+    }
+    Ok(board)
+}
+
+fn parse_castling(board: &Board, castling: &str) -> Result<CastlingRights, ParseError> {
+    let mut rights = CastlingRights::none();
+    if castling == "-" {
+        return Ok(rights);
+    }
+    let outermost = CastlingRights::from_board(board);
+    for c in castling.chars() {
+        let color = if c.is_ascii_uppercase() {
+            ColorKind::WHITE
+        } else {
+            ColorKind::BLACK
+        };
+        let rank = board.back_rank(color);
+        let king_file = match board.king_square(color) {
+            Some(king) if board.file_rank(king).1 == rank => board.file_rank(king).0,
+            _ => return Err(ParseError::InvalidCastling(c)),
+        };
+
+        let (side, file) = match c.to_ascii_uppercase() {
+            'K' => (CastleSide::King, outermost.get(color, CastleSide::King)),
+            'Q' => (CastleSide::Queen, outermost.get(color, CastleSide::Queen)),
+            file_char if file_char.is_ascii_uppercase() => {
+                let file = file_char as u32 - 'A' as u32;
+                let side = if file > king_file {
+                    CastleSide::King
+                } else {
+                    CastleSide::Queen
+                };
+                (side, Some(file))
+            }
+            _ => return Err(ParseError::InvalidCastling(c)),
+        };
+
+        let rook = file.and_then(|file| board.square(file, rank));
+        match rook.and_then(|rook| board.get(rook)) {
+            Some(piece) if piece == Piece::new(PieceKind::Rook, color) => {
+                rights.set(color, side, file);
+            }
+            _ => return Err(ParseError::InvalidCastling(c)),
+        }
+    }
+    Ok(rights)
+}
+
+fn parse_number(number: &str) -> Result<u32, ParseError> {
+    number
+        .parse()
+        .map_err(|_| ParseError::InvalidNumber(number.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn start_position() {
+        let board = Board::from_fen(Kind::Chess, START).unwrap();
+        assert_eq!(board, Board::start_position(Kind::Chess));
+        assert_eq!(board.to_fen(), START);
+        assert_eq!(
+            board.to_shredder_fen(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 12 40",
+        ]
+        .iter()
+        {
+            assert_eq!(Board::from_fen(Kind::Chess, fen).unwrap().to_fen(), *fen);
+        }
+    }
+
+    #[test]
+    fn chess960_castling() {
+        // Two rooks on the kingside, the inner one can't be written as K in X-FEN
+        let shredder = "rk2r1r1/8/8/8/8/8/8/RK2R1R1 w EAea - 0 1";
+        let board = Board::from_fen(Kind::Chess, shredder).unwrap();
+        assert_eq!(
+            board.castling().get(ColorKind::WHITE, CastleSide::King),
+            Some(4)
+        );
+        assert_eq!(
+            board.castling().get(ColorKind::BLACK, CastleSide::Queen),
+            Some(0)
+        );
+        assert_eq!(board.to_fen(), "rk2r1r1/8/8/8/8/8/8/RK2R1R1 w EQeq - 0 1");
+        assert_eq!(board.to_shredder_fen(), shredder);
+        assert_eq!(
+            Board::from_fen(Kind::Chess, &board.to_fen()).unwrap(),
+            board
+        );
+    }
+
+    #[test]
+    fn en_passant_squares() {
+        let parse = |fen| Board::from_fen(Kind::Chess, fen);
+        assert!(parse("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").is_ok());
+        assert!(parse("4k3/8/8/4p3/8/8/8/4K3 w - e6 0 1").is_ok());
+        // On the wrong side's rank, with no pawn in front, or with a piece other than a pawn
+        for fen in [
+            "4k3/8/8/4p3/8/8/8/4K3 b - e6 0 1",
+            "4k3/8/8/8/4P3/8/8/4K3 w - e3 0 1",
+            "4k3/8/8/8/4P3/8/8/4K3 w - e5 0 1",
+            "4k3/8/8/8/8/8/8/4K3 b - e3 0 1",
+            "4k3/8/8/8/4N3/8/8/4K3 b - e3 0 1",
+            "4k3/8/8/8/4p3/8/8/4K3 b - e3 0 1",
+        ]
+        .iter()
+        {
+            let square = fen.split(' ').nth(3).unwrap().to_owned();
+            assert_eq!(
+                parse(fen),
+                Err(ParseError::InvalidEnPassant(square)),
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn defaults_and_errors() {
+        let board = Board::from_fen(Kind::Chess, "8/8/8/8/8/8/8/K6k w - -").unwrap();
+        assert_eq!(board.halfmove_clock(), 0);
+        assert_eq!(board.fullmove_number(), 1);
+
+        assert_eq!(
+            Board::from_fen(Kind::Chess, "8/8/8/8/8/8/8/K6k"),
+            Err(ParseError::MissingField("side to move"))
+        );
+        assert_eq!(
+            Board::from_fen(Kind::Chess, "8/8/8/8/8/8/8/K6k x - -"),
+            Err(ParseError::InvalidSideToMove("x".to_owned()))
+        );
+        assert_eq!(
+            Board::from_fen(Kind::Chess, "8/8/8/8/8/8/8/K6k w K -"),
+            Err(ParseError::InvalidCastling('K'))
+        );
+        assert_eq!(
+            Board::from_fen(Kind::Chess, "8/8/8/8/8/8/8/K6k w - z9"),
+            Err(ParseError::InvalidEnPassant("z9".to_owned()))
+        );
+        assert_eq!(
+            Board::from_fen(Kind::Chess, "8/8/8/8/8/8/8/K7k w - -"),
+            Err(ParseError::RankLength {
+                rank: 0,
+                expected: 8,
+                found: 9
+            })
+        );
+        // Long runs of digits are rejected rather than overflowing
+        assert_eq!(
+            Board::from_fen(Kind::Chess, "99999999999/8/8/8/8/8/8/K6k w - -"),
+            Err(ParseError::RankLength {
+                rank: 7,
+                expected: 8,
+                found: 9
+            })
+        );
+        assert_eq!(
+            Board::from_fen(Kind::Chess, "8/8/8/8/8/8/8/K6kk w - -"),
+            Err(ParseError::RankLength {
+                rank: 0,
+                expected: 8,
+                found: 9
+            })
+        );
+    }
+}
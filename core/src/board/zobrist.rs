@@ -0,0 +1,418 @@
+//! Zobrist hashing, which reduces a position to a 64 bit key. Positions that are the same for the
+//! purposes of the repetition rules have the same key.
+//!
+//! Keys are laid out the way the Polyglot opening book format lays out its `Random64` table, so
+//! that loading Polyglot's published table with `ZobristKeys::polyglot` gives standard chess
+//! positions the keys Polyglot books are indexed by. The key of a position is the xor of:
+//!
+//! * `pieces[piece * squares + square]` for every piece on the board, where `piece` is
+//!   `2 * kind + color` with kinds numbered pawn, knight, bishop, rook, queen, king from 0 (the
+//!   pieces of contrasting chess follow, elephant, bear, horse, dragon, moose, weasel, then the
+//!   pieces of fairy games in the order they are defined) and
+//!   color 0 for black and 1 for white (Polyglot's `kind_of_piece`), and `square` is
+//!   `rank * side_len + file`. On an 8x8 board this is Polyglot's `64 * kind_of_piece + 8 * row +
+//!   file`
+//! * `castling[2 * color + side]` for every castling right, white then black and the king side
+//!   then the queen side. The file of the rook doesn't matter, so Chess960 positions use the same
+//!   four keys
+//! * `en_passant[file]` if a pawn of the color to move stands next to the pawn that just moved two
+//!   squares, whether or not capturing would leave its king in check
+//! * `turn` if white is to move
+//!
+//! Polyglot only defines keys for the 8x8 board and the six standard pieces. The built in tables
+//! use the same layout for every board size up to `MAX_SIDE_LEN`, such as the 10x10 board of
+//! contrasting chess, with keys drawn from a fixed seed. Their keys don't match Polyglot's, so the
+//! keys built in to `Board` are only meant to be compared with each other
+//!
+//! The key without the en passant part is kept up to date as pieces are placed and moves are
+//! applied. Whether en passant counts depends on the pawns next to the square, so that part is
+//! added when the key is read
+
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use super::{Board, CastleSide, CastlingRights, Piece, PieceKind};
+use crate::game::{ColorKind, RawSquarePosition};
+
+/// The longest board edge keys are built in for
+pub const MAX_SIDE_LEN: usize = 16;
+
+/// The number of keys in Polyglot's `Random64` table: 768 piece keys, 4 castling keys, 8 en
+/// passant keys and the turn key, in that order
+pub const POLYGLOT_KEY_COUNT: usize = 781;
+
+/// The number of piece kinds keys are generated for: the pieces of chess and contrasting chess,
+/// and those of fairy games
+const PIECE_KINDS: usize = 12 + crate::fairy::MAX_PIECES;
+
+/// The number of colors keys are generated for
+const COLORS: usize = 2;
+
+/// The seed of the generator the built in keys are drawn from. Keys have to be the same every run
+/// for stored keys to stay valid
+const SEED: u64 = 0x6769_6761_6368_6573;
+
+/// The random keys that are combined with xor to form the key of a position on a board of a
+/// particular size. See the module documentation for the layout
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZobristKeys {
+    side_len: usize,
+    pieces: Vec<u64>,
+    castling: [u64; 4],
+    en_passant: Vec<u64>,
+    turn: u64,
+}
+
+/// The parts of a `Board` that are serialized. The Zobrist key is derived from the rest of the
+/// board, so it is recomputed rather than trusted
+#[derive(Deserialize)]
+pub(super) struct SerializedBoard {
+    kind: crate::game::Kind,
+    squares: Vec<Option<Piece>>,
+    to_move: ColorKind,
+    castling: CastlingRights,
+    en_passant: Option<RawSquarePosition>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+}
+
+impl ZobristKeys {
+    /// Draws keys for a board with `side_len` squares along each edge from a generator seeded with
+    /// `seed`
+    pub fn generate(side_len: usize, seed: u64) -> ZobristKeys {
+        let mut state = seed;
+        let mut next = || {
+            // SplitMix64
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        ZobristKeys {
+            side_len,
+            pieces: (0..PIECE_KINDS * COLORS * side_len * side_len)
+                .map(|_| next())
+                .collect(),
+            castling: [next(), next(), next(), next()],
+            en_passant: (0..side_len).map(|_| next()).collect(),
+            turn: next(),
+        }
+    }
+
+    /// Builds keys for the 8x8 board from Polyglot's `Random64` table. Returns None if `random64`
+    /// doesn't have exactly `POLYGLOT_KEY_COUNT` keys. Pieces Polyglot doesn't know about keep
+    /// built in keys
+    pub fn polyglot(random64: &[u64]) -> Option<ZobristKeys> {
+        if random64.len() != POLYGLOT_KEY_COUNT {
+            return None;
+        }
+        let mut keys = ZobristKeys::built_in(8).clone();
+        keys.pieces[..768].copy_from_slice(&random64[..768]);
+        keys.castling.copy_from_slice(&random64[768..772]);
+        keys.en_passant.copy_from_slice(&random64[772..780]);
+        keys.turn = random64[780];
+        Some(keys)
+    }
+
+    /// The keys `Board` uses for boards with `side_len` squares along each edge
+    pub(super) fn built_in(side_len: usize) -> &'static ZobristKeys {
+        static KEYS: [OnceLock<ZobristKeys>; MAX_SIDE_LEN + 1] =
+            [const { OnceLock::new() }; MAX_SIDE_LEN + 1];
+        assert!(
+            side_len <= MAX_SIDE_LEN,
+            "No Zobrist keys for boards this large"
+        );
+        KEYS[side_len].get_or_init(|| ZobristKeys::generate(side_len, SEED ^ side_len as u64))
+    }
+
+    /// The number of squares along each edge of the boards these keys are for
+    pub fn side_len(&self) -> usize {
+        self.side_len
+    }
+
+    pub(super) fn piece(&self, pos: RawSquarePosition, piece: Piece) -> u64 {
+        let kind = match piece.kind {
+            PieceKind::Pawn => 0,
+            PieceKind::Knight => 1,
+            PieceKind::Bishop => 2,
+            PieceKind::Rook => 3,
+            PieceKind::Queen => 4,
+            PieceKind::King => 5,
+            PieceKind::Elephant => 6,
+            PieceKind::Bear => 7,
+            PieceKind::Horse => 8,
+            PieceKind::Dragon => 9,
+            PieceKind::Moose => 10,
+            PieceKind::Weasel => 11,
+            PieceKind::Fairy(index) => 12 + index as usize,
+        };
+        // Polyglot numbers black before white
+        let color = COLORS - 1 - piece.color.id() as usize % COLORS;
+        let squares = self.side_len * self.side_len;
+        self.pieces[(kind * COLORS + color) * squares + pos.index() as usize]
+    }
+
+    pub(super) fn castling(&self, castling: CastlingRights) -> u64 {
+        let mut key = 0;
+        for color in [ColorKind::WHITE, ColorKind::BLACK].iter() {
+            for side in [CastleSide::King, CastleSide::Queen].iter() {
+                if castling.get(*color, *side).is_some() {
+                    key ^= self.castling[color.id() as usize * 2 + side.index()];
+                }
+            }
+        }
+        key
+    }
+
+    pub(super) fn en_passant(&self, file: u32) -> u64 {
+        self.en_passant[file as usize]
+    }
+
+    pub(super) fn to_move(&self, color: ColorKind) -> u64 {
+        if color == ColorKind::WHITE {
+            self.turn
+        } else {
+            0
+        }
+    }
+}
+
+impl Board {
+    /// The Zobrist key of this position. It covers the pieces, the color to move, the castling
+    /// rights, and the en passant square if a pawn can actually capture onto it. The move counters
+    /// aren't part of a position, so they don't affect the key
+    pub fn zobrist_key(&self) -> u64 {
+        self.zobrist ^ self.en_passant_key(self.keys())
+    }
+
+    /// Computes the Zobrist key of this position with `keys` instead of the built in keys, for
+    /// example to look the position up in a Polyglot book.
+    /// Panics if `keys` are for a different board size
+    pub fn zobrist_key_with(&self, keys: &ZobristKeys) -> u64 {
+        assert_eq!(
+            keys.side_len,
+            self.side_len() as usize,
+            "Zobrist keys are for a different board size"
+        );
+        self.key_without_en_passant(keys) ^ self.en_passant_key(keys)
+    }
+
+    /// Computes the key `zobrist_key` keeps up to date from scratch
+    pub(super) fn key_without_en_passant(&self, keys: &ZobristKeys) -> u64 {
+        let mut key = keys.castling(self.castling) ^ keys.to_move(self.to_move);
+        for (pos, piece) in self.pieces() {
+            key ^= keys.piece(pos, piece);
+        }
+        key
+    }
+
+    /// The built in keys for this board's size
+    pub(super) fn keys(&self) -> &'static ZobristKeys {
+        ZobristKeys::built_in(self.side_len() as usize)
+    }
+
+    /// Updates the key for `old` being replaced by `new` on `pos`
+    pub(super) fn hash_square(
+        &mut self,
+        pos: RawSquarePosition,
+        old: Option<Piece>,
+        new: Option<Piece>,
+    ) {
+        let keys = self.keys();
+        for piece in old.iter().chain(new.iter()) {
+            self.zobrist ^= keys.piece(pos, *piece);
+        }
+    }
+
+    /// Updates the key for the color to move changing from `old` to `new`
+    pub(super) fn hash_to_move(&mut self, old: ColorKind, new: ColorKind) {
+        let keys = self.keys();
+        self.zobrist ^= keys.to_move(old) ^ keys.to_move(new);
+    }
+
+    /// Updates the key for the castling rights changing from `old` to `new`
+    pub(super) fn hash_castling(&mut self, old: CastlingRights, new: CastlingRights) {
+        let keys = self.keys();
+        self.zobrist ^= keys.castling(old) ^ keys.castling(new);
+    }
+
+    fn en_passant_key(&self, keys: &ZobristKeys) -> u64 {
+        match self.en_passant {
+            Some(en_passant) if self.can_capture_en_passant() => {
+                keys.en_passant(self.file_rank(en_passant).0)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns true if a pawn of the color to move stands next to the pawn that just moved two
+    /// squares, so that en passant makes a difference to the position
+    fn can_capture_en_passant(&self) -> bool {
+        let en_passant = match self.en_passant {
+            Some(en_passant) => en_passant,
+            None => return false,
+        };
+        let back = -self.forward(self.to_move);
+        [-1, 1].iter().any(|file| {
+            self.offset(en_passant, *file, back)
+                .and_then(|pos| self.get(pos))
+                .is_some_and(|piece| piece.kind == PieceKind::Pawn && piece.color == self.to_move)
+        })
+    }
+}
+
+impl From<SerializedBoard> for Board {
+    fn from(board: SerializedBoard) -> Board {
+        let serialized = board;
+        let kind = serialized.kind;
+        let mut board = Board::empty(kind);
+        // Pieces the game kind has no place for are dropped, as `Board::empty` may have picked
+        // storage that can't hold them
+        for (i, piece) in serialized.squares.into_iter().enumerate() {
+            let pos = RawSquarePosition::new(i as u32);
+            let fits = |piece: &Piece| {
+                piece.color.id() < kind.color_count()
+                    && piece.kind.to_char(kind, piece.color).is_some()
+            };
+            if board.contains(pos) && piece.as_ref().is_some_and(fits) {
+                board.set(pos, piece);
+            }
+        }
+        // A color that isn't playing or an en passant square no pawn skipped over would corrupt
+        // move generation, so they are dropped too
+        if serialized.to_move.id() < kind.color_count() {
+            board.to_move = serialized.to_move;
+        }
+        board.castling = serialized.castling;
+        board.en_passant = serialized
+            .en_passant
+            .filter(|en_passant| board.is_possible_en_passant(*en_passant));
+        board.halfmove_clock = serialized.halfmove_clock;
+        board.fullmove_number = serialized.fullmove_number;
+        board.zobrist = board.key_without_en_passant(board.keys());
+        board
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Kind;
+
+    fn key(fen: &str) -> u64 {
+        Board::from_fen(Kind::Chess, fen).unwrap().zobrist_key()
+    }
+
+    fn assert_up_to_date(board: &Board) {
+        assert_eq!(board.zobrist_key(), board.zobrist_key_with(board.keys()));
+    }
+
+    #[test]
+    fn transpositions_share_keys() {
+        let mut board = Board::start_position(Kind::Chess);
+        let start = board.zobrist_key();
+        for uci in ["g1f3", "g8f6", "f3g1", "f6g8"].iter() {
+            board.apply_move(board.parse_uci(uci).unwrap());
+        }
+        assert_eq!(board.zobrist_key(), start);
+        // Only the move counters differ
+        assert_eq!(
+            key("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 4 3"),
+            start
+        );
+        assert_ne!(
+            key("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1"),
+            start
+        );
+        assert_ne!(
+            key("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kkq - 0 1"),
+            start
+        );
+    }
+
+    #[test]
+    fn en_passant_only_counts_when_possible() {
+        // No black pawn can capture on e3
+        assert_eq!(
+            key("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"),
+            key("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1")
+        );
+        assert_ne!(
+            key("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"),
+            key("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1")
+        );
+    }
+
+    #[test]
+    fn incremental_keys_match_computed_keys() {
+        let mut board = Board::from_fen(
+            Kind::Chess,
+            "r3k2r/1P4p1/8/3pP3/8/8/6P1/R3K2R w KQkq d6 0 1",
+        )
+        .unwrap();
+        assert_up_to_date(&board);
+        // En passant, a promotion capturing a rook, castling, and a rook capture
+        for uci in ["e5d6", "e8g8", "b7a8q", "f8a8", "e1c1", "a8a1"].iter() {
+            board.apply_move(board.parse_uci(uci).unwrap());
+            assert_up_to_date(&board);
+        }
+
+        let json = serde_json::to_string(&board).unwrap();
+        let board: Board = serde_json::from_str(&json).unwrap();
+        assert_up_to_date(&board);
+    }
+
+    #[test]
+    fn invalid_serialized_state_is_dropped() {
+        let board = Board::from_fen(Kind::Chess, "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+        let json = serde_json::to_value(&board).unwrap();
+        let parse = |field: &str, value: serde_json::Value| {
+            let mut json = json.clone();
+            json[field] = value;
+            serde_json::from_value::<Board>(json).unwrap()
+        };
+        assert_eq!(parse("en_passant", json["en_passant"].clone()), board);
+        // Off the board, on the wrong rank, and with no pawn that skipped over it
+        let e3 = board.parse_square("e3").unwrap();
+        let e6 = board.parse_square("e6").unwrap();
+        for en_passant in [RawSquarePosition::new(200), e3, e6].iter() {
+            let value = serde_json::to_value(Some(*en_passant)).unwrap();
+            assert_eq!(parse("en_passant", value).en_passant(), None);
+        }
+        let value = serde_json::to_value(ColorKind::new(5)).unwrap();
+        assert_eq!(parse("to_move", value).to_move(), ColorKind::WHITE);
+    }
+
+    #[test]
+    fn polyglot_layout() {
+        let random64: Vec<u64> = (1..=POLYGLOT_KEY_COUNT as u64)
+            .map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+            .collect();
+        assert_eq!(ZobristKeys::polyglot(&random64[1..]), None);
+        let keys = ZobristKeys::polyglot(&random64).unwrap();
+        let key = |fen| {
+            Board::from_fen(Kind::Chess, fen)
+                .unwrap()
+                .zobrist_key_with(&keys)
+        };
+        // White king on e1 and black king on e8, with white to move
+        assert_eq!(
+            key("4k3/8/8/8/8/8/8/4K3 w - - 0 1"),
+            random64[64 * 11 + 4] ^ random64[64 * 10 + 8 * 7 + 4] ^ random64[780]
+        );
+        // A black pawn on d4 can capture the white pawn on e4 en passant, and black may castle
+        // queenside
+        assert_eq!(
+            key("r3k3/8/8/8/3pP3/8/8/4K3 b q e3 0 1"),
+            random64[64 * 11 + 4]
+                ^ random64[64 * 10 + 8 * 7 + 4]
+                ^ random64[64 * 6 + 8 * 7]
+                ^ random64[64 + 8 * 3 + 4]
+                ^ random64[8 * 3 + 3]
+                ^ random64[768 + 3]
+                ^ random64[772 + 4]
+        );
+    }
+}
@@ -0,0 +1,820 @@
+//! Fairy games described by data instead of code. A definition gives the size of the board, the
+//! pieces and how they move in Betza notation, the start position, where pieces promote and how
+//! the game is won. Definitions are read from TOML or JSON files and registered at runtime, after
+//! which the game is a `Kind::Fairy` that boards, the moderator and engines play like any other
+//! kind. A definition for a small chess game could look like this:
+//!
+//! ```toml
+//! name = "Mini Chess"
+//! side_len = 6
+//! start = "rnqknr/pppppp/6/6/PPPPPP/RNQKNR"
+//!
+//! [[pieces]]
+//! name = "King"
+//! symbol = "K"
+//! betza = "K"
+//! royal = true
+//!
+//! [[pieces]]
+//! name = "Pawn"
+//! symbol = "P"
+//! betza = "fmWfcF"
+//!
+//! # ... the queen, rook and knight
+//!
+//! [promotion]
+//! pieces = ["P"]
+//! to = ["Q", "R", "N"]
+//! ```
+//!
+//! The supported subset of Betza notation is:
+//!
+//! * The atoms W (1,0), F (1,1), D (2,0), N (2,1), A (2,2), H (3,0), C (3,1), Z (3,2) and G (3,3),
+//!   and the compounds K (WF), R (WW), B (FF) and Q (WWFF)
+//! * Repeating an atom makes it a rider that slides until blocked, and a number after it limits
+//!   how far it slides. 0 means no limit
+//! * `m` for moves that can't capture and `c` for captures that can't move to an empty square
+//! * `i` for moves only allowed from a square the piece started the game on
+//! * The directions `f`, `b`, `l`, `r`, `v` and `s`, as seen by the player owning the piece.
+//!   `f` or `b` directly followed by `l` or `r` restricts to both, as in `flF`
+//!
+//! Leapers jump over anything in between, riders stop at the first piece in their way. There is
+//! no castling, en passant or drops in fairy games
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::sync::{OnceLock, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::board::{Piece, PieceKind};
+use crate::game::{ColorKind, Kind};
+
+/// The most piece kinds a fairy game may define
+pub const MAX_PIECES: usize = 16;
+
+/// The largest board a fairy game may be played on
+pub const MAX_SIDE_LEN: u32 = crate::board::MAX_SIDE_LEN as u32;
+
+/// A fairy game as it is written in a definition file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GameDefinition {
+    /// The name of the game, which is also the name of its kind in messages. It has to differ from
+    /// every other game kind
+    pub name: String,
+
+    /// The number of squares along each edge of the board
+    pub side_len: u32,
+
+    pub pieces: Vec<PieceDefinition>,
+
+    /// The piece placement field of a FEN string for the start position, using the symbols of
+    /// `pieces`. The first color moves first
+    pub start: String,
+
+    #[serde(default)]
+    pub promotion: Option<PromotionDefinition>,
+
+    #[serde(default)]
+    pub win: WinCondition,
+}
+
+/// A piece of a fairy game
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PieceDefinition {
+    pub name: String,
+
+    /// The uppercase char representing the piece. The second color uses the lowercase char
+    pub symbol: char,
+
+    /// How the piece moves and captures, in Betza notation
+    pub betza: String,
+
+    /// Royal pieces are the ones that can be checkmated or captured to win the game
+    #[serde(default)]
+    pub royal: bool,
+}
+
+/// Which pieces promote, where, and to what
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PromotionDefinition {
+    /// The symbols of the pieces that promote
+    pub pieces: Vec<char>,
+
+    /// The number of ranks on the far side of the board that make up the promotion zone. A piece
+    /// moving into the zone may promote, and has to on the last rank. Defaults to 1
+    #[serde(default = "default_zone_ranks")]
+    pub ranks: u32,
+
+    /// The symbols of the pieces that may be promoted to
+    pub to: Vec<char>,
+}
+
+/// How a fairy game is won
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WinCondition {
+    /// Royal pieces may never be left attacked, and a player whose royal piece is attacked
+    /// without a legal move loses. The game must have exactly one royal piece kind
+    #[default]
+    Checkmate,
+
+    /// Royal pieces may be left attacked, and a player loses when they have no royal pieces left
+    RoyalCapture,
+}
+
+/// A registered fairy game, checked and with its movement parsed
+#[derive(Debug)]
+pub struct FairyGame {
+    definition: GameDefinition,
+    pieces: Vec<FairyPiece>,
+    chars: Vec<(PieceKind, char)>,
+    /// The start position as a board string
+    board_string: String,
+    /// The piece on each square of the start position
+    start: Vec<Option<Piece>>,
+    promotion_pieces: Vec<PieceKind>,
+}
+
+/// A registered piece of a fairy game
+#[derive(Debug)]
+pub struct FairyPiece {
+    pub royal: bool,
+    pub promotes: bool,
+    pub movements: Vec<Movement>,
+}
+
+/// One part of a piece's movement, such as the forward moves of a pawn
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movement {
+    /// The steps the piece takes, as (file, rank) offsets for the first color. The second color
+    /// uses the opposite offsets
+    pub offsets: Vec<(i32, i32)>,
+
+    /// The most steps the piece takes in one direction. 1 for leapers
+    pub range: u32,
+
+    /// True if the piece may move to an empty square
+    pub moves: bool,
+
+    /// True if the piece may capture an enemy piece
+    pub captures: bool,
+
+    /// True if the movement is only allowed from a square the piece started the game on
+    pub initial: bool,
+}
+
+/// A registered fairy game kind. Cheap to copy, and compared by name as names are unique
+#[derive(Copy, Clone)]
+pub struct FairyKind(&'static FairyGame);
+
+/// The reasons a fairy game can fail to load
+#[derive(Debug)]
+pub enum FairyError {
+    /// The definition file could not be read
+    Io(io::Error),
+
+    /// The definition file was not valid TOML or JSON, or was missing fields
+    Parse(String),
+
+    /// The definition file did not end in .toml or .json
+    UnknownFormat(String),
+
+    /// Another game kind already has this name
+    NameTaken(String),
+
+    /// Boards must have between 1 and `MAX_SIDE_LEN` squares along each edge
+    InvalidSideLen(u32),
+
+    /// More than `MAX_PIECES` piece kinds were defined
+    TooManyPieces(usize),
+
+    /// Two pieces were given the same symbol, or a symbol was not an uppercase letter
+    InvalidSymbol(char),
+
+    /// A piece's movement couldn't be parsed
+    InvalidBetza { piece: String, betza: String },
+
+    /// The start position or promotion referred to a symbol no piece has
+    UnknownSymbol(char),
+
+    /// The start position was malformed, or didn't fit the board
+    InvalidStart(String),
+
+    /// A checkmate game needs exactly one royal piece kind, and a royal capture game at least one
+    RoyalPieces(usize),
+}
+
+fn default_zone_ranks() -> u32 {
+    1
+}
+
+impl GameDefinition {
+    pub fn from_toml(s: &str) -> Result<GameDefinition, FairyError> {
+        toml::from_str(s).map_err(|err| FairyError::Parse(err.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<GameDefinition, FairyError> {
+        serde_json::from_str(s).map_err(|err| FairyError::Parse(err.to_string()))
+    }
+
+    /// Reads a definition file, choosing the format by its extension
+    pub fn read(path: &Path) -> Result<GameDefinition, FairyError> {
+        let contents = fs::read_to_string(path).map_err(FairyError::Io)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => GameDefinition::from_toml(&contents),
+            Some("json") => GameDefinition::from_json(&contents),
+            _ => Err(FairyError::UnknownFormat(path.display().to_string())),
+        }
+    }
+}
+
+/// The registered games, in registration order
+fn registry() -> &'static RwLock<Vec<&'static FairyGame>> {
+    static REGISTRY: OnceLock<RwLock<Vec<&'static FairyGame>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(Vec::new()))
+}
+
+/// Checks a definition and registers it, returning its game kind. Registering a definition that
+/// is already registered returns the existing kind
+pub fn register(definition: GameDefinition) -> Result<Kind, FairyError> {
+    if builtin_kind(&definition.name).is_some() {
+        return Err(FairyError::NameTaken(definition.name));
+    }
+    let mut registry = registry().write().unwrap();
+    if let Some(game) = registry
+        .iter()
+        .find(|game| game.definition.name == definition.name)
+    {
+        return if game.definition == definition {
+            Ok(Kind::Fairy(FairyKind(game)))
+        } else {
+            Err(FairyError::NameTaken(definition.name))
+        };
+    }
+    // Registered games live for the rest of the program, so that kinds can be copied freely
+    let game: &'static FairyGame = Box::leak(Box::new(FairyGame::new(definition)?));
+    registry.push(game);
+    Ok(Kind::Fairy(FairyKind(game)))
+}
+
+/// Reads a definition file and registers the game in it
+pub fn load(path: impl AsRef<Path>) -> Result<Kind, FairyError> {
+    register(GameDefinition::read(path.as_ref())?)
+}
+
+/// Looks up a registered game by name
+pub fn find(name: &str) -> Option<Kind> {
+    registry()
+        .read()
+        .unwrap()
+        .iter()
+        .find(|game| game.definition.name == name)
+        .map(|game| Kind::Fairy(FairyKind(game)))
+}
+
+/// Every registered game, in registration order
+pub fn registered() -> Vec<Kind> {
+    registry()
+        .read()
+        .unwrap()
+        .iter()
+        .map(|game| Kind::Fairy(FairyKind(game)))
+        .collect()
+}
+
+/// Returns the built in kind whose name is the same as `name`, ignoring the differences
+/// `Kind::from_str` ignores
+fn builtin_kind(name: &str) -> Option<Kind> {
+    match name.parse() {
+        Ok(Kind::Fairy(_)) | Err(_) => None,
+        Ok(kind) => Some(kind),
+    }
+}
+
+impl FairyGame {
+    fn new(definition: GameDefinition) -> Result<FairyGame, FairyError> {
+        let side_len = definition.side_len;
+        if side_len == 0 || side_len > MAX_SIDE_LEN {
+            return Err(FairyError::InvalidSideLen(side_len));
+        }
+        if definition.pieces.len() > MAX_PIECES {
+            return Err(FairyError::TooManyPieces(definition.pieces.len()));
+        }
+
+        let mut chars: Vec<(PieceKind, char)> = Vec::new();
+        for (i, piece) in definition.pieces.iter().enumerate() {
+            let symbol = piece.symbol;
+            if !symbol.is_ascii_uppercase() || chars.iter().any(|(_, c)| *c == symbol) {
+                return Err(FairyError::InvalidSymbol(symbol));
+            }
+            chars.push((PieceKind::Fairy(i as u8), symbol));
+        }
+        let kind_of = |symbol: char| {
+            chars
+                .iter()
+                .find(|(_, c)| *c == symbol.to_ascii_uppercase())
+                .map(|(kind, _)| *kind)
+                .ok_or(FairyError::UnknownSymbol(symbol))
+        };
+
+        let (promoting, promotion_pieces) = match &definition.promotion {
+            Some(promotion) => (
+                promotion
+                    .pieces
+                    .iter()
+                    .map(|symbol| kind_of(*symbol))
+                    .collect::<Result<Vec<_>, _>>()?,
+                promotion
+                    .to
+                    .iter()
+                    .map(|symbol| kind_of(*symbol))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        let mut pieces = Vec::new();
+        for (i, piece) in definition.pieces.iter().enumerate() {
+            let movements =
+                parse_betza(&piece.betza, side_len).ok_or_else(|| FairyError::InvalidBetza {
+                    piece: piece.name.clone(),
+                    betza: piece.betza.clone(),
+                })?;
+            pieces.push(FairyPiece {
+                royal: piece.royal,
+                promotes: promoting.contains(&PieceKind::Fairy(i as u8)),
+                movements,
+            });
+        }
+        let royals = pieces.iter().filter(|piece| piece.royal).count();
+        let royals_allowed = match definition.win {
+            WinCondition::Checkmate => royals == 1,
+            WinCondition::RoyalCapture => royals >= 1,
+        };
+        if !royals_allowed {
+            return Err(FairyError::RoyalPieces(royals));
+        }
+
+        let board_string = expand_placement(&definition.start, side_len)
+            .ok_or_else(|| FairyError::InvalidStart(definition.start.clone()))?;
+        let mut start = vec![None; (side_len * side_len) as usize];
+        for (row, rank) in board_string.split('/').enumerate() {
+            let rank_index = side_len - 1 - row as u32;
+            for (file, c) in rank.chars().enumerate() {
+                if c != '.' {
+                    let color = if c.is_ascii_uppercase() {
+                        ColorKind::WHITE
+                    } else {
+                        ColorKind::BLACK
+                    };
+                    let square = (rank_index * side_len) as usize + file;
+                    start[square] = Some(Piece::new(kind_of(c)?, color));
+                }
+            }
+        }
+
+        Ok(FairyGame {
+            definition,
+            pieces,
+            chars,
+            board_string,
+            start,
+            promotion_pieces,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    pub fn side_len(&self) -> u32 {
+        self.definition.side_len
+    }
+
+    pub fn definition(&self) -> &GameDefinition {
+        &self.definition
+    }
+
+    pub fn win_condition(&self) -> WinCondition {
+        self.definition.win
+    }
+
+    /// The piece a `PieceKind::Fairy` refers to, if it is part of this game
+    pub fn piece(&self, kind: PieceKind) -> Option<&FairyPiece> {
+        match kind {
+            PieceKind::Fairy(index) => self.pieces.get(index as usize),
+            _ => None,
+        }
+    }
+
+    /// The pieces of this game mapped to the chars that represent them for the first color
+    pub fn piece_chars(&self) -> &[(PieceKind, char)] {
+        &self.chars
+    }
+
+    /// The start position as a board string, see `board::Board::from_board_string`
+    pub fn board_string(&self) -> &str {
+        &self.board_string
+    }
+
+    /// The piece on `square` in the start position
+    pub fn start_piece(&self, square: usize) -> Option<Piece> {
+        self.start.get(square).copied().flatten()
+    }
+
+    /// The pieces a promoting piece may become, in the order they are generated
+    pub fn promotion_pieces(&self) -> &[PieceKind] {
+        &self.promotion_pieces
+    }
+
+    /// The number of ranks at the far side of the board where pieces may promote
+    pub fn promotion_ranks(&self) -> u32 {
+        self.definition
+            .promotion
+            .as_ref()
+            .map_or(0, |promotion| promotion.ranks)
+    }
+}
+
+impl FairyKind {
+    pub fn game(self) -> &'static FairyGame {
+        self.0
+    }
+}
+
+impl PartialEq for FairyKind {
+    fn eq(&self, other: &FairyKind) -> bool {
+        self.0.name() == other.0.name()
+    }
+}
+
+impl Eq for FairyKind {}
+
+impl Hash for FairyKind {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.name().hash(state);
+    }
+}
+
+impl fmt::Debug for FairyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FairyKind").field(&self.0.name()).finish()
+    }
+}
+
+/// Expands the digits of a FEN piece placement into '.'s, checking that there are `side_len`
+/// ranks of `side_len` squares
+fn expand_placement(placement: &str, side_len: u32) -> Option<String> {
+    let side_len = side_len as usize;
+    let mut ranks = Vec::new();
+    for rank in placement.split('/') {
+        let mut expanded = String::new();
+        let mut empty = 0;
+        for c in rank.chars() {
+            if let Some(digit) = c.to_digit(10) {
+                empty = empty * 10 + digit as usize;
+                // Checked before expanding, so a huge count can't overflow or allocate
+                if expanded.len() + empty > side_len {
+                    return None;
+                }
+                continue;
+            }
+            expanded.extend(std::iter::repeat_n('.', empty));
+            empty = 0;
+            if !c.is_ascii_alphabetic() || expanded.len() == side_len {
+                return None;
+            }
+            expanded.push(c);
+        }
+        expanded.extend(std::iter::repeat_n('.', empty));
+        if expanded.len() != side_len {
+            return None;
+        }
+        ranks.push(expanded);
+    }
+    if ranks.len() != side_len {
+        return None;
+    }
+    Some(ranks.join("/"))
+}
+
+/// Parses a piece's movement in Betza notation. See the module documentation for what is
+/// supported. Returns None if the notation is malformed or uses anything unsupported
+pub fn parse_betza(betza: &str, side_len: u32) -> Option<Vec<Movement>> {
+    let atoms: HashMap<char, &[(i32, i32)]> = [
+        ('W', &[(1, 0)][..]),
+        ('F', &[(1, 1)][..]),
+        ('D', &[(2, 0)][..]),
+        ('N', &[(2, 1)][..]),
+        ('A', &[(2, 2)][..]),
+        ('H', &[(3, 0)][..]),
+        ('C', &[(3, 1)][..]),
+        ('Z', &[(3, 2)][..]),
+        ('G', &[(3, 3)][..]),
+        ('K', &[(1, 0), (1, 1)][..]),
+        ('R', &[(1, 0)][..]),
+        ('B', &[(1, 1)][..]),
+        ('Q', &[(1, 0), (1, 1)][..]),
+    ]
+    .iter()
+    .copied()
+    .collect();
+
+    let mut movements = Vec::new();
+    let mut chars = betza.chars().filter(|c| !c.is_whitespace()).peekable();
+    while chars.peek().is_some() {
+        let mut modifiers = String::new();
+        while let Some(c) = chars.next_if(char::is_ascii_lowercase) {
+            modifiers.push(c);
+        }
+        let atom = chars.next()?;
+        let steps = atoms.get(&atom)?;
+
+        let mut range = if matches!(atom, 'R' | 'B' | 'Q') {
+            side_len
+        } else {
+            1
+        };
+        if chars.next_if_eq(&atom).is_some() {
+            range = side_len;
+        }
+        let mut digits = String::new();
+        while let Some(c) = chars.next_if(char::is_ascii_digit) {
+            digits.push(c);
+        }
+        if !digits.is_empty() {
+            range = match digits.parse().ok()? {
+                0 => side_len,
+                n => n,
+            };
+        }
+
+        let mut moves = false;
+        let mut captures = false;
+        let mut initial = false;
+        let mut directions: Vec<Vec<char>> = Vec::new();
+        let mut modifier_chars = modifiers.chars().peekable();
+        while let Some(c) = modifier_chars.next() {
+            match c {
+                'm' => moves = true,
+                'c' => captures = true,
+                'i' => initial = true,
+                'f' | 'b' => match modifier_chars.next_if(|c| matches!(c, 'l' | 'r')) {
+                    Some(side) => directions.push(vec![c, side]),
+                    None => directions.push(vec![c]),
+                },
+                'l' | 'r' | 'v' | 's' => directions.push(vec![c]),
+                _ => return None,
+            }
+        }
+        if !moves && !captures {
+            moves = true;
+            captures = true;
+        }
+
+        let offsets: Vec<(i32, i32)> = steps
+            .iter()
+            .flat_map(|step| symmetric_offsets(*step))
+            .filter(|offset| {
+                directions.is_empty()
+                    || directions
+                        .iter()
+                        .any(|group| group.iter().all(|c| in_direction(*c, *offset)))
+            })
+            .collect();
+        if offsets.is_empty() {
+            return None;
+        }
+        movements.push(Movement {
+            offsets,
+            range,
+            moves,
+            captures,
+            initial,
+        });
+    }
+    if movements.is_empty() {
+        None
+    } else {
+        Some(movements)
+    }
+}
+
+/// The up to eight offsets an atom reaches by reflecting it across both axes and the diagonal
+fn symmetric_offsets((a, b): (i32, i32)) -> Vec<(i32, i32)> {
+    let mut offsets = Vec::new();
+    for (file, rank) in [(a, b), (b, a)].iter() {
+        for (file_sign, rank_sign) in [(1, 1), (-1, 1), (1, -1), (-1, -1)].iter() {
+            let offset = (file * file_sign, rank * rank_sign);
+            if !offsets.contains(&offset) {
+                offsets.push(offset);
+            }
+        }
+    }
+    offsets
+}
+
+/// Returns true if an offset goes in a Betza direction, as seen by the first color
+fn in_direction(direction: char, (file, rank): (i32, i32)) -> bool {
+    match direction {
+        'f' => rank > 0,
+        'b' => rank < 0,
+        'l' => file < 0,
+        'r' => file > 0,
+        'v' => rank.abs() > file.abs(),
+        's' => file.abs() > rank.abs(),
+        _ => false,
+    }
+}
+
+impl fmt::Display for FairyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FairyError::Io(err) => write!(f, "Failed to read game definition: {}", err),
+            FairyError::Parse(err) => write!(f, "Invalid game definition: {}", err),
+            FairyError::UnknownFormat(path) => {
+                write!(f, "{} is not a .toml or .json game definition", path)
+            }
+            FairyError::NameTaken(name) => write!(f, "A game named {} already exists", name),
+            FairyError::InvalidSideLen(side_len) => write!(
+                f,
+                "Boards must have 1 to {} squares along each edge, not {}",
+                MAX_SIDE_LEN, side_len
+            ),
+            FairyError::TooManyPieces(count) => write!(
+                f,
+                "Games may have at most {} piece kinds, not {}",
+                MAX_PIECES, count
+            ),
+            FairyError::InvalidSymbol(c) => write!(f, "Invalid or repeated piece symbol {}", c),
+            FairyError::InvalidBetza { piece, betza } => {
+                write!(f, "Invalid movement {} for {}", betza, piece)
+            }
+            FairyError::UnknownSymbol(c) => write!(f, "No piece has the symbol {}", c),
+            FairyError::InvalidStart(start) => write!(f, "Invalid start position {}", start),
+            FairyError::RoyalPieces(count) => {
+                write!(f, "Wrong number of royal piece kinds: {}", count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FairyError {}
+
+/// A six by six chess game without bishops, shared by the tests of fairy games and their boards
+#[cfg(test)]
+pub(crate) const MINI_CHESS: &str = r#"
+name = "Mini Chess (test)"
+side_len = 6
+start = "rnqknr/pppppp/6/6/PPPPPP/RNQKNR"
+
+[[pieces]]
+name = "King"
+symbol = "K"
+betza = "K"
+royal = true
+
+[[pieces]]
+name = "Queen"
+symbol = "Q"
+betza = "Q"
+
+[[pieces]]
+name = "Rook"
+symbol = "R"
+betza = "R"
+
+[[pieces]]
+name = "Knight"
+symbol = "N"
+betza = "N"
+
+[[pieces]]
+name = "Pawn"
+symbol = "P"
+betza = "fmWfcFifmW2"
+
+[promotion]
+pieces = ["P"]
+to = ["Q", "R", "N"]
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offsets(betza: &str) -> Vec<(i32, i32)> {
+        let mut offsets: Vec<_> = parse_betza(betza, 8)
+            .unwrap()
+            .into_iter()
+            .flat_map(|movement| movement.offsets)
+            .collect();
+        offsets.sort();
+        offsets
+    }
+
+    #[test]
+    fn betza() {
+        assert_eq!(offsets("N").len(), 8);
+        assert_eq!(offsets("K").len(), 8);
+        assert_eq!(offsets("fW"), [(0, 1)]);
+        assert_eq!(offsets("fF"), [(-1, 1), (1, 1)]);
+        assert_eq!(offsets("flF"), [(-1, 1)]);
+        assert_eq!(offsets("sW"), [(-1, 0), (1, 0)]);
+        assert_eq!(offsets("fN"), [(-2, 1), (-1, 2), (1, 2), (2, 1)]);
+
+        let pawn = parse_betza("fmWfcFifmW2", 8).unwrap();
+        assert_eq!(pawn.len(), 3);
+        assert!(pawn[0].moves && !pawn[0].captures);
+        assert!(!pawn[1].moves && pawn[1].captures);
+        assert!(pawn[2].initial);
+        assert_eq!(pawn[2].range, 2);
+
+        assert_eq!(parse_betza("R", 8).unwrap()[0].range, 8);
+        assert_eq!(parse_betza("WW", 8).unwrap()[0].range, 8);
+        assert_eq!(parse_betza("B4", 8).unwrap()[0].range, 4);
+        assert_eq!(parse_betza("N0", 8).unwrap()[0].range, 8);
+
+        assert_eq!(parse_betza("", 8), None);
+        assert_eq!(parse_betza("X", 8), None);
+        assert_eq!(parse_betza("pR", 8), None);
+        assert_eq!(parse_betza("fm", 8), None);
+    }
+
+    #[test]
+    fn definition_formats() {
+        let toml = GameDefinition::from_toml(MINI_CHESS).unwrap();
+        let json = GameDefinition::from_json(&serde_json::to_string(&toml).unwrap()).unwrap();
+        assert_eq!(toml, json);
+        assert_eq!(toml.win, WinCondition::Checkmate);
+        assert_eq!(toml.promotion.unwrap().ranks, 1);
+    }
+
+    #[test]
+    fn registration() {
+        let definition = GameDefinition::from_toml(MINI_CHESS).unwrap();
+        let kind = register(definition.clone()).unwrap();
+        assert_eq!(register(definition.clone()).unwrap(), kind);
+        assert_eq!(find("Mini Chess (test)"), Some(kind));
+        assert!(registered().contains(&kind));
+        assert_eq!(kind.side_len(), 6);
+
+        let changed = GameDefinition {
+            side_len: 8,
+            ..definition.clone()
+        };
+        assert!(matches!(register(changed), Err(FairyError::NameTaken(_))));
+        let builtin = GameDefinition {
+            name: "contrasting chess".to_owned(),
+            ..definition
+        };
+        assert!(matches!(register(builtin), Err(FairyError::NameTaken(_))));
+    }
+
+    #[test]
+    fn invalid_definitions() {
+        let definition = GameDefinition::from_toml(MINI_CHESS).unwrap();
+        let invalid = |change: &dyn Fn(&mut GameDefinition)| {
+            let mut definition = definition.clone();
+            change(&mut definition);
+            FairyGame::new(definition).unwrap_err()
+        };
+        assert!(matches!(
+            invalid(&|d| d.start = "rnqknr/pppppp/6/6/PPPPPP".to_owned()),
+            FairyError::InvalidStart(_)
+        ));
+        assert!(matches!(
+            invalid(&|d| d.start = "rnqknr/pppppp/4000000000/6/PPPPPP/RNQKNR".to_owned()),
+            FairyError::InvalidStart(_)
+        ));
+        assert!(matches!(
+            invalid(&|d| d.start = "rnqknr/pppppp/99999999999999/6/PPPPPP/RNQKNR".to_owned()),
+            FairyError::InvalidStart(_)
+        ));
+        assert!(matches!(
+            invalid(&|d| d.start = "rnqkbr/pppppp/6/6/PPPPPP/RNQKNR".to_owned()),
+            FairyError::UnknownSymbol('b')
+        ));
+        assert!(matches!(
+            invalid(&|d| d.pieces[1].betza = "Qx".to_owned()),
+            FairyError::InvalidBetza { .. }
+        ));
+        assert!(matches!(
+            invalid(&|d| d.pieces[1].royal = true),
+            FairyError::RoyalPieces(2)
+        ));
+        assert!(matches!(
+            invalid(&|d| d.pieces[1].symbol = 'K'),
+            FairyError::InvalidSymbol('K')
+        ));
+        assert!(matches!(
+            invalid(&|d| d.side_len = 0),
+            FairyError::InvalidSideLen(0)
+        ));
+    }
+}
@@ -1,51 +1,100 @@
 use chrono::prelude::*;
 use smallvec::SmallVec;
 
-use serde::{Deserialize, Serialize};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
 
-/// Stores a square on the board. Generic over all game kinds
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash)]
+use crate::board::PieceKind;
+use crate::fairy::{self, FairyKind};
+
+/// Stores a square on the board. Generic over all game kinds.
+/// Squares are numbered from the bottom left corner of the board (a1 in chess) going across each
+/// rank, so index = rank * side_len + file
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct RawSquarePosition(u32);
 
 /// A basic move, generic over all game kinds
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct RawMove {
     pub src: RawSquarePosition,
     pub dst: RawSquarePosition,
+
+    /// The piece a pawn becomes when it reaches the last rank. Required for promotions, and must
+    /// be None for every other move
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promotion: Option<PieceKind>,
+
+    /// Extra information for moves that can't be described by moving a piece from src to dst.
+    /// None for regular moves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extra: Option<MoveExtra>,
+}
+
+/// Additional move information for game kinds with moves other than moving a piece from one square
+/// to another
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type")]
+#[non_exhaustive]
+pub enum MoveExtra {
+    /// A piece from the player's hand is placed on dst. src is ignored
+    Drop { piece: PieceKind },
+
+    /// A game defined special move. The meaning of id depends on the game kind
+    Special { id: u32 },
 }
 
 /// The identifier for a particular color. Values are game kind dependent but must be sequential
 /// starting from 0 in move order. For example, in chess white is id 0, and black is is 1.
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+/// Serialized as the bare id. Ids written as strings are also accepted when deserializing, as
+/// that is how json object keys are written
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(transparent)]
 pub struct ColorKind(u32);
 
 /// A game's unique identifier. Never re-used within the same execution of this library
 pub type ID = u64;
 
-/// The kind of game. Currently only chess is supported hoverer more kinds may be added in the
-/// future (eg. Contrasting chess (10x10 chess like game with different pieces), additive chess,
-/// etc.)
+/// The kind of game. More kinds may be added in the future (eg. additive chess), and fairy games
+/// can be defined at runtime.
 /// Games determine the size of the board, the pieces used, and the moves that govern the game and
-/// piece movement
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+/// piece movement.
+/// Serialized as the name of the kind: "Chess", "ContrastingChess", or the name of a fairy game.
+/// Fairy games have to be registered before they can be deserialized
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[non_exhaustive]
 pub enum Kind {
     Chess,
+
+    /// A 10x10 chess like game where each side has a king, elephants, bears, horses, dragons,
+    /// moose and a row of weasels. See the `board::contrasting` module for the rules
+    ContrastingChess,
+
+    /// A game described by a definition registered at runtime. See the `fairy` module
+    Fairy(FairyKind),
 }
 
+/// A game kind name that isn't known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKind(pub String);
+
 /// Variants are small changes to a base chess game. Variant cannot change the pieces used, or the
 /// size of the board. However, they can change the starting position and the rules.
 /// Not all Variants are supported by a game type (for example using Chess960 with ContrastingChess
 /// makes no sense and is not supported)
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[non_exhaustive]
 pub enum Variant {
+    /// The game starts from one of the 960 shuffled start positions, see `board::Board::chess960`
     Chess960,
 
+    /// Neither color may ever castle
     NoCastling,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum GameEndCause {
     /// The king of the player to move is in check and has no legal moves
     Checkmate,
@@ -53,9 +102,25 @@ pub enum GameEndCause {
     /// The king of the player to move is not in check but has no legal moves
     Stalemate,
 
+    /// The player to move has no royal pieces left, in a fairy game won by capturing them
+    RoyalCaptured,
+
     /// Insufficient material for the game to have a decisive ending. Different from stalemate
     DeadPosition,
 
+    /// The player to move claimed a draw after 50 moves by each color without a capture or pawn
+    /// move
+    FiftyMoveRule,
+
+    /// 75 moves were made by each color without a capture or pawn move
+    SeventyFiveMoveRule,
+
+    /// The player to move claimed a draw because the position occurred for the third time
+    ThreefoldRepetition,
+
+    /// The same position occurred for the fifth time
+    FivefoldRepetition,
+
     /// The players agreed to a draw
     DrawOffer,
 
@@ -70,13 +135,13 @@ pub enum GameEndCause {
 }
 
 /// The clocks for all players in the game
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Clocks {
     /// The clocks of the player's participating in the game
     data: SmallVec<[Clock; 2]>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum TimeFormat {
     Timed {
         /// The initial time a player gets on their clock in nanoseconds
@@ -87,13 +152,40 @@ pub enum TimeFormat {
         /// of each move
         delay_nanos: u64,
     },
+    /// Every move has to be made within the same fixed time. Time not used on one move doesn't
+    /// carry over to the next
+    PerMove {
+        nanos_per_move: u64,
+    },
+    /// A classical time control made of consecutive periods, such as 40 moves in 90 minutes
+    /// followed by 30 minutes for the rest of the game. The time of each period is added to the
+    /// clock once the moves of the previous period have been made
+    Periods {
+        periods: Vec<TimePeriod>,
+    },
     Unlimited,
 }
 
+/// One period of `TimeFormat::Periods`
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimePeriod {
+    /// The number of moves that have to be made in this period, or None if it lasts for the rest
+    /// of the game. If the last period has a number of moves it repeats for as long as the game
+    /// goes on
+    pub moves: Option<u32>,
+    /// The nanoseconds added to the clock when the period begins
+    pub nanos: u64,
+    /// The number of nanoseconds added to a player's clock each move made in this period
+    pub increment_nanos: u64,
+    /// The number of nanoseconds a player's clock is delayed from ticking down at the beginning
+    /// of each move made in this period
+    pub delay_nanos: u64,
+}
+
 /// The clock of a given player. Really just the points in time they made a move. The time of move
 /// one is in index 0, move 5 is in index 4, etc. Moves that have not yet been made are indicated by
 /// the end of the Vec
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Clock {
     pub times: Vec<DateTime<Utc>>,
     pub time_format: TimeFormat,
@@ -102,19 +194,316 @@ pub struct Clock {
     pub nanos_on_clock: Option<u64>,
 }
 
+impl RawSquarePosition {
+    pub fn new(index: u32) -> RawSquarePosition {
+        RawSquarePosition(index)
+    }
+
+    /// The index of this square into a board of its game kind
+    pub fn index(&self) -> u32 {
+        self.0
+    }
+}
+
+impl RawMove {
+    pub fn new(src: RawSquarePosition, dst: RawSquarePosition) -> RawMove {
+        RawMove {
+            src,
+            dst,
+            promotion: None,
+            extra: None,
+        }
+    }
+
+    /// Creates a move that promotes a pawn
+    pub fn with_promotion(
+        src: RawSquarePosition,
+        dst: RawSquarePosition,
+        promotion: PieceKind,
+    ) -> RawMove {
+        RawMove {
+            promotion: Some(promotion),
+            ..RawMove::new(src, dst)
+        }
+    }
+}
+
+impl ColorKind {
+    /// The first player to move. White in chess
+    pub const WHITE: ColorKind = ColorKind(0);
+
+    /// The second player to move. Black in chess
+    pub const BLACK: ColorKind = ColorKind(1);
+
+    pub fn new(id: u32) -> ColorKind {
+        ColorKind(id)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for ColorKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ColorKind, D::Error> {
+        struct IdVisitor;
+
+        impl<'de> Visitor<'de> for IdVisitor {
+            type Value = ColorKind;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a color id")
+            }
+
+            fn visit_u64<E: de::Error>(self, id: u64) -> Result<ColorKind, E> {
+                u32::try_from(id)
+                    .map(ColorKind)
+                    .map_err(|_| E::custom(format!("color id {} is too large", id)))
+            }
+
+            fn visit_str<E: de::Error>(self, id: &str) -> Result<ColorKind, E> {
+                id.parse()
+                    .map(ColorKind)
+                    .map_err(|_| E::custom(format!("invalid color id '{}'", id)))
+            }
+        }
+
+        deserializer.deserialize_any(IdVisitor)
+    }
+}
+
 impl Clocks {
+    /// Creates the clocks for a game. `data` is indexed by color id
+    pub fn new(data: SmallVec<[Clock; 2]>) -> Clocks {
+        Clocks { data }
+    }
+
     pub fn get_clock(&self, player: ColorKind) -> Option<&Clock> {
         self.data.get(player.0 as usize)
     }
+
+    pub fn get_clock_mut(&mut self, player: ColorKind) -> Option<&mut Clock> {
+        self.data.get_mut(player.0 as usize)
+    }
+}
+
+impl Clock {
+    /// Creates a clock for a player that hasn't moved yet
+    pub fn new(time_format: TimeFormat) -> Clock {
+        let nanos_on_clock = time_format.initial_nanos();
+        Clock {
+            times: Vec::new(),
+            time_format,
+            nanos_on_clock,
+        }
+    }
+}
+
+impl TimeFormat {
+    /// The nanoseconds on a player's clock before their first move. None if the time is unlimited
+    pub fn initial_nanos(&self) -> Option<u64> {
+        match self {
+            TimeFormat::Timed { initial_nanos, .. } => Some(*initial_nanos),
+            TimeFormat::PerMove { nanos_per_move } => Some(*nanos_per_move),
+            TimeFormat::Periods { periods } => Some(periods.first().map_or(0, |first| first.nanos)),
+            TimeFormat::Unlimited => None,
+        }
+    }
+
+    /// The nanoseconds added to a player's clock after their `move_number`th move, not counting
+    /// the time of any period that begins afterwards. Moves are counted from 1
+    pub fn increment_nanos(&self, move_number: u32) -> u64 {
+        match self {
+            TimeFormat::Timed {
+                increment_nanos, ..
+            } => *increment_nanos,
+            TimeFormat::Periods { periods } => {
+                period_of(periods, move_number).map_or(0, |(period, _)| period.increment_nanos)
+            }
+            TimeFormat::PerMove { .. } | TimeFormat::Unlimited => 0,
+        }
+    }
+
+    /// The nanoseconds a player's clock is delayed from ticking down at the beginning of their
+    /// `move_number`th move
+    pub fn delay_nanos(&self, move_number: u32) -> u64 {
+        match self {
+            TimeFormat::Timed { delay_nanos, .. } => *delay_nanos,
+            TimeFormat::Periods { periods } => {
+                period_of(periods, move_number).map_or(0, |(period, _)| period.delay_nanos)
+            }
+            TimeFormat::PerMove { .. } | TimeFormat::Unlimited => 0,
+        }
+    }
+
+    /// The number of moves a player has to make, counting their `move_number`th move, before time
+    /// is next added to their clock at the end of a period. None if no more time will be added
+    /// that way
+    pub fn moves_to_go(&self, move_number: u32) -> Option<u32> {
+        match self {
+            TimeFormat::PerMove { .. } => Some(1),
+            TimeFormat::Periods { periods } => {
+                let (_, last_move) = period_of(periods, move_number)?;
+                Some(last_move? + 1 - move_number)
+            }
+            TimeFormat::Timed { .. } | TimeFormat::Unlimited => None,
+        }
+    }
+
+    /// The nanoseconds on a player's clock after their `move_number`th move, given the time left
+    /// once that move was charged
+    pub fn after_move(&self, remaining_nanos: u64, move_number: u32) -> u64 {
+        match self {
+            TimeFormat::PerMove { nanos_per_move } => *nanos_per_move,
+            TimeFormat::Periods { periods } => {
+                let mut after = remaining_nanos + self.increment_nanos(move_number);
+                if let Some((_, Some(last_move))) = period_of(periods, move_number) {
+                    if last_move == move_number {
+                        if let Some((next, _)) = period_of(periods, move_number + 1) {
+                            after += next.nanos;
+                        }
+                    }
+                }
+                after
+            }
+            TimeFormat::Timed { .. } | TimeFormat::Unlimited => {
+                remaining_nanos + self.increment_nanos(move_number)
+            }
+        }
+    }
+}
+
+/// The period in effect for a player's `move_number`th move, along with the number of the last
+/// move of that period. The last move is None for a period lasting the rest of the game
+fn period_of(periods: &[TimePeriod], move_number: u32) -> Option<(&TimePeriod, Option<u32>)> {
+    let mut last_move = 0;
+    for period in periods {
+        match period.moves {
+            Some(moves) => {
+                last_move += moves;
+                if move_number <= last_move {
+                    return Some((period, Some(last_move)));
+                }
+            }
+            None => return Some((period, None)),
+        }
+    }
+    // Every period has a number of moves, so the last one repeats
+    let last = periods.last()?;
+    let moves = last.moves.unwrap_or(1).max(1);
+    let repeats = (move_number - last_move).div_ceil(moves);
+    Some((last, Some(last_move + repeats * moves)))
 }
 
 impl Kind {
+    /// The number of squares along one edge of the board. Boards are always square
+    pub fn side_len(&self) -> u32 {
+        match *self {
+            Kind::Chess => 8,
+            Kind::ContrastingChess => 10,
+            Kind::Fairy(fairy) => fairy.game().side_len(),
+        }
+    }
+
+    /// The number of players (and therefore colors) that take part in a game of this kind
+    pub fn color_count(&self) -> u32 {
+        match *self {
+            Kind::Chess | Kind::ContrastingChess | Kind::Fairy(_) => 2,
+        }
+    }
+
+    /// Returns true if games of this kind can be played with `variant`
     pub fn supports_variant(&self, variant: &Variant) -> bool {
         match *self {
             Kind::Chess => match *variant {
                 Variant::Chess960 => true,
                 Variant::NoCastling => true,
             },
+            // Neither has castling, so games are always without it, and shuffling the pieces isn't
+            // part of either
+            Kind::ContrastingChess | Kind::Fairy(_) => *variant == Variant::NoCastling,
+        }
+    }
+
+    /// Returns true if games of this kind can be played with all of `variants` at once. Each
+    /// variant may only be given once
+    pub fn supports_variants(&self, variants: &[Variant]) -> bool {
+        variants
+            .iter()
+            .enumerate()
+            .all(|(i, variant)| self.supports_variant(variant) && !variants[..i].contains(variant))
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Chess => "Chess",
+            Kind::ContrastingChess => "Contrasting Chess",
+            Kind::Fairy(fairy) => fairy.game().name(),
+        })
+    }
+}
+
+impl FromStr for Kind {
+    type Err = UnknownKind;
+
+    /// Parses the name `Display` writes. Built in kinds ignore case, spaces, dashes and
+    /// underscores, so that "contrasting-chess" is accepted on a command line. Fairy games have to
+    /// be registered, and are looked up by their exact name
+    fn from_str(s: &str) -> Result<Kind, UnknownKind> {
+        let name: String = s
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        match name.as_str() {
+            "chess" => Ok(Kind::Chess),
+            "contrastingchess" => Ok(Kind::ContrastingChess),
+            _ => fairy::find(s).ok_or_else(|| UnknownKind(s.to_owned())),
         }
     }
 }
+
+impl Serialize for Kind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Kind::Chess => serializer.serialize_str("Chess"),
+            Kind::ContrastingChess => serializer.serialize_str("ContrastingChess"),
+            Kind::Fairy(fairy) => serializer.serialize_str(fairy.game().name()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Kind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Kind, D::Error> {
+        struct NameVisitor;
+
+        impl<'de> Visitor<'de> for NameVisitor {
+            type Value = Kind;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a game kind name")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Kind, E> {
+                match name {
+                    "Chess" => Ok(Kind::Chess),
+                    "ContrastingChess" => Ok(Kind::ContrastingChess),
+                    _ => fairy::find(name)
+                        .ok_or_else(|| E::custom(format!("unknown game kind '{}'", name))),
+                }
+            }
+        }
+
+        deserializer.deserialize_str(NameVisitor)
+    }
+}
+
+impl fmt::Display for UnknownKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown game kind: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownKind {}
@@ -0,0 +1,133 @@
+//! The positions a game has passed through. Some draws depend on more than the current position:
+//! a position occurring for the fifth time ends the game, and one occurring for the third time, or
+//! 50 moves by each color without a capture or pawn move, let the player to move claim a draw
+
+use crate::board::Board;
+use crate::game::GameEndCause;
+
+/// The number of times a position has to occur before the player to move may claim a draw
+pub const CLAIMABLE_REPETITIONS: usize = 3;
+
+/// The number of times a position has to occur for the game to be drawn without a claim
+pub const AUTOMATIC_REPETITIONS: usize = 5;
+
+/// The halfmove clock at which the player to move may claim a draw
+pub const FIFTY_MOVE_HALFMOVES: u32 = 100;
+
+/// The Zobrist keys of every position of a game
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PositionHistory {
+    /// The key of every position since the start of the game, the current position last
+    keys: Vec<u64>,
+    /// The index into `keys` of the first position after the last capture or pawn move. Earlier
+    /// positions had different material or pawns, so they can't be repeated
+    reversible_from: usize,
+}
+
+impl PositionHistory {
+    /// Starts the history of a game that begins at `start`
+    pub fn new(start: &Board) -> PositionHistory {
+        PositionHistory {
+            keys: vec![start.zobrist_key()],
+            reversible_from: 0,
+        }
+    }
+
+    /// Records the position after a move has been applied
+    pub fn push(&mut self, board: &Board) {
+        if board.halfmove_clock() == 0 {
+            self.reversible_from = self.keys.len();
+        }
+        self.keys.push(board.zobrist_key());
+    }
+
+    /// The key of the current position
+    pub fn key(&self) -> Option<u64> {
+        self.keys.last().copied()
+    }
+
+    /// The number of times the current position has occurred, including now
+    pub fn repetitions(&self) -> usize {
+        let current = match self.key() {
+            Some(current) => current,
+            None => return 0,
+        };
+        self.keys[self.reversible_from..]
+            .iter()
+            .filter(|key| **key == current)
+            .count()
+    }
+
+    /// Returns why the game is over at `board`, the current position, including the draws that
+    /// don't have to be claimed
+    pub fn game_end(&self, board: &Board) -> Option<GameEndCause> {
+        board.game_end().or_else(|| {
+            if self.repetitions() >= AUTOMATIC_REPETITIONS {
+                Some(GameEndCause::FivefoldRepetition)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the draw the player to move may claim at `board`, the current position, if any
+    pub fn claimable_draw(&self, board: &Board) -> Option<GameEndCause> {
+        if self.repetitions() >= CLAIMABLE_REPETITIONS {
+            Some(GameEndCause::ThreefoldRepetition)
+        } else if board.halfmove_clock() >= FIFTY_MOVE_HALFMOVES {
+            Some(GameEndCause::FiftyMoveRule)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Kind;
+
+    fn play(board: &mut Board, history: &mut PositionHistory, moves: &[&str]) {
+        for uci in moves {
+            board.apply_move(board.parse_uci(uci).unwrap());
+            history.push(board);
+        }
+    }
+
+    #[test]
+    fn repetitions() {
+        let mut board = Board::start_position(Kind::Chess);
+        let mut history = PositionHistory::new(&board);
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        play(&mut board, &mut history, &shuffle);
+        assert_eq!(history.repetitions(), 2);
+        assert_eq!(history.claimable_draw(&board), None);
+        play(&mut board, &mut history, &shuffle);
+        assert_eq!(
+            history.claimable_draw(&board),
+            Some(GameEndCause::ThreefoldRepetition)
+        );
+        assert_eq!(history.game_end(&board), None);
+        play(&mut board, &mut history, &shuffle);
+        play(&mut board, &mut history, &shuffle);
+        assert_eq!(
+            history.game_end(&board),
+            Some(GameEndCause::FivefoldRepetition)
+        );
+
+        // A pawn move means nothing before it can be repeated
+        play(&mut board, &mut history, &["e2e4"]);
+        assert_eq!(history.repetitions(), 1);
+    }
+
+    #[test]
+    fn fifty_move_rule() {
+        let board = Board::from_fen(Kind::Chess, "8/8/4k3/8/8/3K4/3R4/8 w - - 100 80").unwrap();
+        let history = PositionHistory::new(&board);
+        assert_eq!(
+            history.claimable_draw(&board),
+            Some(GameEndCause::FiftyMoveRule)
+        );
+        assert_eq!(history.game_end(&board), None);
+    }
+}
@@ -1,3 +1,7 @@
 pub mod board;
+pub mod fairy;
 pub mod game;
+pub mod history;
 pub mod message;
+pub mod notation;
+pub mod pgn;
@@ -1,3 +1,8 @@
+//! The messages exchanged between the moderator and engines.
+//! `In` and `Out` are sent over the engine's stdin and stdout, and `GameIn` and `GameOut` over
+//! the game socket of each game. Every stream uses the newline delimited json framing described
+//! in `framing`, and implemented by `Encoder`, `Decoder` and `MessageReader`
+
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -6,6 +11,18 @@ use smallvec::SmallVec;
 
 use crate::game;
 
+pub mod framing;
+
+pub use self::framing::{encode, Decoder, Encoder, FrameError, MessageReader, MAX_MESSAGE_LEN};
+
+/// The version of the protocol described by this module. Increased whenever a change is made that
+/// an implementation of an older version would mis-parse
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The version spoken by engines that don't advertise any versions, which predate version
+/// negotiation
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
 /// The kinds of messages that are sent by the moderator to the engine
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -14,18 +31,43 @@ pub enum In {
     /// that will be received.
     /// When this message is received by an engine, it must send a EngineInfo message back to the
     /// moderator to inform the moderator about itself
-    EngineInit,
+    EngineInit {
+        /// The protocol versions the moderator can speak
+        #[serde(default)]
+        protocol_versions: Vec<u32>,
+
+        /// The optional features the moderator supports
+        #[serde(default)]
+        features: Vec<Feature>,
+    },
+
+    /// Sent in response to EngineInfo with the protocol version and features that will be used
+    /// from now on. The version is the newest version both sides support, and the features are
+    /// the ones both sides support.
+    /// If there is no version in common, the moderator instead sends an InvalidRequest and shuts
+    /// the engine down
+    ProtocolAccepted { protocol: Protocol },
 
     /// Indicates that a game is beginning
     GameStart {
         variant: game::Kind,
-        /// Contains the game defined piece chars. Always a square
+
+        /// The variants of the game kind in play, which are always among the ones the engine listed
+        /// for the kind in `Out::EngineInfo`. Empty for the stock game. `board` already follows
+        /// the variants, for example it has no castling rights with `game::Variant::NoCastling`
+        #[serde(default)]
+        variants: SmallVec<[game::Variant; 2]>,
+
+        /// The position the game starts from as a FEN string, using the game defined piece chars.
+        /// Castling rights use X-FEN, so Chess960 positions can be described. Usually this is the
+        /// start position of the game kind, however games may be started from any position.
+        /// See `board::Board::from_fen`
         board: String,
 
         /// The path to a unix socket that the moderator listens on for traffic related to this game.
         /// Once the engine establishes a connection to this path, this socket is called the
-        /// game socket, and all future communication about this game will happen there, using json
-        /// serialized `GameIn` and `GameOut` messages
+        /// game socket, and all future communication about this game will happen there, using
+        /// framed `GameIn` and `GameOut` messages
         game_listen_path: String,
 
         /// A unique identifier for the game. Never re-used within the same execution of the
@@ -75,15 +117,15 @@ pub enum In {
 }
 
 /// Contains information about an engine
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
 pub struct EngineInfo {
-    name: String,
-    version: String,
-    description: String,
+    pub name: String,
+    pub version: String,
+    pub description: String,
     /// Author and email in the format Name <Email>. Ie: "Troy Neubauer <troyneubauer@gmail.com>"
-    author: String,
+    pub author: String,
     /// Link to repository containing the code for this engine
-    repo: String,
+    pub repo: String,
 }
 
 /// The kinds of messages that are sent from the engine to the moderator
@@ -97,9 +139,91 @@ pub enum Out {
         /// A mapping between a game type and an empty variant list indicates that the stock
         /// version of this game is supported, but no variants are supported for that game
         supported_games: HashMap<game::Kind, SmallVec<[game::Variant; 2]>>,
+
+        /// The protocol versions this engine can speak. Engines that leave this out are assumed to
+        /// speak `LEGACY_PROTOCOL_VERSION`
+        #[serde(default = "legacy_protocol_versions")]
+        protocol_versions: Vec<u32>,
+
+        /// The optional features this engine supports. Engines that leave this out are assumed to
+        /// support the features that were part of `LEGACY_PROTOCOL_VERSION`
+        #[serde(default = "legacy_features")]
+        features: Vec<Feature>,
     },
 }
 
+/// Optional parts of the protocol, which are only used if both the moderator and the engine
+/// support them
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Feature {
+    /// The engine may think while it is the opponent's move
+    Pondering,
+
+    /// The engine may report what it is thinking about during a search
+    AnalysisOutput,
+
+    /// The engine receives OpponentDrawOffer messages, and may send DrawOffer and
+    /// RejectDrawOffer
+    DrawOffers,
+
+    /// A feature added in a newer version of the protocol. Never used
+    #[serde(other)]
+    Unknown,
+}
+
+/// The protocol version and features that a moderator and engine agreed on
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Protocol {
+    pub version: u32,
+    pub features: Vec<Feature>,
+}
+
+impl Protocol {
+    /// Chooses the newest version both sides support, along with the features both sides support.
+    /// None if there is no version in common
+    pub fn negotiate(
+        versions: &[u32],
+        features: &[Feature],
+        other_versions: &[u32],
+        other_features: &[Feature],
+    ) -> Option<Protocol> {
+        let version = versions
+            .iter()
+            .filter(|version| other_versions.contains(version))
+            .max()?;
+        let features = features
+            .iter()
+            .filter(|feature| **feature != Feature::Unknown && other_features.contains(feature))
+            .copied()
+            .collect();
+        Some(Protocol {
+            version: *version,
+            features,
+        })
+    }
+
+    /// The protocol spoken with engines that don't negotiate
+    pub fn legacy() -> Protocol {
+        Protocol {
+            version: LEGACY_PROTOCOL_VERSION,
+            features: legacy_features(),
+        }
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+fn legacy_protocol_versions() -> Vec<u32> {
+    vec![LEGACY_PROTOCOL_VERSION]
+}
+
+fn legacy_features() -> Vec<Feature> {
+    vec![Feature::DrawOffers]
+}
+
 /// Messages from the moderator to the engine about a particular game
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -125,7 +249,8 @@ pub enum GameIn {
     /// performed during this step take < 1 microsecond. It is still important that the moderator
     /// functions in this way to keep fast games fair.
     YourMove {
-        /// The instant this engine will flag
+        /// The instant this engine will flag. Players with unlimited time are sent the latest
+        /// representable instant
         flag_instant: DateTime<Utc>,
     },
     /// An opponent offers a draw. This engine can either ignore the offer, reject it by sending
@@ -153,7 +278,11 @@ pub enum GameOut {
 
     /// This engine wants to send a draw offer to the other player. If all players send draw
     /// offers, the game ends in a draw. If no other players have send a draw offer on this move,
-    /// then this initiates a draw offer to all players
+    /// then this initiates a draw offer to all players.
+    /// An offer stands until another player makes a move, so an engine may offer a draw and then
+    /// move.
+    /// If it is this engine's move and the position allows a draw to be claimed, by threefold
+    /// repetition or the fifty move rule, the offer claims it and the game ends in a draw
     DrawOffer,
 
     /// This engine rejects the pending draw offer from another player.
@@ -167,7 +296,10 @@ pub enum GameOut {
     /// This engine wishes to move a piece from src to dst.
     /// If the move is valid: then is processed by the moderator and the opponent
     ///   receives the valid move.
-    /// If move is non valid, (contains invalid squares, or is illegal):
+    /// Pawn moves to the last rank must set `promotion` to the piece the pawn becomes, and all
+    /// other moves must leave it unset.
+    /// If move is non valid, (contains invalid squares, is missing or has an invalid promotion,
+    /// or is illegal):
     ///   The game is ended, this engine looses, and a game over message is sent to all players
     Move(game::RawMove),
 
@@ -179,3 +311,147 @@ pub enum GameOut {
     ///   Any other logical invariant preventing the engine from ever making a move
     Err { message: String },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::PieceKind;
+    use crate::game::{
+        ColorKind, Kind, MoveExtra, RawMove, RawSquarePosition, TimeFormat, Variant,
+    };
+
+    fn promotion() -> RawMove {
+        RawMove::with_promotion(
+            RawSquarePosition::new(52),
+            RawSquarePosition::new(60),
+            PieceKind::Knight,
+        )
+    }
+
+    #[test]
+    fn move_json() {
+        let json = serde_json::to_string(&GameOut::Move(promotion())).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"Move","src":52,"dst":60,"promotion":"Knight"}"#
+        );
+        match serde_json::from_str(&json).unwrap() {
+            GameOut::Move(m) => assert_eq!(m, promotion()),
+            _ => panic!("Expected a move"),
+        }
+
+        // Moves without a promotion don't need to include the field
+        let json = r#"{"type":"Move","src":12,"dst":28}"#;
+        match serde_json::from_str(json).unwrap() {
+            GameOut::Move(m) => assert_eq!(
+                m,
+                RawMove::new(RawSquarePosition::new(12), RawSquarePosition::new(28))
+            ),
+            _ => panic!("Expected a move"),
+        }
+    }
+
+    #[test]
+    fn opponent_move_json() {
+        let mut drop = RawMove::new(RawSquarePosition::new(0), RawSquarePosition::new(20));
+        drop.extra = Some(MoveExtra::Drop {
+            piece: PieceKind::Pawn,
+        });
+        for m in [promotion(), drop].iter() {
+            let message = GameIn::OpponentMove {
+                opponent_move: *m,
+                opponent: ColorKind::BLACK,
+            };
+            let json = serde_json::to_string(&message).unwrap();
+            match serde_json::from_str(&json).unwrap() {
+                GameIn::OpponentMove {
+                    opponent_move,
+                    opponent,
+                } => {
+                    assert_eq!(opponent_move, *m);
+                    assert_eq!(opponent, ColorKind::BLACK);
+                }
+                _ => panic!("Expected an opponent move"),
+            }
+        }
+    }
+
+    #[test]
+    fn game_start_json() {
+        let time_format = TimeFormat::Unlimited;
+        let mut opponents = HashMap::new();
+        opponents.insert(
+            ColorKind::BLACK,
+            (EngineInfo::default(), time_format.clone()),
+        );
+        let message = In::GameStart {
+            variant: Kind::Chess,
+            variants: [Variant::NoCastling].iter().copied().collect(),
+            board: "8/8/8/8/8/8/8/K6k w - - 0 1".to_owned(),
+            game_listen_path: "/tmp/game.sock".to_owned(),
+            game_id: 3,
+            playing_as: ColorKind::WHITE,
+            time_format,
+            opponents,
+        };
+        let json = serde_json::to_string(&message).unwrap();
+        match serde_json::from_str(&json).unwrap() {
+            In::GameStart {
+                variants,
+                opponents,
+                ..
+            } => {
+                assert_eq!(variants.as_slice(), [Variant::NoCastling]);
+                assert!(opponents.contains_key(&ColorKind::BLACK))
+            }
+            _ => panic!("Expected a game start"),
+        }
+    }
+
+    #[test]
+    fn negotiation() {
+        // An engine from before versions were negotiated
+        let json = r#"{"type":"EngineInfo","info":{"name":"Old","version":"","description":"","author":"","repo":""},"supported_games":{"Chess":[]}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Out::EngineInfo {
+                protocol_versions,
+                features,
+                ..
+            } => {
+                let protocol = Protocol::negotiate(
+                    &[LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION],
+                    &[Feature::DrawOffers, Feature::Pondering],
+                    &protocol_versions,
+                    &features,
+                );
+                assert_eq!(protocol, Some(Protocol::legacy()));
+            }
+        }
+
+        let features: Vec<Feature> =
+            serde_json::from_str(r#"["Pondering","Telepathy","DrawOffers"]"#).unwrap();
+        assert_eq!(
+            features,
+            vec![Feature::Pondering, Feature::Unknown, Feature::DrawOffers]
+        );
+        assert_eq!(
+            Protocol::negotiate(&[2, 3], &[Feature::Pondering], &[1, 2, 3], &features),
+            Some(Protocol {
+                version: 3,
+                features: vec![Feature::Pondering]
+            })
+        );
+        assert_eq!(
+            Protocol::negotiate(&[2], &features, &[1, 3], &features),
+            None
+        );
+
+        // Older moderators send EngineInit without any fields
+        match serde_json::from_str(r#"{"type":"EngineInit"}"#).unwrap() {
+            In::EngineInit {
+                protocol_versions, ..
+            } => assert!(protocol_versions.is_empty()),
+            _ => panic!("Expected EngineInit"),
+        }
+    }
+}
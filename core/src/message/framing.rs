@@ -0,0 +1,370 @@
+//! How messages are delimited on a stream.
+//!
+//! Every message is a single line of UTF-8 json terminated by a newline (`\n`). Json never needs
+//! a raw newline inside a value, so a newline always ends a message. A carriage return before the
+//! newline is ignored, as are empty lines. Messages may be at most `MAX_MESSAGE_LEN` bytes long,
+//! not counting the newline. Longer messages are discarded up to the next newline without being
+//! parsed
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::In;
+use crate::game;
+
+/// The longest message in bytes that a decoder accepts by default
+pub const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+const DELIMITER: u8 = b'\n';
+
+/// How many bytes from the start of an oversized message are kept to report which message it was
+const OVERSIZED_PREFIX_LEN: usize = 256;
+
+/// The ways a message can fail to be received
+#[derive(Debug)]
+pub enum FrameError {
+    /// A message was longer than the decoder's limit. `len` is the number of bytes received
+    /// before the message was given up on, and `prefix` is the start of the message, at most
+    /// `OVERSIZED_PREFIX_LEN` bytes with invalid UTF-8 replaced
+    Oversized { len: usize, prefix: String },
+
+    /// A complete message was received but could not be parsed
+    Invalid {
+        /// The text of the message. Invalid UTF-8 is replaced
+        json: String,
+        /// Why the message could not be parsed
+        message: String,
+    },
+
+    /// Reading from the stream failed
+    Io(io::Error),
+}
+
+impl FrameError {
+    /// The `In::InvalidRequest` a moderator sends in response to this error, or None for I/O
+    /// errors, which can't be reported back to the sender
+    pub fn to_invalid_request(&self, related_game: Option<game::ID>) -> Option<In> {
+        match self {
+            FrameError::Oversized { prefix, .. } => Some(In::InvalidRequest {
+                message: self.to_string(),
+                request_json: prefix.clone(),
+                related_game,
+            }),
+            FrameError::Invalid { json, message } => Some(In::InvalidRequest {
+                message: message.clone(),
+                request_json: json.clone(),
+                related_game,
+            }),
+            FrameError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Oversized { len, .. } => {
+                write!(f, "Message of at least {} bytes is too long", len)
+            }
+            FrameError::Invalid { json, message } => {
+                write!(f, "Invalid message {}: {}", json, message)
+            }
+            FrameError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// Serializes a message into a single frame, including the trailing newline
+pub fn encode<T: Serialize>(message: &T) -> Vec<u8> {
+    let mut frame = serde_json::to_vec(message).expect("Messages always serialize");
+    frame.push(DELIMITER);
+    frame
+}
+
+/// Writes framed messages to a stream
+pub struct Encoder<W> {
+    writer: W,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(writer: W) -> Encoder<W> {
+        Encoder { writer }
+    }
+
+    /// Writes a message and flushes the stream so the message is delivered right away
+    pub fn send<T: Serialize>(&mut self, message: &T) -> io::Result<()> {
+        self.writer.write_all(&encode(message))?;
+        self.writer.flush()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Splits a stream of bytes into messages. Bytes may be pushed in pieces of any size, so partial
+/// reads are handled by pushing whatever was read and then taking every complete message
+pub struct Decoder {
+    buffer: Vec<u8>,
+    max_len: usize,
+    /// The number of bytes of an oversized message that have been dropped. While this is non zero
+    /// everything up to the next newline is dropped
+    discarded: usize,
+    /// How many bytes at the start of the buffer are known not to contain a newline, so that each
+    /// push only has to search the new bytes
+    scanned: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::with_max_len(MAX_MESSAGE_LEN)
+    }
+
+    /// Creates a decoder that rejects messages longer than `max_len` bytes
+    pub fn with_max_len(max_len: usize) -> Decoder {
+        Decoder {
+            buffer: Vec::new(),
+            max_len,
+            discarded: 0,
+            scanned: 0,
+        }
+    }
+
+    /// Adds bytes read from the stream
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Takes the next complete message, or returns None if more bytes are needed
+    pub fn next_message<T: DeserializeOwned>(&mut self) -> Option<Result<T, FrameError>> {
+        loop {
+            let found = self.buffer[self.scanned..]
+                .iter()
+                .position(|b| *b == DELIMITER);
+            let end = match found {
+                Some(end) => self.scanned + end,
+                None => {
+                    self.scanned = self.buffer.len();
+                    if self.discarded == 0 && self.buffer.len() <= self.max_len {
+                        return None;
+                    }
+                    // Don't hold on to the start of a message that is already too long
+                    let len = self.buffer.len();
+                    let prefix = oversized_prefix(&self.buffer);
+                    self.buffer.clear();
+                    self.scanned = 0;
+                    self.discarded += len;
+                    if self.discarded == len {
+                        return Some(Err(FrameError::Oversized { len, prefix }));
+                    }
+                    return None;
+                }
+            };
+
+            self.scanned = 0;
+            let frame: Vec<u8> = self.buffer.drain(..=end).collect();
+            let mut frame = &frame[..end];
+            if self.discarded != 0 {
+                // The end of an oversized message, which was already reported
+                self.discarded = 0;
+                continue;
+            }
+            if frame.last() == Some(&b'\r') {
+                frame = &frame[..frame.len() - 1];
+            }
+            if frame.iter().all(|b| b.is_ascii_whitespace()) {
+                continue;
+            }
+            if frame.len() > self.max_len {
+                return Some(Err(FrameError::Oversized {
+                    len: frame.len(),
+                    prefix: oversized_prefix(frame),
+                }));
+            }
+            return Some(
+                serde_json::from_slice(frame).map_err(|err| FrameError::Invalid {
+                    json: String::from_utf8_lossy(frame).into_owned(),
+                    message: err.to_string(),
+                }),
+            );
+        }
+    }
+}
+
+fn oversized_prefix(frame: &[u8]) -> String {
+    String::from_utf8_lossy(&frame[..frame.len().min(OVERSIZED_PREFIX_LEN)]).into_owned()
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+/// Reads messages of type `T` from a stream, blocking until each message arrives. Ends when the
+/// stream does, or after an I/O error
+pub struct MessageReader<R, T> {
+    reader: R,
+    decoder: Decoder,
+    done: bool,
+    message: PhantomData<fn() -> T>,
+}
+
+impl<R: Read, T: DeserializeOwned> MessageReader<R, T> {
+    pub fn new(reader: R) -> MessageReader<R, T> {
+        MessageReader::with_decoder(reader, Decoder::new())
+    }
+
+    pub fn with_decoder(reader: R, decoder: Decoder) -> MessageReader<R, T> {
+        MessageReader {
+            reader,
+            decoder,
+            done: false,
+            message: PhantomData,
+        }
+    }
+}
+
+impl<R: Read, T: DeserializeOwned> Iterator for MessageReader<R, T> {
+    type Item = Result<T, FrameError>;
+
+    fn next(&mut self) -> Option<Result<T, FrameError>> {
+        let mut chunk = [0; 4096];
+        loop {
+            if let Some(message) = self.decoder.next_message() {
+                return Some(message);
+            }
+            if self.done {
+                return None;
+            }
+            match self.reader.read(&mut chunk) {
+                Ok(0) => self.done = true,
+                Ok(len) => self.decoder.push(&chunk[..len]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(FrameError::Io(err)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::GameOut;
+
+    #[test]
+    fn partial_reads() {
+        let mut bytes = encode(&GameOut::Resign);
+        bytes.extend_from_slice(b"\r\n\n");
+        bytes.extend(encode(&GameOut::DrawOffer));
+
+        let mut decoder = Decoder::new();
+        let mut messages = Vec::new();
+        // Feed one byte at a time
+        for byte in bytes.iter() {
+            decoder.push(&[*byte]);
+            while let Some(message) = decoder.next_message::<GameOut>() {
+                messages.push(message.unwrap());
+            }
+        }
+        assert!(matches!(
+            messages.as_slice(),
+            [GameOut::Resign, GameOut::DrawOffer]
+        ));
+    }
+
+    #[test]
+    fn invalid_and_oversized() {
+        let mut decoder = Decoder::with_max_len(32);
+        decoder.push(b"{\"type\":\"Dance\"}\n");
+        match decoder.next_message::<GameOut>() {
+            Some(Err(err)) => match err.to_invalid_request(Some(7)) {
+                Some(In::InvalidRequest {
+                    request_json,
+                    related_game,
+                    ..
+                }) => {
+                    assert_eq!(request_json, "{\"type\":\"Dance\"}");
+                    assert_eq!(related_game, Some(7));
+                }
+                _ => panic!("Expected an invalid request"),
+            },
+            _ => panic!("Expected a parse error"),
+        }
+
+        // An oversized message is reported once along with its start, then skipped up to the
+        // next newline
+        decoder.push(&[b'x'; 40]);
+        match decoder.next_message::<GameOut>() {
+            Some(Err(err @ FrameError::Oversized { len: 40, .. })) => {
+                match err.to_invalid_request(None) {
+                    Some(In::InvalidRequest { request_json, .. }) => {
+                        assert_eq!(request_json, "x".repeat(40))
+                    }
+                    _ => panic!("Expected an invalid request"),
+                }
+            }
+            _ => panic!("Expected an oversized message"),
+        }
+        decoder.push(&[b'x'; 40]);
+        assert!(decoder.next_message::<GameOut>().is_none());
+        decoder.push(b"xx\n");
+        decoder.push(&encode(&GameOut::Resign));
+        assert!(matches!(
+            decoder.next_message::<GameOut>(),
+            Some(Ok(GameOut::Resign))
+        ));
+    }
+
+    #[test]
+    fn long_messages_in_small_pieces() {
+        let mut frame = encode(&GameOut::Resign);
+        frame.splice(1..1, b" ".repeat(100_000));
+        let mut decoder = Decoder::new();
+        for chunk in frame.chunks(7) {
+            decoder.push(chunk);
+            if decoder.buffer.len() < frame.len() {
+                assert!(decoder.next_message::<GameOut>().is_none());
+                // Only the bytes that were just pushed are left to search
+                assert_eq!(decoder.scanned, decoder.buffer.len());
+            }
+        }
+        assert!(matches!(
+            decoder.next_message::<GameOut>(),
+            Some(Ok(GameOut::Resign))
+        ));
+        assert_eq!(decoder.scanned, 0);
+    }
+
+    #[test]
+    fn reader() {
+        let mut bytes = encode(&GameOut::GetClocks);
+        bytes.extend(b"not json\n");
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.send(&GameOut::Resign).unwrap();
+        bytes.extend(encoder.into_inner());
+
+        let messages: Vec<_> = MessageReader::<_, GameOut>::new(&bytes[..]).collect();
+        assert!(matches!(
+            messages.as_slice(),
+            [
+                Ok(GameOut::GetClocks),
+                Err(FrameError::Invalid { .. }),
+                Ok(GameOut::Resign)
+            ]
+        ));
+    }
+}
@@ -0,0 +1,396 @@
+//! Human readable move notation.
+//! UCI long algebraic notation (e2e4, e7e8q) can be parsed and printed without a board, however it
+//! needs a board to be converted to and from a `RawMove`. Standard Algebraic Notation (Nf3, exd5,
+//! O-O, e8=Q#) depends on the position, so it is always produced and parsed through a board
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::board::{Board, Move, MoveKind, Piece, PieceKind};
+use crate::game::{ColorKind, RawMove};
+
+/// A square described by its file and rank, both starting at 0. Displayed as "e4"
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Square {
+    pub file: u32,
+    pub rank: u32,
+}
+
+/// A move in UCI long algebraic notation. Castling is written as the king moving to its
+/// destination in standard chess (e1g1) and as the king moving onto its rook in Chess960 (e1h1)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UciMove {
+    pub src: Square,
+    pub dst: Square,
+    /// The lowercase piece char of the piece promoted to
+    pub promotion: Option<char>,
+}
+
+/// A move displayed in Standard Algebraic Notation, including check and checkmate suffixes
+pub struct SanMove<'a> {
+    board: &'a Board,
+    m: Move,
+}
+
+/// The reasons notation can fail to parse
+#[derive(Debug, PartialEq, Eq)]
+pub enum NotationError {
+    /// A square name was not in the form file letter followed by rank number
+    InvalidSquare(String),
+
+    /// The move string was malformed
+    InvalidMove(String),
+
+    /// The promotion char did not refer to a piece in the game being played
+    InvalidPromotion(char),
+
+    /// The move was well formed but is not legal in the position
+    IllegalMove(String),
+}
+
+impl Square {
+    pub fn new(file: u32, rank: u32) -> Square {
+        Square { file, rank }
+    }
+}
+
+impl UciMove {
+    /// Describes a raw move made on `board`
+    pub fn from_raw(board: &Board, raw: &RawMove) -> UciMove {
+        let (src_file, src_rank) = board.file_rank(raw.src);
+        let (dst_file, dst_rank) = board.file_rank(raw.dst);
+        UciMove {
+            src: Square::new(src_file, src_rank),
+            dst: Square::new(dst_file, dst_rank),
+            promotion: raw
+                .promotion
+                .and_then(|piece| piece.to_char(board.kind(), ColorKind::BLACK)),
+        }
+    }
+
+    /// Converts this move into a raw move on `board`. The move is not checked for legality
+    pub fn to_raw(&self, board: &Board) -> Result<RawMove, NotationError> {
+        let square = |square: Square| {
+            board
+                .square(square.file, square.rank)
+                .ok_or_else(|| NotationError::InvalidSquare(square.to_string()))
+        };
+        let mut raw = RawMove::new(square(self.src)?, square(self.dst)?);
+        if let Some(c) = self.promotion {
+            let piece = Piece::from_char(board.kind(), c.to_ascii_uppercase())
+                .ok_or(NotationError::InvalidPromotion(c))?;
+            raw.promotion = Some(piece.kind);
+        }
+        Ok(raw)
+    }
+}
+
+impl Board {
+    /// Parses a move in UCI notation, returning the legal move it describes
+    pub fn parse_uci(&self, s: &str) -> Result<Move, NotationError> {
+        let raw = s.parse::<UciMove>()?.to_raw(self)?;
+        self.move_from_raw(&raw)
+            .ok_or_else(|| NotationError::IllegalMove(s.to_owned()))
+    }
+
+    /// Returns the UCI notation for a move on this board
+    pub fn to_uci(&self, m: Move) -> UciMove {
+        UciMove::from_raw(self, &self.to_raw_move(m))
+    }
+
+    /// Returns a displayable SAN representation of a legal move on this board
+    pub fn san(&self, m: Move) -> SanMove<'_> {
+        SanMove { board: self, m }
+    }
+
+    /// Parses a move in Standard Algebraic Notation, returning the legal move it describes.
+    /// Check, checkmate and annotation suffixes are ignored, as is the '=' before a promotion
+    /// piece
+    pub fn parse_san(&self, s: &str) -> Result<Move, NotationError> {
+        let wanted = normalize_san(s);
+        if wanted.is_empty() {
+            return Err(NotationError::InvalidMove(s.to_owned()));
+        }
+        // Generated once and shared with the disambiguation of every candidate
+        let legal = self.legal_moves();
+        legal
+            .iter()
+            .copied()
+            .find(|m| normalize_san(&san_without_suffix(self, *m, &legal)) == wanted)
+            .ok_or_else(|| NotationError::IllegalMove(s.to_owned()))
+    }
+}
+
+/// Strips the parts of a SAN string that don't affect which move it refers to
+fn normalize_san(s: &str) -> String {
+    let s = s
+        .trim()
+        .trim_end_matches(['+', '#', '!', '?'])
+        .replace('=', "");
+    // Castling is sometimes written with zeros
+    if s.starts_with("0-0") {
+        s.replace('0', "O")
+    } else {
+        s
+    }
+}
+
+/// Returns the SAN of a move without the check or checkmate suffix. `legal` are the legal moves
+/// on `board`, which the move is told apart from
+fn san_without_suffix(board: &Board, m: Move, legal: &[Move]) -> String {
+    let kind = board.kind();
+    let piece = board.get(m.src).expect("SAN move has no piece on src");
+    let mut san = String::new();
+
+    if let MoveKind::Castle { rook_src } = m.kind {
+        return if rook_src.index() > m.src.index() {
+            "O-O".to_owned()
+        } else {
+            "O-O-O".to_owned()
+        };
+    }
+
+    let capture = board.get(m.dst).is_some() || m.kind == MoveKind::EnPassant;
+    let (src_file, src_rank) = board.file_rank(m.src);
+    if piece.kind == PieceKind::Pawn {
+        if capture {
+            san.push(file_char(src_file));
+        }
+    } else {
+        san.push(piece.kind.to_char(kind, ColorKind::WHITE).unwrap());
+
+        let others: Vec<&Move> = legal
+            .iter()
+            .filter(|other| {
+                other.dst == m.dst
+                    && other.src != m.src
+                    && board.get(other.src).map(|p| p.kind) == Some(piece.kind)
+            })
+            .collect();
+        if !others.is_empty() {
+            let shares_file = others
+                .iter()
+                .any(|other| board.file_rank(other.src).0 == src_file);
+            let shares_rank = others
+                .iter()
+                .any(|other| board.file_rank(other.src).1 == src_rank);
+            if !shares_file {
+                san.push(file_char(src_file));
+            } else if !shares_rank {
+                san.push_str(&(src_rank + 1).to_string());
+            } else {
+                san.push(file_char(src_file));
+                san.push_str(&(src_rank + 1).to_string());
+            }
+        }
+    }
+
+    if capture {
+        san.push('x');
+    }
+    san.push_str(&board.square_name(m.dst));
+
+    if let Some(promotion) = m.promotion() {
+        san.push('=');
+        san.push(promotion.to_char(kind, ColorKind::WHITE).unwrap());
+    }
+    san
+}
+
+fn file_char(file: u32) -> char {
+    (b'a' + file as u8) as char
+}
+
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", file_char(self.file), self.rank + 1)
+    }
+}
+
+impl FromStr for Square {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<Square, NotationError> {
+        let invalid = || NotationError::InvalidSquare(s.to_owned());
+        let mut chars = s.chars();
+        let file = chars
+            .next()
+            .filter(char::is_ascii_lowercase)
+            .ok_or_else(invalid)?;
+        let rank: u32 = chars.as_str().parse().map_err(|_| invalid())?;
+        if rank == 0 {
+            return Err(invalid());
+        }
+        Ok(Square::new(file as u32 - 'a' as u32, rank - 1))
+    }
+}
+
+impl fmt::Display for UciMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.src, self.dst)?;
+        if let Some(promotion) = self.promotion {
+            write!(f, "{}", promotion)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for UciMove {
+    type Err = NotationError;
+
+    fn from_str(s: &str) -> Result<UciMove, NotationError> {
+        let invalid = || NotationError::InvalidMove(s.to_owned());
+        // Squares are a letter followed by one or more digits, so split where a letter follows a
+        // digit
+        let bytes = s.as_bytes();
+        let split = (1..bytes.len())
+            .find(|i| bytes[*i].is_ascii_lowercase() && bytes[*i - 1].is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let (src, rest) = s.split_at(split);
+        let dst_len = rest
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| !c.is_ascii_digit())
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| rest.len());
+        let (dst, promotion) = rest.split_at(dst_len);
+
+        let mut promotion_chars = promotion.chars();
+        let promotion = match (promotion_chars.next(), promotion_chars.next()) {
+            (None, _) => None,
+            (Some(c), None) if c.is_ascii_alphabetic() => Some(c.to_ascii_lowercase()),
+            _ => return Err(invalid()),
+        };
+
+        Ok(UciMove {
+            src: src.parse()?,
+            dst: dst.parse()?,
+            promotion,
+        })
+    }
+}
+
+impl<'a> fmt::Display for SanMove<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&san_without_suffix(
+            self.board,
+            self.m,
+            &self.board.legal_moves(),
+        ))?;
+        let mut after = self.board.clone();
+        after.apply_move(self.m);
+        if after.is_in_check() {
+            if after.legal_moves().is_empty() {
+                f.write_str("#")?;
+            } else {
+                f.write_str("+")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::InvalidSquare(square) => write!(f, "Invalid square '{}'", square),
+            NotationError::InvalidMove(m) => write!(f, "Invalid move '{}'", m),
+            NotationError::InvalidPromotion(c) => write!(f, "Invalid promotion piece '{}'", c),
+            NotationError::IllegalMove(m) => write!(f, "Illegal move '{}'", m),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Kind;
+
+    fn board(fen: &str) -> Board {
+        Board::from_fen(Kind::Chess, fen).unwrap()
+    }
+
+    #[test]
+    fn uci_round_trip() {
+        for s in ["e2e4", "e7e8q", "a10j10", "h2h1n"].iter() {
+            assert_eq!(s.parse::<UciMove>().unwrap().to_string(), *s);
+        }
+        assert_eq!(
+            "e7e8q".parse::<UciMove>().unwrap(),
+            UciMove {
+                src: Square::new(4, 6),
+                dst: Square::new(4, 7),
+                promotion: Some('q'),
+            }
+        );
+        assert!("e2".parse::<UciMove>().is_err());
+        assert!("e2e4qq".parse::<UciMove>().is_err());
+        assert!("e0e4".parse::<UciMove>().is_err());
+    }
+
+    #[test]
+    fn uci_on_board() {
+        let start = Board::start_position(Kind::Chess);
+        let m = start.parse_uci("g1f3").unwrap();
+        assert_eq!(start.to_uci(m).to_string(), "g1f3");
+        assert_eq!(
+            start.parse_uci("e2e5"),
+            Err(NotationError::IllegalMove("e2e5".to_owned()))
+        );
+
+        let promotion = board("8/4P3/8/8/8/8/k7/4K3 w - - 0 1");
+        let m = promotion.parse_uci("e7e8r").unwrap();
+        assert_eq!(m.promotion(), Some(PieceKind::Rook));
+        assert_eq!(promotion.to_uci(m).to_string(), "e7e8r");
+        assert!(promotion.parse_uci("e7e8").is_err());
+
+        let castling = board("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let m = castling.parse_uci("e1g1").unwrap();
+        assert_eq!(castling.to_uci(m).to_string(), "e1g1");
+        let chess960 = board("1r4kr/8/8/8/8/8/8/1R4KR w HBhb - 0 1");
+        let m = chess960.parse_uci("g1b1").unwrap();
+        assert_eq!(chess960.to_uci(m).to_string(), "g1b1");
+    }
+
+    #[test]
+    fn san() {
+        let start = Board::start_position(Kind::Chess);
+        for (uci, san) in [("g1f3", "Nf3"), ("e2e4", "e4")].iter() {
+            let m = start.parse_uci(uci).unwrap();
+            assert_eq!(start.san(m).to_string(), *san);
+            assert_eq!(start.parse_san(san), Ok(m));
+        }
+
+        // Knights on b1 and f1 can both reach d2, rooks on a1 and a5 can both reach a3
+        let position = board("4k3/8/8/R7/8/8/8/RN2KN2 w - - 0 1");
+        let cases = [("b1d2", "Nbd2"), ("a5a3", "R5a3"), ("a5e5", "Re5+")];
+        for (uci, san) in cases.iter() {
+            let m = position.parse_uci(uci).unwrap();
+            assert_eq!(position.san(m).to_string(), *san);
+            assert_eq!(position.parse_san(san), Ok(m));
+        }
+
+        let mate = board("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        let m = mate.parse_uci("a1a8").unwrap();
+        assert_eq!(mate.san(m).to_string(), "Ra8#");
+
+        let castles = board("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1");
+        assert_eq!(
+            castles.san(castles.parse_uci("e8c8").unwrap()).to_string(),
+            "O-O-O"
+        );
+        assert!(castles.parse_san("0-0").is_ok());
+
+        let en_passant = board("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2");
+        let m = en_passant.parse_uci("e5d6").unwrap();
+        assert_eq!(en_passant.san(m).to_string(), "exd6");
+
+        let promotion = board("3r4/4P3/8/8/8/8/k7/4K3 w - - 0 1");
+        let m = promotion.parse_uci("e7d8n").unwrap();
+        assert_eq!(promotion.san(m).to_string(), "exd8=N");
+        assert_eq!(promotion.parse_san("exd8N"), Ok(m));
+        assert!(promotion.parse_san("Nf3").is_err());
+    }
+}
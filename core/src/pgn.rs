@@ -0,0 +1,962 @@
+//! Portable Game Notation export and import.
+//! Games are written with the seven tag roster, the variant, time control, termination and start
+//! position tags, and a `[%clk]` comment after every move when clocks are in use
+
+use chrono::prelude::*;
+use chrono::Duration;
+use smallvec::SmallVec;
+use std::fmt;
+
+use crate::board::{Board, ParseError, STANDARD_CHESS960_INDEX};
+use crate::game::{Clock, Clocks, ColorKind, GameEndCause, Kind, RawMove, TimeFormat, TimePeriod};
+use crate::history::PositionHistory;
+use crate::message::EngineInfo;
+use crate::notation::{NotationError, UciMove};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+/// The prefix of the comment used to record the move that ended a game by rules infraction, as
+/// illegal moves cannot be written in SAN
+const ILLEGAL_MOVE_COMMENT: &str = "Illegal move: ";
+
+/// How a game ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameResult {
+    /// The color that won the game, or None for a draw
+    pub winner: Option<ColorKind>,
+
+    /// Why the game ended. None if it isn't known, for example when importing a PGN without a
+    /// Termination tag
+    pub cause: Option<GameEndCause>,
+}
+
+/// A complete record of a game, which can be converted to and from PGN
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameRecord {
+    pub event: String,
+    pub site: String,
+    pub round: String,
+
+    /// The instant the game started
+    pub start_time: DateTime<Utc>,
+
+    /// The players of the game, indexed by color id
+    pub players: Vec<EngineInfo>,
+
+    /// The position the game started from
+    pub start: Board,
+
+    /// The moves played, in order
+    pub moves: Vec<RawMove>,
+
+    /// The clocks of each player. The times of each clock are the instants that player finished
+    /// each of their moves. None if the clocks aren't known
+    pub clocks: Option<Clocks>,
+
+    /// None if the game is still in progress
+    pub result: Option<GameResult>,
+}
+
+/// The reasons a PGN can fail to be written or read
+#[derive(Debug, PartialEq)]
+pub enum PgnError {
+    /// A tag pair was malformed
+    InvalidTag(String),
+
+    /// The FEN tag could not be parsed
+    InvalidFen(ParseError),
+
+    /// A move could not be parsed or was illegal. `ply` is 0 based
+    InvalidMove { ply: usize, error: NotationError },
+
+    /// The game termination marker was not one of 1-0, 0-1, 1/2-1/2 or *
+    InvalidResult(String),
+
+    /// A move in a game record being exported is illegal. Only the last move of a game ended by
+    /// `GameEndCause::IllegalMove` may be illegal
+    IllegalMove { ply: usize, illegal_move: RawMove },
+}
+
+impl GameRecord {
+    /// Creates a record for a game that hasn't started yet
+    pub fn new(start: Board, players: Vec<EngineInfo>, start_time: DateTime<Utc>) -> GameRecord {
+        GameRecord {
+            event: "?".to_owned(),
+            site: "?".to_owned(),
+            round: "?".to_owned(),
+            start_time,
+            players,
+            start,
+            moves: Vec::new(),
+            clocks: None,
+            result: None,
+        }
+    }
+
+    /// Writes this game as PGN
+    pub fn to_pgn(&self) -> Result<String, PgnError> {
+        let mut board = self.start.clone();
+        let illegal_move = match &self.result {
+            Some(GameResult {
+                cause: Some(GameEndCause::IllegalMove(m)),
+                ..
+            }) => Some(*m),
+            _ => None,
+        };
+        let clock_times = self.remaining_times();
+
+        let mut tokens = Vec::new();
+        for (ply, raw) in self.moves.iter().enumerate() {
+            let m = match board.move_from_raw(raw) {
+                Some(m) => m,
+                None if ply + 1 == self.moves.len() && illegal_move == Some(*raw) => break,
+                None => {
+                    return Err(PgnError::IllegalMove {
+                        ply,
+                        illegal_move: *raw,
+                    })
+                }
+            };
+            let number = board.fullmove_number();
+            if board.to_move() == ColorKind::WHITE {
+                tokens.push(format!("{}.", number));
+            } else if ply == 0 {
+                tokens.push(format!("{}...", number));
+            }
+            tokens.push(board.san(m).to_string());
+            if let Some(remaining) = clock_times.as_ref().and_then(|times| times.get(ply)) {
+                tokens.push(format!("{{[%clk {}]}}", format_clock(*remaining)));
+            }
+            board.apply_move(m);
+        }
+        if let Some(m) = illegal_move {
+            let uci = UciMove::from_raw(&board, &m);
+            tokens.push(format!("{{{}{}}}", ILLEGAL_MOVE_COMMENT, uci));
+        }
+        tokens.push(result_marker(self.result.as_ref()).to_owned());
+
+        let mut pgn = String::new();
+        for (name, value) in self.tags() {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, escape(&value)));
+        }
+        pgn.push('\n');
+        let mut line_len = 0;
+        for token in tokens {
+            if line_len != 0 && line_len + 1 + token.len() > 79 {
+                pgn.push('\n');
+                line_len = 0;
+            }
+            if line_len != 0 {
+                pgn.push(' ');
+                line_len += 1;
+            }
+            line_len += token.len();
+            pgn.push_str(&token);
+        }
+        pgn.push('\n');
+        Ok(pgn)
+    }
+
+    fn tags(&self) -> Vec<(&'static str, String)> {
+        let player = |color: ColorKind| match self.players.get(color.id() as usize) {
+            Some(info) => format!("{} {}", info.name, info.version).trim().to_owned(),
+            None => "?".to_owned(),
+        };
+        let mut tags = vec![
+            ("Event", self.event.clone()),
+            ("Site", self.site.clone()),
+            ("Date", self.start_time.format("%Y.%m.%d").to_string()),
+            ("Round", self.round.clone()),
+            ("White", player(ColorKind::WHITE)),
+            ("Black", player(ColorKind::BLACK)),
+            ("Result", result_marker(self.result.as_ref()).to_owned()),
+            ("UTCDate", self.start_time.format("%Y.%m.%d").to_string()),
+            ("UTCTime", self.start_time.format("%H:%M:%S").to_string()),
+        ];
+        if self.start.kind() != Kind::Chess {
+            tags.push(("Variant", self.start.kind().to_string()));
+        } else if self
+            .start
+            .chess960_index()
+            .is_some_and(|index| index != STANDARD_CHESS960_INDEX)
+        {
+            // Not a game kind, the FEN tag is enough to play the game as regular chess
+            tags.push(("Variant", "Chess960".to_owned()));
+        }
+        if let Some(clock) = self
+            .clocks
+            .as_ref()
+            .and_then(|clocks| clocks.get_clock(ColorKind::WHITE))
+        {
+            tags.push(("TimeControl", format_time_control(&clock.time_format)));
+        }
+        if let Some(cause) = self
+            .result
+            .as_ref()
+            .and_then(|result| result.cause.as_ref())
+        {
+            tags.push(("Termination", termination(cause).to_owned()));
+        }
+        if self.start != Board::start_position(self.start.kind()) {
+            tags.push(("SetUp", "1".to_owned()));
+            tags.push(("FEN", self.start.to_fen()));
+        }
+        tags
+    }
+
+    /// Computes the time left on the mover's clock after each move, or None if there are no
+    /// clocks or the game is untimed
+    fn remaining_times(&self) -> Option<Vec<Duration>> {
+        let clocks = self.clocks.as_ref()?;
+        let colors = self.start.kind().color_count();
+        let mut remaining: Vec<u64> = Vec::new();
+        let mut moves_made = vec![0; colors as usize];
+        for id in 0..colors {
+            remaining.push(
+                clocks
+                    .get_clock(ColorKind::new(id))?
+                    .time_format
+                    .initial_nanos()?,
+            );
+        }
+
+        let mut result = Vec::new();
+        let mut last_move_time = self.start_time;
+        let mut color = self.start.to_move();
+        for _ in self.moves.iter() {
+            let clock = clocks.get_clock(color)?;
+            let made = &mut moves_made[color.id() as usize];
+            let time = *clock.times.get(*made)?;
+            *made += 1;
+            let move_number = *made as u32;
+            let used = (time - last_move_time)
+                .num_nanoseconds()
+                .unwrap_or(0)
+                .max(0) as u64;
+            let format = &clock.time_format;
+            let charged = used.saturating_sub(format.delay_nanos(move_number));
+            let left = &mut remaining[color.id() as usize];
+            *left = format.after_move(left.saturating_sub(charged), move_number);
+            result.push(Duration::nanoseconds(*left as i64));
+            last_move_time = time;
+            color = ColorKind::new((color.id() + 1) % colors);
+        }
+        Some(result)
+    }
+
+    /// Reads a single game from PGN. The moves are replayed on the starting position, so the
+    /// PGN must contain only legal moves. Variations, NAGs and comments other than `[%clk]` are
+    /// ignored. A Variant tag naming a known game kind overrides `kind`
+    pub fn from_pgn(kind: Kind, pgn: &str) -> Result<GameRecord, PgnError> {
+        let mut tags = Vec::new();
+        let mut movetext = String::new();
+        for line in pgn.lines() {
+            let trimmed = line.trim();
+            if movetext.is_empty() && trimmed.starts_with('[') {
+                tags.push(parse_tag(trimmed)?);
+            } else {
+                movetext.push_str(line);
+                movetext.push('\n');
+            }
+        }
+        let tag = |name: &str| {
+            tags.iter()
+                .find(|(tag_name, _)| tag_name == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let kind = tag("Variant")
+            .and_then(|variant| variant.parse().ok())
+            .unwrap_or(kind);
+        let start = match tag("FEN") {
+            Some(fen) => Board::from_fen(kind, fen).map_err(PgnError::InvalidFen)?,
+            None => Board::start_position(kind),
+        };
+        let player = |name: Option<&str>| EngineInfo {
+            name: name.unwrap_or("?").to_owned(),
+            ..EngineInfo::default()
+        };
+        let mut record = GameRecord::new(
+            start.clone(),
+            vec![player(tag("White")), player(tag("Black"))],
+            parse_start_time(tag("UTCDate").or_else(|| tag("Date")), tag("UTCTime")),
+        );
+        record.event = tag("Event").unwrap_or("?").to_owned();
+        record.site = tag("Site").unwrap_or("?").to_owned();
+        record.round = tag("Round").unwrap_or("?").to_owned();
+
+        let mut history = PositionHistory::new(&start);
+        let mut board = start;
+        let mut clock_comments: Vec<Option<Duration>> = Vec::new();
+        let mut illegal_move = None;
+        let mut marker = tag("Result").unwrap_or("*").to_owned();
+        for token in tokenize(&movetext) {
+            match token {
+                Token::Comment(comment) => {
+                    if let Some(uci) = comment.trim().strip_prefix(ILLEGAL_MOVE_COMMENT) {
+                        illegal_move = uci.parse::<UciMove>().and_then(|m| m.to_raw(&board)).ok();
+                    } else if let Some(last) = clock_comments.last_mut() {
+                        *last = parse_clock_comment(&comment);
+                    }
+                }
+                Token::Result(result) => marker = result,
+                Token::Move(san) => {
+                    let m = board
+                        .parse_san(&san)
+                        .map_err(|error| PgnError::InvalidMove {
+                            ply: record.moves.len(),
+                            error,
+                        })?;
+                    record.moves.push(board.to_raw_move(m));
+                    clock_comments.push(None);
+                    board.apply_move(m);
+                    history.push(&board);
+                }
+            }
+        }
+
+        let time_format = tag("TimeControl").and_then(parse_time_control);
+        if let Some(time_format) = time_format {
+            let remaining: Option<Vec<Duration>> = clock_comments.into_iter().collect();
+            record.clocks = Some(build_clocks(&record, time_format, remaining));
+        }
+        if let Some(m) = illegal_move {
+            record.moves.push(m);
+        }
+        let end = history
+            .game_end(&board)
+            .map(|cause| (cause, board.to_move()));
+        record.result = parse_result(&marker, tag("Termination"), end, illegal_move)?;
+        Ok(record)
+    }
+}
+
+/// Returns the PGN game termination marker for a result
+fn result_marker(result: Option<&GameResult>) -> &'static str {
+    match result {
+        Some(GameResult {
+            winner: Some(winner),
+            ..
+        }) => match winner.id() {
+            0 => "1-0",
+            _ => "0-1",
+        },
+        Some(GameResult { winner: None, .. }) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+/// Returns the value of the Termination tag for a game end cause
+fn termination(cause: &GameEndCause) -> &'static str {
+    match cause {
+        GameEndCause::Flag => "Time forfeit",
+        GameEndCause::IllegalMove(_) => "Rules infraction",
+        _ => "Normal",
+    }
+}
+
+/// Works out how a game ended from its result marker and Termination tag. `end` is how the rules
+/// end the game in the final position, if they do, and whose move it is there. The cause is only
+/// recovered when the PGN determines it: a "Normal" game that the rules didn't end, or whose
+/// result doesn't match how they ended it, could have been resigned, agreed drawn or claimed
+/// drawn, so its cause is None
+fn parse_result(
+    marker: &str,
+    termination: Option<&str>,
+    end: Option<(GameEndCause, ColorKind)>,
+    illegal_move: Option<RawMove>,
+) -> Result<Option<GameResult>, PgnError> {
+    let winner = match marker {
+        "1-0" => Some(ColorKind::WHITE),
+        "0-1" => Some(ColorKind::BLACK),
+        "1/2-1/2" => None,
+        "*" => return Ok(None),
+        _ => return Err(PgnError::InvalidResult(marker.to_owned())),
+    };
+    let cause = match (termination, illegal_move) {
+        (_, Some(m)) => Some(GameEndCause::IllegalMove(m)),
+        (Some("Time forfeit"), _) => Some(GameEndCause::Flag),
+        (Some("Normal"), _) | (None, _) => match end {
+            // The player to move has lost, every other end the rules make is a draw
+            Some((cause @ GameEndCause::Checkmate, to_move))
+            | Some((cause @ GameEndCause::RoyalCaptured, to_move)) => {
+                if winner.is_some() && winner != Some(to_move) {
+                    Some(cause)
+                } else {
+                    None
+                }
+            }
+            Some((cause, _)) if winner.is_none() => Some(cause),
+            _ => None,
+        },
+        _ => None,
+    };
+    Ok(Some(GameResult { winner, cause }))
+}
+
+/// Rebuilds the clocks of each player from the time left after each move
+fn build_clocks(
+    record: &GameRecord,
+    time_format: TimeFormat,
+    remaining: Option<Vec<Duration>>,
+) -> Clocks {
+    let colors = record.start.kind().color_count();
+    let mut clocks: SmallVec<[Clock; 2]> = (0..colors)
+        .map(|_| Clock::new(time_format.clone()))
+        .collect();
+    let remaining = match remaining {
+        Some(remaining) if time_format.initial_nanos().is_some() => remaining,
+        _ => return Clocks::new(clocks),
+    };
+
+    let mut last_move_time = record.start_time;
+    let mut color = record.start.to_move();
+    for left in remaining {
+        let clock = &mut clocks[color.id() as usize];
+        let move_number = clock.times.len() as u32 + 1;
+        let before = clock.nanos_on_clock.unwrap_or(0) as i64;
+        let added = time_format.after_move(0, move_number) as i64;
+        let left = left.num_nanoseconds().unwrap_or(0).max(0);
+        let used = (before + added - left).max(0);
+        last_move_time += Duration::nanoseconds(used);
+        clock.times.push(last_move_time);
+        clock.nanos_on_clock = Some(left as u64);
+        color = ColorKind::new((color.id() + 1) % colors);
+    }
+    Clocks::new(clocks)
+}
+
+fn format_seconds(nanos: u64) -> String {
+    if nanos.is_multiple_of(NANOS_PER_SECOND) {
+        (nanos / NANOS_PER_SECOND).to_string()
+    } else {
+        format!("{}", nanos as f64 / NANOS_PER_SECOND as f64)
+    }
+}
+
+/// Formats the time of a period and its increment, such as `300+2`
+fn format_period(nanos: u64, increment_nanos: u64) -> String {
+    if increment_nanos == 0 {
+        format_seconds(nanos)
+    } else {
+        format!(
+            "{}+{}",
+            format_seconds(nanos),
+            format_seconds(increment_nanos)
+        )
+    }
+}
+
+/// Formats a time format as the value of a PGN TimeControl tag, for example `300+2` for five
+/// minutes with a two second increment, `40/5400+30:1800+30` for 40 moves in 90 minutes followed
+/// by 30 minutes, `10/move` for ten seconds per move, or `-` for unlimited time. Delays can't be
+/// written and are left out
+pub fn format_time_control(time_format: &TimeFormat) -> String {
+    match time_format {
+        TimeFormat::Timed {
+            initial_nanos,
+            increment_nanos,
+            ..
+        } => format_period(*initial_nanos, *increment_nanos),
+        TimeFormat::PerMove { nanos_per_move } => {
+            format!("{}/move", format_seconds(*nanos_per_move))
+        }
+        TimeFormat::Periods { periods } => periods
+            .iter()
+            .map(|period| {
+                let time = format_period(period.nanos, period.increment_nanos);
+                match period.moves {
+                    Some(moves) => format!("{}/{}", moves, time),
+                    None => time,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(":"),
+        TimeFormat::Unlimited => "-".to_owned(),
+    }
+}
+
+/// Parses the value of a PGN TimeControl tag. Besides the forms written by
+/// `format_time_control`, periods may be given without an increment, as in `40/7200:3600`. Unknown
+/// (`?`) and sandclock (`*<seconds>`) time controls aren't supported
+pub fn parse_time_control(time_control: &str) -> Option<TimeFormat> {
+    let seconds = |s: &str| {
+        s.parse::<f64>()
+            .ok()
+            .filter(|seconds| *seconds >= 0.0)
+            .map(|seconds| (seconds * NANOS_PER_SECOND as f64).round() as u64)
+    };
+    let period = |s: &str| -> Option<(u64, u64)> {
+        let mut parts = s.splitn(2, '+');
+        let nanos = seconds(parts.next()?)?;
+        let increment_nanos = match parts.next() {
+            Some(increment) => seconds(increment)?,
+            None => 0,
+        };
+        Some((nanos, increment_nanos))
+    };
+
+    let time_control = time_control.trim();
+    if time_control == "-" {
+        return Some(TimeFormat::Unlimited);
+    }
+    if let Some(per_move) = time_control.strip_suffix("/move") {
+        return Some(TimeFormat::PerMove {
+            nanos_per_move: seconds(per_move)?,
+        });
+    }
+    if !time_control.contains(['/', ':']) {
+        let (initial_nanos, increment_nanos) = period(time_control)?;
+        return Some(TimeFormat::Timed {
+            initial_nanos,
+            increment_nanos,
+            delay_nanos: 0,
+        });
+    }
+
+    let mut periods: Vec<TimePeriod> = Vec::new();
+    for field in time_control.split(':') {
+        // Nothing can follow a period that lasts for the rest of the game
+        if periods.last().is_some_and(|last| last.moves.is_none()) {
+            return None;
+        }
+        let (moves, time) = match field.split_once('/') {
+            Some((moves, time)) => (Some(moves.parse().ok().filter(|moves| *moves > 0)?), time),
+            None => (None, field),
+        };
+        let (nanos, increment_nanos) = period(time)?;
+        periods.push(TimePeriod {
+            moves,
+            nanos,
+            increment_nanos,
+            delay_nanos: 0,
+        });
+    }
+    Some(TimeFormat::Periods { periods })
+}
+
+/// Formats a clock as H:MM:SS, adding tenths of a second if there are any
+fn format_clock(remaining: Duration) -> String {
+    let tenths = remaining.num_milliseconds() / 100;
+    let seconds = tenths / 10;
+    let mut result = format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    );
+    if tenths % 10 != 0 {
+        result.push_str(&format!(".{}", tenths % 10));
+    }
+    result
+}
+
+/// Parses the time out of a comment containing `[%clk H:MM:SS]`
+fn parse_clock_comment(comment: &str) -> Option<Duration> {
+    let start = comment.find("[%clk")? + "[%clk".len();
+    let end = start + comment[start..].find(']')?;
+    let mut parts = comment[start..end].trim().split(':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(
+        Duration::hours(hours)
+            + Duration::minutes(minutes)
+            + Duration::milliseconds((seconds * 1000.0).round() as i64),
+    )
+}
+
+fn parse_start_time(date: Option<&str>, time: Option<&str>) -> DateTime<Utc> {
+    let date = date
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y.%m.%d").ok())
+        .unwrap_or_else(|| NaiveDate::from_ymd_opt(1970, 1, 1).unwrap());
+    let time = time
+        .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M:%S").ok())
+        .unwrap_or_else(|| NaiveTime::from_hms_opt(0, 0, 0).unwrap());
+    DateTime::from_naive_utc_and_offset(date.and_time(time), Utc)
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Parses a tag pair line such as `[Event "Casual game"]`
+fn parse_tag(line: &str) -> Result<(String, String), PgnError> {
+    let invalid = || PgnError::InvalidTag(line.to_owned());
+    let inner = line
+        .strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+        .ok_or_else(invalid)?;
+    let (name, value) = inner.split_once(char::is_whitespace).ok_or_else(invalid)?;
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    Ok((name.to_owned(), unescaped))
+}
+
+enum Token {
+    Move(String),
+    Comment(String),
+    Result(String),
+}
+
+/// Splits movetext into moves, comments and the result. Move numbers, NAGs and variations are
+/// dropped
+fn tokenize(movetext: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = movetext.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => tokens.push(Token::Comment(
+                chars.by_ref().take_while(|c| *c != '}').collect(),
+            )),
+            ';' => tokens.push(Token::Comment(
+                chars.by_ref().take_while(|c| *c != '\n').collect(),
+            )),
+            '(' => {
+                let mut depth = 1;
+                for c in chars.by_ref() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(next) = chars.peek() {
+                    if next.is_whitespace() || "{};(".contains(*next) {
+                        break;
+                    }
+                    word.push(chars.next().unwrap());
+                }
+                if word.starts_with('$') {
+                    continue;
+                }
+                if ["1-0", "0-1", "1/2-1/2", "*"].contains(&word.as_str()) {
+                    tokens.push(Token::Result(word));
+                    continue;
+                }
+                let san = word.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                if !san.is_empty() {
+                    tokens.push(Token::Move(san.to_owned()));
+                }
+            }
+        }
+    }
+    tokens
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::InvalidTag(tag) => write!(f, "Invalid tag pair: {}", tag),
+            PgnError::InvalidFen(err) => write!(f, "Invalid FEN tag: {}", err),
+            PgnError::InvalidMove { ply, error } => {
+                write!(f, "Invalid move at ply {}: {}", ply, error)
+            }
+            PgnError::InvalidResult(result) => write!(f, "Invalid result '{}'", result),
+            PgnError::IllegalMove { ply, illegal_move } => {
+                write!(f, "Illegal move {:?} at ply {}", illegal_move, ply)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(name: &str) -> EngineInfo {
+        EngineInfo {
+            name: name.to_owned(),
+            version: "1.0".to_owned(),
+            ..EngineInfo::default()
+        }
+    }
+
+    fn start_time() -> DateTime<Utc> {
+        DateTime::from_naive_utc_and_offset(
+            NaiveDate::from_ymd_opt(2021, 3, 14)
+                .unwrap()
+                .and_hms_opt(15, 9, 26)
+                .unwrap(),
+            Utc,
+        )
+    }
+
+    /// Plays fool's mate with each move taking one more second than the last
+    fn fools_mate() -> GameRecord {
+        let board = Board::start_position(Kind::Chess);
+        let mut record =
+            GameRecord::new(board.clone(), vec![engine("A"), engine("B")], start_time());
+        record.event = "Test \"match\"".to_owned();
+        let time_format = TimeFormat::Timed {
+            initial_nanos: 60 * NANOS_PER_SECOND,
+            increment_nanos: NANOS_PER_SECOND,
+            delay_nanos: 0,
+        };
+        let mut clocks = Clocks::new((0..2).map(|_| Clock::new(time_format.clone())).collect());
+
+        let mut board = board;
+        let mut time = start_time();
+        for (i, uci) in ["f2f3", "e7e5", "g2g4", "d8h4"].iter().enumerate() {
+            let m = board.parse_uci(uci).unwrap();
+            record.moves.push(board.to_raw_move(m));
+            time += Duration::seconds(i as i64 + 1);
+            clocks
+                .get_clock_mut(board.to_move())
+                .unwrap()
+                .times
+                .push(time);
+            board.apply_move(m);
+        }
+        record.clocks = Some(clocks);
+        record.result = Some(GameResult {
+            winner: Some(ColorKind::BLACK),
+            cause: Some(GameEndCause::Checkmate),
+        });
+        record
+    }
+
+    #[test]
+    fn export() {
+        assert_eq!(
+            fools_mate().to_pgn().unwrap(),
+            r#"[Event "Test \"match\""]
+[Site "?"]
+[Date "2021.03.14"]
+[Round "?"]
+[White "A 1.0"]
+[Black "B 1.0"]
+[Result "0-1"]
+[UTCDate "2021.03.14"]
+[UTCTime "15:09:26"]
+[TimeControl "60+1"]
+[Termination "Normal"]
+
+1. f3 {[%clk 0:01:00]} e5 {[%clk 0:00:59]} 2. g4 {[%clk 0:00:58]} Qh4#
+{[%clk 0:00:56]} 0-1
+"#
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let record = fools_mate();
+        let imported = GameRecord::from_pgn(Kind::Chess, &record.to_pgn().unwrap()).unwrap();
+        assert_eq!(imported.moves, record.moves);
+        assert_eq!(imported.result, record.result);
+        let (imported_clocks, clocks) = (imported.clocks.unwrap(), record.clocks.unwrap());
+        for color in [ColorKind::WHITE, ColorKind::BLACK].iter() {
+            let imported_clock = imported_clocks.get_clock(*color).unwrap();
+            let clock = clocks.get_clock(*color).unwrap();
+            assert_eq!(imported_clock.time_format, clock.time_format);
+            assert_eq!(imported_clock.times, clock.times);
+        }
+        assert_eq!(imported.start_time, record.start_time);
+        assert_eq!(imported.event, record.event);
+        assert_eq!(imported.players[0].name, "A 1.0");
+    }
+
+    #[test]
+    fn variant_tag() {
+        let mut board = Board::start_position(Kind::ContrastingChess);
+        let mut record =
+            GameRecord::new(board.clone(), vec![engine("A"), engine("B")], start_time());
+        for uci in ["e2e3", "e9e8"].iter() {
+            let m = board.parse_uci(uci).unwrap();
+            record.moves.push(board.to_raw_move(m));
+            board.apply_move(m);
+        }
+        let pgn = record.to_pgn().unwrap();
+        assert!(pgn.contains("[Variant \"Contrasting Chess\"]"));
+        assert!(!pgn.contains("[FEN"));
+
+        let imported = GameRecord::from_pgn(Kind::Chess, &pgn).unwrap();
+        assert_eq!(imported.start.kind(), Kind::ContrastingChess);
+        assert_eq!(imported.moves, record.moves);
+
+        let start = Board::chess960(0).unwrap();
+        let record = GameRecord::new(start.clone(), vec![engine("A"), engine("B")], start_time());
+        let pgn = record.to_pgn().unwrap();
+        assert!(pgn.contains("[Variant \"Chess960\"]"));
+        assert!(pgn.contains("[FEN \"bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1\"]"));
+        let imported = GameRecord::from_pgn(Kind::Chess, &pgn).unwrap();
+        assert_eq!(imported.start, start);
+    }
+
+    #[test]
+    fn illegal_move_and_setup() {
+        let start = Board::from_fen(Kind::Chess, "4k3/8/8/8/8/8/8/R3K3 b Q - 0 1").unwrap();
+        let mut record = GameRecord::new(start.clone(), Vec::new(), start_time());
+        let m = start.parse_uci("e8d8").unwrap();
+        record.moves.push(start.to_raw_move(m));
+        let mut after = start.clone();
+        after.apply_move(m);
+        let illegal = RawMove::new(
+            after.parse_square("e1").unwrap(),
+            after.parse_square("e8").unwrap(),
+        );
+        record.moves.push(illegal);
+        record.result = Some(GameResult {
+            winner: Some(ColorKind::BLACK),
+            cause: Some(GameEndCause::IllegalMove(illegal)),
+        });
+
+        let pgn = record.to_pgn().unwrap();
+        assert!(pgn.contains("[FEN \"4k3/8/8/8/8/8/8/R3K3 b Q - 0 1\"]"));
+        assert!(pgn.contains("1... Kd8 {Illegal move: e1e8} 0-1"));
+        let imported = GameRecord::from_pgn(Kind::Chess, &pgn).unwrap();
+        assert_eq!(imported.start, start);
+        assert_eq!(imported.moves, record.moves);
+        assert_eq!(imported.result, record.result);
+    }
+
+    #[test]
+    fn import_ignores_annotations() {
+        let pgn = "[Event \"?\"]\n[Result \"1/2-1/2\"]\n\n\
+                   1. e4 $1 e5 (1... c5 2. Nf3 (2. c3)) 2. Nf3 {A comment} Nc6 ; rest of line\n\
+                   3. Bb5 a6 1/2-1/2\n";
+        let record = GameRecord::from_pgn(Kind::Chess, pgn).unwrap();
+        assert_eq!(record.moves.len(), 6);
+        assert_eq!(record.clocks, None);
+        assert_eq!(
+            record.result,
+            Some(GameResult {
+                winner: None,
+                cause: None
+            })
+        );
+
+        match GameRecord::from_pgn(Kind::Chess, "1. e4 e4 *") {
+            Err(PgnError::InvalidMove { ply: 1, .. }) => {}
+            other => panic!("Expected an invalid move, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn import_only_recovers_unambiguous_causes() {
+        let result = |pgn: &str| {
+            GameRecord::from_pgn(Kind::Chess, pgn)
+                .unwrap()
+                .result
+                .unwrap()
+        };
+
+        // Resigned or agreed drawn games look the same as games the rules didn't end
+        assert_eq!(result("1. e4 e5 1-0").cause, None);
+        assert_eq!(
+            result("[Termination \"Normal\"]\n\n1. e4 e5 1/2-1/2").cause,
+            None
+        );
+
+        // A threefold repetition could be claimed, but a decisive result means it wasn't
+        let repeated = "1. Nf3 Nf6 2. Ng1 Ng8 3. Nf3 Nf6 4. Ng1 Ng8";
+        assert_eq!(result(&format!("{} 0-1", repeated)).cause, None);
+        assert_eq!(result(&format!("{} 1/2-1/2", repeated)).cause, None);
+
+        let mate = "1. f3 e5 2. g4 Qh4#";
+        assert_eq!(
+            result(&format!("{} 0-1", mate)).cause,
+            Some(GameEndCause::Checkmate)
+        );
+        // The mated player can't have won
+        assert_eq!(result(&format!("{} 1-0", mate)).cause, None);
+        assert_eq!(
+            result("[FEN \"7k/5Q2/6K1/8/8/8/8/8 b - - 0 1\"]\n\n1/2-1/2").cause,
+            Some(GameEndCause::Stalemate)
+        );
+        assert_eq!(
+            result(&format!(
+                "[Termination \"Time forfeit\"]\n\n{} 1-0",
+                repeated
+            ))
+            .cause,
+            Some(GameEndCause::Flag)
+        );
+    }
+
+    #[test]
+    fn time_controls() {
+        for time_control in [
+            "-",
+            "300",
+            "60+0.5",
+            "15/move",
+            "40/5400+30:1800+30",
+            "40/7200",
+        ]
+        .iter()
+        {
+            let format = parse_time_control(time_control).unwrap();
+            assert_eq!(format_time_control(&format), *time_control);
+        }
+        assert_eq!(
+            parse_time_control("40/7200:20/3600:900"),
+            Some(TimeFormat::Periods {
+                periods: vec![
+                    TimePeriod {
+                        moves: Some(40),
+                        nanos: 7200 * NANOS_PER_SECOND,
+                        increment_nanos: 0,
+                        delay_nanos: 0,
+                    },
+                    TimePeriod {
+                        moves: Some(20),
+                        nanos: 3600 * NANOS_PER_SECOND,
+                        increment_nanos: 0,
+                        delay_nanos: 0,
+                    },
+                    TimePeriod {
+                        moves: None,
+                        nanos: 900 * NANOS_PER_SECOND,
+                        increment_nanos: 0,
+                        delay_nanos: 0,
+                    },
+                ]
+            })
+        );
+        for invalid in ["?", "*180", "300:40/60", "0/60", "abc", "-5"].iter() {
+            assert_eq!(parse_time_control(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn periods() {
+        let format = parse_time_control("40/5400+30:1800+30").unwrap();
+        assert_eq!(format.initial_nanos(), Some(5400 * NANOS_PER_SECOND));
+        assert_eq!(format.moves_to_go(1), Some(40));
+        assert_eq!(format.after_move(0, 39), 30 * NANOS_PER_SECOND);
+        // The second period's time is added after the 40th move
+        assert_eq!(format.after_move(0, 40), 1830 * NANOS_PER_SECOND);
+        assert_eq!(format.moves_to_go(41), None);
+        assert_eq!(format.after_move(0, 100), 30 * NANOS_PER_SECOND);
+
+        // A last period with a number of moves repeats
+        let format = parse_time_control("40/7200").unwrap();
+        assert_eq!(format.moves_to_go(41), Some(40));
+        assert_eq!(format.after_move(0, 80), 7200 * NANOS_PER_SECOND);
+
+        let format = parse_time_control("10/move").unwrap();
+        assert_eq!(format.after_move(3, 7), 10 * NANOS_PER_SECOND);
+    }
+}
@@ -0,0 +1,255 @@
+//! Plumbing shared by the adapters that let engines speaking other protocols play GigaChess games
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+
+use giga_core::game::{ColorKind, TimeFormat};
+use giga_sdk::{Action, GameInfo, Turn};
+
+/// How long an engine has to exit after `quit` before it is killed
+const QUIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long past its flag instant an engine is waited on for its move
+const MOVE_GRACE: Duration = Duration::from_secs(1);
+
+/// A connection to an engine that speaks a line based text protocol, such as UCI or XBoard.
+/// Dropping it sends `quit`, which both protocols use to shut down
+pub struct ExternalProcess {
+    child: Option<Child>,
+    input: Box<dyn Write + Send>,
+    lines: Receiver<String>,
+}
+
+/// Keeps track of every player's clock for protocols that tell engines how much time is left.
+/// The player's own clock is taken from its flag instant, while opponents' clocks are estimated
+/// from how long they took to reply
+pub struct ClockTracker {
+    /// The time format of each player, indexed by color id
+    formats: Vec<TimeFormat>,
+    /// The nanoseconds left on each player's clock, indexed by color id
+    remaining: Vec<u64>,
+    /// The number of moves each player has made, indexed by color id
+    moves_made: Vec<u32>,
+    /// When the player last moved, which is when the next opponent's clock started
+    last_move: Option<DateTime<Utc>>,
+}
+
+impl ExternalProcess {
+    /// Launches an engine. `command` is the path to the engine executable followed by its
+    /// arguments, separated by whitespace
+    pub fn spawn(command: &str) -> io::Result<ExternalProcess> {
+        let mut parts = command.split_whitespace();
+        let program = parts
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty engine command"))?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let mut process = ExternalProcess::from_streams(stdout, stdin);
+        process.child = Some(child);
+        Ok(process)
+    }
+
+    /// Talks to an engine that reads commands from `input` and writes to `output`
+    pub fn from_streams<R, W>(output: R, input: W) -> ExternalProcess
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(output).lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(line).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+        ExternalProcess {
+            child: None,
+            input: Box::new(input),
+            lines,
+        }
+    }
+
+    /// Sends a single command
+    pub fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.input, "{}", command)?;
+        self.input.flush()
+    }
+
+    /// Waits for the next line. Gives up at `deadline`, or waits forever if there is none
+    pub fn next_line(&mut self, deadline: Option<Instant>) -> io::Result<String> {
+        match deadline {
+            Some(deadline) => {
+                self.lines
+                    .recv_timeout(time_left(deadline))
+                    .map_err(|err| match err {
+                        RecvTimeoutError::Timeout => io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "The engine did not reply in time",
+                        ),
+                        RecvTimeoutError::Disconnected => closed(),
+                    })
+            }
+            None => self.lines.recv().map_err(|_| closed()),
+        }
+    }
+
+    /// Waits for a line whose first word is `word`, skipping every other line. Gives up at
+    /// `deadline` however many lines were skipped
+    pub fn wait_for(&mut self, word: &str, deadline: Option<Instant>) -> io::Result<String> {
+        loop {
+            let line = self.next_line(deadline)?;
+            if line.split_whitespace().next() == Some(word) {
+                return Ok(line);
+            }
+        }
+    }
+}
+
+impl Drop for ExternalProcess {
+    /// Sends `quit` and waits briefly for the engine to exit, killing it if it doesn't
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let mut child = match self.child.take() {
+            Some(child) => child,
+            None => return,
+        };
+        let step = Duration::from_millis(10);
+        let mut waited = Duration::from_secs(0);
+        while waited < QUIT_TIMEOUT {
+            if let Ok(Some(_)) = child.try_wait() {
+                return;
+            }
+            thread::sleep(step);
+            waited += step;
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// How long until `deadline`, or nothing if it has passed
+fn time_left(deadline: Instant) -> Duration {
+    deadline.saturating_duration_since(Instant::now())
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "The engine exited")
+}
+
+/// Returns the action an engine chose, or resigns for an engine that failed to move. The failed
+/// engine is dropped, so that it isn't waited on again
+pub fn action_or_resign(
+    result: io::Result<Action>,
+    process: &mut Option<ExternalProcess>,
+    protocol: &str,
+) -> Action {
+    result.unwrap_or_else(|err| {
+        eprintln!("The {} engine failed to move: {}", protocol, err);
+        process.take();
+        Action::Resign
+    })
+}
+
+/// When to stop waiting for the move of a player taking `turn`. None if it has unlimited time
+pub fn move_deadline(turn: &Turn) -> Option<Instant> {
+    turn.flag_instant.map(|flag_instant| {
+        let left = (flag_instant - Utc::now())
+            .to_std()
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        Instant::now() + left + MOVE_GRACE
+    })
+}
+
+impl ClockTracker {
+    /// Starts every clock at its initial time
+    pub fn new(game: &GameInfo) -> ClockTracker {
+        let colors = game.start.kind().color_count() as usize;
+        let mut formats = vec![TimeFormat::Unlimited; colors];
+        formats[game.color.id() as usize] = game.time_format.clone();
+        for (color, (_, format)) in game.opponents.iter() {
+            if let Some(slot) = formats.get_mut(color.id() as usize) {
+                *slot = format.clone();
+            }
+        }
+        let remaining = formats
+            .iter()
+            .map(|format| format.initial_nanos().unwrap_or(0))
+            .collect();
+        ClockTracker {
+            formats,
+            remaining,
+            moves_made: vec![0; colors],
+            last_move: None,
+        }
+    }
+
+    /// The time format of `color`
+    pub fn format(&self, color: ColorKind) -> &TimeFormat {
+        &self.formats[color.id() as usize]
+    }
+
+    /// The number of the next move `color` makes, counting from 1
+    pub fn move_number(&self, color: ColorKind) -> u32 {
+        self.moves_made[color.id() as usize] + 1
+    }
+
+    /// The nanoseconds left on the clock of `color`, not counting any delay. None if the player
+    /// has unlimited time
+    pub fn remaining(&self, color: ColorKind) -> Option<u64> {
+        match self.format(color) {
+            TimeFormat::Unlimited => None,
+            _ => Some(self.remaining[color.id() as usize]),
+        }
+    }
+
+    /// The player is taking `turn`
+    pub fn start_turn(&mut self, turn: &Turn) {
+        let color = turn.board.to_move();
+        if let Some(flag_instant) = turn.flag_instant {
+            let left = (flag_instant - turn.move_start)
+                .num_nanoseconds()
+                .unwrap_or(0)
+                .max(0) as u64;
+            let delay = turn.time_format.delay_nanos(self.move_number(color));
+            self.remaining[color.id() as usize] = left.saturating_sub(delay);
+        }
+    }
+
+    /// The player has sent its move, starting the next opponent's clock
+    pub fn moved(&mut self, color: ColorKind) {
+        self.moves_made[color.id() as usize] += 1;
+        self.last_move = Some(Utc::now());
+    }
+
+    /// `opponent` moved, stopping their clock
+    pub fn opponent_moved(&mut self, opponent: ColorKind) {
+        let now = Utc::now();
+        let index = opponent.id() as usize;
+        let move_number = self.move_number(opponent);
+        let format = &self.formats[index];
+        if let (Some(last_move), Some(_)) = (self.last_move, format.initial_nanos()) {
+            let used = (now - last_move).num_nanoseconds().unwrap_or(0).max(0) as u64;
+            let charged = used.saturating_sub(format.delay_nanos(move_number));
+            self.remaining[index] =
+                format.after_move(self.remaining[index].saturating_sub(charged), move_number);
+        }
+        self.moves_made[index] += 1;
+        self.last_move = Some(now);
+    }
+}
@@ -1,3 +1,67 @@
+//! Runs one of the engines in this crate. The engine is chosen by the first argument, and
+//! defaults to material. `uci <command>` and `xboard <command>` play using the UCI or XBoard engine
+//! run by the rest of the arguments. Passing `--uci` or `--xboard` after an engine's name runs it
+//! as a UCI or XBoard engine instead, for use in chess GUIs. `--variant-file <path>` after the
+//! material engine's name loads a fairy game definition it then supports, and may be repeated
+
+mod external;
+mod material;
+mod uci_adapter;
+mod xboard_adapter;
+
+use std::io;
+use std::process;
+
+use giga_core::fairy;
+
 fn main() {
-    println!("Hello, world!");
+    let name = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "material".to_owned());
+    let command = std::env::args().skip(2).collect::<Vec<_>>().join(" ");
+    let result =
+        match name.as_str() {
+            "material" => material_options(std::env::args().skip(2)).and_then(|protocol| {
+                match protocol.as_deref() {
+                    Some("--uci") => giga_sdk::uci::run_uci(material::MaterialEngine),
+                    Some("--xboard") => giga_sdk::xboard::run_xboard(material::MaterialEngine),
+                    _ => giga_sdk::run(material::MaterialEngine),
+                }
+            }),
+            "uci" => uci_adapter::UciAdapter::spawn(&command).and_then(giga_sdk::run),
+            "xboard" => xboard_adapter::XBoardAdapter::spawn(&command).and_then(giga_sdk::run),
+            _ => {
+                eprintln!(
+                    "Unknown engine {}. Available engines: material, uci, xboard",
+                    name
+                );
+                process::exit(2);
+            }
+        };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Loads the fairy games named by `--variant-file` options, returning the protocol option if there
+/// is one
+fn material_options(mut args: impl Iterator<Item = String>) -> io::Result<Option<String>> {
+    let mut protocol = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--variant-file" => {
+                let path = args.next().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Missing value for --variant-file",
+                    )
+                })?;
+                fairy::load(path)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+            }
+            _ => protocol = Some(arg),
+        }
+    }
+    Ok(protocol)
 }
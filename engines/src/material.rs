@@ -0,0 +1,130 @@
+//! A greedy engine that plays whichever move leaves it with the most material
+
+use std::collections::HashMap;
+
+use smallvec::SmallVec;
+
+use giga_core::board::{Board, PieceKind};
+use giga_core::fairy;
+use giga_core::game::{ColorKind, GameEndCause, Kind, Variant};
+use giga_core::message::EngineInfo;
+use giga_sdk::{Action, Engine, GameInfo, Player, Turn};
+
+/// The value of a piece in centipawns. Kings aren't counted as they can't be captured. Fairy pieces
+/// move however their game defines them, so they are all counted like a pawn
+pub fn piece_value(kind: PieceKind) -> i32 {
+    match kind {
+        PieceKind::Queen => 900,
+        PieceKind::Elephant => 850,
+        PieceKind::Rook => 500,
+        PieceKind::Dragon => 450,
+        PieceKind::Bear | PieceKind::Moose => 350,
+        PieceKind::Bishop => 330,
+        PieceKind::Knight | PieceKind::Horse => 320,
+        PieceKind::Pawn | PieceKind::Weasel | PieceKind::Fairy(_) => 100,
+        _ => 0,
+    }
+}
+
+/// The material of `color` minus the material of its opponents. Royal pieces aren't counted
+pub fn material(board: &Board, color: ColorKind) -> i32 {
+    board
+        .pieces()
+        .filter(|(_, piece)| !board.is_royal(piece.kind))
+        .map(|(_, piece)| {
+            let value = piece_value(piece.kind);
+            if piece.color == color {
+                value
+            } else {
+                -value
+            }
+        })
+        .sum()
+}
+
+pub struct MaterialEngine;
+
+impl Engine for MaterialEngine {
+    fn info(&self) -> EngineInfo {
+        EngineInfo {
+            name: "Material".to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            description: "Plays the move that wins the most material right away".to_owned(),
+            ..EngineInfo::default()
+        }
+    }
+
+    fn supported_games(&self) -> HashMap<Kind, SmallVec<[Variant; 2]>> {
+        // Moves come from the board, which already follows every variant
+        let mut games = HashMap::new();
+        games.insert(
+            Kind::Chess,
+            [Variant::Chess960, Variant::NoCastling]
+                .iter()
+                .copied()
+                .collect(),
+        );
+        games.insert(Kind::ContrastingChess, SmallVec::new());
+        // Fairy games loaded with --variant-file
+        for kind in fairy::registered() {
+            games.insert(kind, [Variant::NoCastling].iter().copied().collect());
+        }
+        games
+    }
+
+    fn new_player(&mut self, _: &GameInfo) -> Box<dyn Player> {
+        Box::new(MaterialEngine)
+    }
+}
+
+impl Player for MaterialEngine {
+    fn your_move(&mut self, turn: &Turn) -> Action {
+        let board = turn.board;
+        let color = board.to_move();
+        let mut scratch = board.clone();
+        let best = board.legal_moves().into_iter().max_by_key(|m| {
+            let undo = scratch.make_move(*m);
+            let score = match scratch.game_end() {
+                Some(GameEndCause::Checkmate | GameEndCause::RoyalCaptured) => i32::MAX,
+                Some(_) => 0,
+                None => material(&scratch, color),
+            };
+            scratch.unmake_move(*m, undo);
+            score
+        });
+        match best {
+            Some(m) => Action::Move(m),
+            None => Action::Resign,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use giga_core::game::{Kind, TimeFormat};
+    use std::sync::atomic::AtomicBool;
+
+    fn best_move(fen: &str) -> String {
+        let board = Board::from_fen(Kind::Chess, fen).unwrap();
+        let turn = Turn {
+            board: &board,
+            move_start: Utc::now(),
+            flag_instant: None,
+            time_format: TimeFormat::Unlimited,
+            max_depth: None,
+            stop: &AtomicBool::new(false),
+        };
+        match MaterialEngine.your_move(&turn) {
+            Action::Move(m) => board.to_uci(m).to_string(),
+            action => panic!("Expected a move, got {:?}", action),
+        }
+    }
+
+    #[test]
+    fn captures_and_mates() {
+        assert_eq!(best_move("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1"), "d2d5");
+        assert_eq!(best_move("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), "a1a8");
+    }
+}
@@ -0,0 +1,508 @@
+//! Lets engines that speak the Universal Chess Interface play GigaChess games.
+//! Every game gets its own UCI engine process. The position is sent as the start position followed
+//! by every move played so far, and each player's clock is converted into the `wtime`, `btime`,
+//! `winc` and `binc` arguments of `go`. UCI has no notion of a delay, so a delay is reported as
+//! part of the increment
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use giga_core::board::{Board, CastleSide, Move};
+use giga_core::game::{ColorKind, Kind, TimeFormat};
+use giga_core::message::EngineInfo;
+use giga_sdk::uci::uci_move;
+use giga_sdk::{Action, Engine, GameInfo, Player, Turn};
+
+use crate::external::{action_or_resign, move_deadline, ClockTracker, ExternalProcess};
+
+/// How long a UCI engine has to answer `uci` and `isready`
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a UCI engine may think for when its player has unlimited time
+pub const UNLIMITED_MOVETIME_MS: u64 = 1000;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+/// A player's clock in the form UCI describes it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UciClock {
+    /// The time left in milliseconds
+    pub time_ms: u64,
+    /// The time added after each move in milliseconds
+    pub inc_ms: u64,
+    /// The moves left until time is next added at the end of a period, if it will be
+    pub moves_to_go: Option<u32>,
+}
+
+/// A GigaChess engine that plays using a UCI engine
+pub struct UciAdapter {
+    launch: Box<dyn FnMut() -> io::Result<ExternalProcess>>,
+    info: EngineInfo,
+    /// The process that was launched to learn the engine's name, kept for the first game
+    idle: Option<ExternalProcess>,
+}
+
+/// Plays one game through a UCI engine. If the engine couldn't be launched every turn is resigned
+pub struct UciPlayer {
+    process: Option<ExternalProcess>,
+    start: Board,
+    chess960: bool,
+    /// Every move played so far in UCI notation
+    moves: Vec<String>,
+    /// The board after every move in `moves`
+    board: Board,
+    clocks: ClockTracker,
+}
+
+impl UciClock {
+    /// Converts a clock in `format` with `remaining_nanos` left before its player's `move_number`th
+    /// move. None if the time is unlimited
+    pub fn new(format: &TimeFormat, remaining_nanos: u64, move_number: u32) -> Option<UciClock> {
+        format.initial_nanos()?;
+        let inc_nanos = format.increment_nanos(move_number) + format.delay_nanos(move_number);
+        Some(UciClock {
+            time_ms: remaining_nanos / NANOS_PER_MILLI,
+            inc_ms: inc_nanos / NANOS_PER_MILLI,
+            moves_to_go: format.moves_to_go(move_number),
+        })
+    }
+}
+
+/// Builds the `go` command for the player `to_move`. `clocks` is indexed by color id. Players
+/// without a clock are left out, and if the player to move has unlimited time the engine is given
+/// `UNLIMITED_MOVETIME_MS` instead
+pub fn go_command(to_move: ColorKind, clocks: &[Option<UciClock>]) -> String {
+    let own = match clocks.get(to_move.id() as usize).copied().flatten() {
+        Some(own) => own,
+        None => return format!("go movetime {}", UNLIMITED_MOVETIME_MS),
+    };
+    let mut command = "go".to_owned();
+    for (prefix, clock) in ["w", "b"].iter().zip(clocks) {
+        if let Some(clock) = clock {
+            command.push_str(&format!(
+                " {}time {} {}inc {}",
+                prefix, clock.time_ms, prefix, clock.inc_ms
+            ));
+        }
+    }
+    if let Some(moves_to_go) = own.moves_to_go {
+        command.push_str(&format!(" movestogo {}", moves_to_go));
+    }
+    command
+}
+
+/// Builds the `position` command for a game that started at `start` and has had `moves` played
+pub fn position_command(start: &Board, moves: &[String]) -> String {
+    let mut command = if *start == Board::start_position(Kind::Chess) {
+        "position startpos".to_owned()
+    } else {
+        format!("position fen {}", start.to_fen())
+    };
+    if !moves.is_empty() {
+        command.push_str(" moves ");
+        command.push_str(&moves.join(" "));
+    }
+    command
+}
+
+/// Returns true if castling in `start` has to be described the Chess960 way, with the king moving
+/// onto its rook
+pub fn is_chess960(start: &Board) -> bool {
+    let castling = start.castling();
+    let standard = [
+        (CastleSide::Queen, 0),
+        (CastleSide::King, start.side_len() - 1),
+    ];
+    [ColorKind::WHITE, ColorKind::BLACK].iter().any(|color| {
+        let king_file = start
+            .king_square(*color)
+            .map(|king| start.file_rank(king).0);
+        standard
+            .iter()
+            .any(|(side, rook_file)| match castling.get(*color, *side) {
+                Some(file) => file != *rook_file || king_file != Some(4),
+                None => false,
+            })
+    })
+}
+
+/// Reads the move out of a `bestmove` line. None if the engine has no move, or named an illegal
+/// one
+pub fn parse_bestmove(board: &Board, line: &str) -> Option<Move> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("bestmove") {
+        return None;
+    }
+    board.parse_uci(words.next()?).ok()
+}
+
+/// Sends `uci` and collects the engine's name and author, then waits for it to be ready
+fn handshake(process: &mut ExternalProcess) -> io::Result<EngineInfo> {
+    process.send("uci")?;
+    let mut info = EngineInfo::default();
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    loop {
+        let line = process.next_line(Some(deadline))?;
+        let mut words = line.splitn(3, ' ');
+        match (words.next(), words.next(), words.next()) {
+            (Some("uciok"), _, _) => break,
+            (Some("id"), Some("name"), Some(name)) => info.name = name.trim().to_owned(),
+            (Some("id"), Some("author"), Some(author)) => info.author = author.trim().to_owned(),
+            _ => {}
+        }
+    }
+    wait_ready(process)?;
+    Ok(info)
+}
+
+/// Sends `isready` and waits for `readyok`
+fn wait_ready(process: &mut ExternalProcess) -> io::Result<()> {
+    process.send("isready")?;
+    process.wait_for("readyok", Some(Instant::now() + HANDSHAKE_TIMEOUT))?;
+    Ok(())
+}
+
+impl UciAdapter {
+    /// Wraps the UCI engine run by `command`, which is the path to the executable followed by its
+    /// arguments
+    pub fn spawn(command: &str) -> io::Result<UciAdapter> {
+        let command = command.to_owned();
+        UciAdapter::with_launcher(move || ExternalProcess::spawn(&command))
+    }
+
+    /// Wraps the UCI engines created by `launch`, which is called once for every game. The first
+    /// engine is launched right away to learn its name
+    pub fn with_launcher<F>(mut launch: F) -> io::Result<UciAdapter>
+    where
+        F: FnMut() -> io::Result<ExternalProcess> + 'static,
+    {
+        let mut process = launch()?;
+        let mut info = handshake(&mut process)?;
+        info.description = format!("{} (UCI)", info.name);
+        info.version = env!("CARGO_PKG_VERSION").to_owned();
+        Ok(UciAdapter {
+            launch: Box::new(launch),
+            info,
+            idle: Some(process),
+        })
+    }
+
+    fn start_game(&mut self, chess960: bool) -> io::Result<ExternalProcess> {
+        let mut process = match self.idle.take() {
+            Some(process) => process,
+            None => {
+                let mut process = (self.launch)()?;
+                handshake(&mut process)?;
+                process
+            }
+        };
+        if chess960 {
+            process.send("setoption name UCI_Chess960 value true")?;
+        }
+        process.send("ucinewgame")?;
+        wait_ready(&mut process)?;
+        Ok(process)
+    }
+}
+
+impl Engine for UciAdapter {
+    fn info(&self) -> EngineInfo {
+        self.info.clone()
+    }
+
+    fn new_player(&mut self, game: &GameInfo) -> Box<dyn Player> {
+        let chess960 = is_chess960(&game.start);
+        let process = match self.start_game(chess960) {
+            Ok(process) => Some(process),
+            Err(err) => {
+                eprintln!("Failed to start a UCI engine for game {}: {}", game.id, err);
+                None
+            }
+        };
+        Box::new(UciPlayer {
+            process,
+            start: game.start.clone(),
+            chess960,
+            moves: Vec::new(),
+            board: game.start.clone(),
+            clocks: ClockTracker::new(game),
+        })
+    }
+}
+
+impl UciPlayer {
+    fn record_move(&mut self, m: Move) {
+        self.moves.push(uci_move(&self.board, m, self.chess960));
+        self.board.apply_move(m);
+    }
+
+    fn clocks(&self) -> Vec<Option<UciClock>> {
+        (0..self.board.kind().color_count())
+            .map(ColorKind::new)
+            .map(|color| {
+                let remaining = self.clocks.remaining(color)?;
+                UciClock::new(
+                    self.clocks.format(color),
+                    remaining,
+                    self.clocks.move_number(color),
+                )
+            })
+            .collect()
+    }
+
+    fn think(&mut self, turn: &Turn) -> io::Result<Option<Move>> {
+        let color = turn.board.to_move();
+        self.clocks.start_turn(turn);
+        let position = position_command(&self.start, &self.moves);
+        let go = go_command(color, &self.clocks());
+        let deadline = move_deadline(turn);
+
+        let process = match self.process.as_mut() {
+            Some(process) => process,
+            None => return Ok(None),
+        };
+        process.send(&position)?;
+        process.send(&go)?;
+        let line = process.wait_for("bestmove", deadline)?;
+        Ok(parse_bestmove(turn.board, &line))
+    }
+}
+
+impl Player for UciPlayer {
+    fn your_move(&mut self, turn: &Turn) -> Action {
+        let result = self
+            .think(turn)
+            .map(|m| m.map_or(Action::Resign, Action::Move));
+        let action = action_or_resign(result, &mut self.process, "UCI");
+        if let Action::Move(m) = action {
+            self.record_move(m);
+        }
+        self.clocks.moved(turn.board.to_move());
+        action
+    }
+
+    fn opponent_move(&mut self, _: &Board, m: Move, opponent: ColorKind) {
+        self.clocks.opponent_moved(opponent);
+        self.record_move(m);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use giga_core::message::Protocol;
+    use giga_core::pgn;
+    use smallvec::SmallVec;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc::{self, Sender};
+    use std::thread;
+
+    const MINUTE: u64 = 60_000_000_000;
+    const SECOND: u64 = 1_000_000_000;
+
+    /// A UCI engine that plays the first legal move and reports every command it receives. If it
+    /// `thinks_forever`, it prints search info after `go` instead and never moves
+    fn fake_engine(commands: Sender<String>, thinks_forever: bool) -> ExternalProcess {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let mut output = theirs.try_clone().unwrap();
+        thread::spawn(move || {
+            let mut board = Board::start_position(Kind::Chess);
+            for line in BufReader::new(theirs).lines() {
+                let line = line.unwrap();
+                let mut words = line.split_whitespace();
+                let reply = match words.next() {
+                    Some("uci") => "id name Fake\nid author Nobody\nuciok".to_owned(),
+                    Some("isready") => "readyok".to_owned(),
+                    Some("position") => {
+                        let rest: Vec<&str> = words.collect();
+                        let moves = rest.iter().position(|w| *w == "moves");
+                        let (setup, moves) = match moves {
+                            Some(i) => (&rest[..i], &rest[i + 1..]),
+                            None => (&rest[..], &[][..]),
+                        };
+                        board = match setup {
+                            ["startpos"] => Board::start_position(Kind::Chess),
+                            _ => Board::from_fen(Kind::Chess, &setup[1..].join(" ")).unwrap(),
+                        };
+                        for m in moves {
+                            let m = board.parse_uci(m).unwrap();
+                            board.apply_move(m);
+                        }
+                        String::new()
+                    }
+                    Some("go") if thinks_forever => {
+                        let mut output = output.try_clone().unwrap();
+                        thread::spawn(move || {
+                            let mut depth = 1;
+                            while writeln!(output, "info depth {}", depth).is_ok() {
+                                depth += 1;
+                                thread::sleep(Duration::from_millis(20));
+                            }
+                        });
+                        String::new()
+                    }
+                    Some("go") => format!(
+                        "info depth 1\nbestmove {}",
+                        board.to_uci(board.legal_moves()[0])
+                    ),
+                    Some("quit") => break,
+                    _ => String::new(),
+                };
+                let _ = commands.send(line.clone());
+                if !reply.is_empty() {
+                    writeln!(output, "{}", reply).unwrap();
+                }
+            }
+        });
+        ExternalProcess::from_streams(ours.try_clone().unwrap(), ours)
+    }
+
+    #[test]
+    fn clock_conversion() {
+        let format = TimeFormat::Timed {
+            initial_nanos: MINUTE,
+            increment_nanos: 2 * SECOND,
+            delay_nanos: SECOND,
+        };
+        let clock = UciClock::new(&format, 1_500_999_999, 1);
+        assert_eq!(
+            clock,
+            Some(UciClock {
+                time_ms: 1500,
+                inc_ms: 3000,
+                moves_to_go: None,
+            })
+        );
+        assert_eq!(UciClock::new(&TimeFormat::Unlimited, 0, 1), None);
+
+        let other = UciClock {
+            time_ms: 60_000,
+            inc_ms: 0,
+            moves_to_go: None,
+        };
+        assert_eq!(
+            go_command(ColorKind::BLACK, &[clock, Some(other)]),
+            "go wtime 1500 winc 3000 btime 60000 binc 0"
+        );
+        assert_eq!(
+            go_command(ColorKind::WHITE, &[None, Some(other)]),
+            format!("go movetime {}", UNLIMITED_MOVETIME_MS)
+        );
+
+        // 40 moves in 90 minutes, then 30 minutes for the rest of the game
+        let classical = pgn::parse_time_control("40/5400:1800").unwrap();
+        let clock = UciClock::new(&classical, MINUTE, 38);
+        assert_eq!(clock.and_then(|clock| clock.moves_to_go), Some(3));
+        assert_eq!(
+            go_command(ColorKind::WHITE, &[clock, None]),
+            "go wtime 60000 winc 0 movestogo 3"
+        );
+        let clock = UciClock::new(&classical, MINUTE, 41);
+        assert_eq!(clock.and_then(|clock| clock.moves_to_go), None);
+    }
+
+    /// A game of standard chess where the adapter plays black
+    fn black_game(format: &TimeFormat) -> GameInfo {
+        let mut opponents = HashMap::new();
+        opponents.insert(ColorKind::WHITE, (EngineInfo::default(), format.clone()));
+        GameInfo {
+            id: 1,
+            start: Board::start_position(Kind::Chess),
+            variants: SmallVec::new(),
+            color: ColorKind::BLACK,
+            time_format: format.clone(),
+            opponents,
+            protocol: Protocol::legacy(),
+        }
+    }
+
+    #[test]
+    fn plays_through_uci() {
+        let (sender, commands) = mpsc::channel();
+        let mut launches = vec![fake_engine(sender, false)];
+        let mut adapter = UciAdapter::with_launcher(move || Ok(launches.pop().unwrap())).unwrap();
+        assert_eq!(adapter.info().name, "Fake");
+        assert_eq!(adapter.info().author, "Nobody");
+
+        let format = TimeFormat::Timed {
+            initial_nanos: MINUTE,
+            increment_nanos: SECOND,
+            delay_nanos: 0,
+        };
+        let game = black_game(&format);
+        let mut player = adapter.new_player(&game);
+
+        let mut board = game.start.clone();
+        let e4 = board.parse_uci("e2e4").unwrap();
+        board.apply_move(e4);
+        player.opponent_move(&board, e4, ColorKind::WHITE);
+
+        let move_start = Utc::now();
+        let turn = Turn {
+            board: &board,
+            move_start,
+            flag_instant: Some(move_start + chrono::Duration::seconds(30)),
+            time_format: format.clone(),
+            max_depth: None,
+            stop: &AtomicBool::new(false),
+        };
+        let expected = board.to_uci(board.legal_moves()[0]).to_string();
+        match player.your_move(&turn) {
+            Action::Move(m) => assert_eq!(board.to_uci(m).to_string(), expected),
+            action => panic!("Expected a move, got {:?}", action),
+        }
+
+        let commands: Vec<String> = commands.try_iter().collect();
+        let go = commands.iter().position(|c| c.starts_with("go")).unwrap();
+        assert_eq!(commands[go - 1], "position startpos moves e2e4");
+        // White moved before black's clock started, so no time was taken off
+        assert_eq!(
+            commands[go],
+            "go wtime 60000 winc 1000 btime 30000 binc 1000"
+        );
+        assert!(commands.contains(&"ucinewgame".to_owned()));
+    }
+
+    #[test]
+    fn gives_up_after_the_flag() {
+        let (sender, _commands) = mpsc::channel();
+        let mut launches = vec![fake_engine(sender, true)];
+        let mut adapter = UciAdapter::with_launcher(move || Ok(launches.pop().unwrap())).unwrap();
+        let format = TimeFormat::Timed {
+            initial_nanos: SECOND,
+            increment_nanos: 0,
+            delay_nanos: 0,
+        };
+        let game = black_game(&format);
+        let mut player = adapter.new_player(&game);
+
+        let mut board = game.start.clone();
+        board.apply_move(board.parse_uci("e2e4").unwrap());
+        let move_start = Utc::now();
+        let turn = Turn {
+            board: &board,
+            move_start,
+            flag_instant: Some(move_start),
+            time_format: format,
+            max_depth: None,
+            stop: &AtomicBool::new(false),
+        };
+        // The info lines keep coming, but the engine is only waited on until its grace period ends
+        let waited = std::time::Instant::now();
+        assert!(matches!(player.your_move(&turn), Action::Resign));
+        assert!(waited.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn chess960_detection() {
+        let board = Board::from_fen(Kind::Chess, "4k3/8/8/8/8/8/8/1K1R4 w D - 0 1").unwrap();
+        assert!(is_chess960(&board));
+        assert!(!is_chess960(&Board::start_position(Kind::Chess)));
+        let board = Board::from_fen(Kind::Chess, "4k3/8/8/8/8/8/8/1K6 w - - 0 1").unwrap();
+        assert!(!is_chess960(&board));
+    }
+}
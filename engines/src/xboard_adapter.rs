@@ -0,0 +1,646 @@
+//! Lets engines that speak the Chess Engine Communication Protocol (XBoard) play GigaChess games.
+//! Every game gets its own engine process, which is kept in force mode until its first move and
+//! then sent `go`. The clocks are sent with `time` and `otim` before every move, and the time
+//! format with `level`. XBoard has no notion of a delay, so a delay is reported as part of the
+//! increment. An engine that claims a result instead of moving resigns, as the moderator decides
+//! how games end
+
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+use smallvec::SmallVec;
+
+use giga_core::board::{Board, Move};
+use giga_core::game::{ColorKind, GameEndCause, Kind, TimeFormat, Variant};
+use giga_core::message::EngineInfo;
+use giga_sdk::xboard::{parse_xboard_move, xboard_move};
+use giga_sdk::{Action, Engine, GameInfo, Player, Turn};
+
+use crate::external::{action_or_resign, move_deadline, ClockTracker, ExternalProcess};
+use crate::uci_adapter::is_chess960;
+
+/// How long an engine that has said it isn't done sending features has to finish
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long an engine has to send its features before it is assumed to speak protocol version 1
+const FEATURE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long an engine has to accept a draw offer
+const DRAW_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long an engine may think for when its player has unlimited time, in seconds
+pub const UNLIMITED_MOVE_SECONDS: u64 = 1;
+
+const NANOS_PER_CENTI: u64 = 10_000_000;
+
+/// The features an XBoard engine sent during the handshake. Features that weren't sent have their
+/// protocol version 1 defaults
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XBoardFeatures {
+    pub name: Option<String>,
+    pub variants: Vec<String>,
+    pub setboard: bool,
+    pub usermove: bool,
+    pub san: bool,
+    pub draw: bool,
+}
+
+/// A GigaChess engine that plays using an XBoard engine
+pub struct XBoardAdapter {
+    launch: Box<dyn FnMut() -> io::Result<ExternalProcess>>,
+    info: EngineInfo,
+    features: XBoardFeatures,
+    /// The process that was launched to learn the engine's features, kept for the first game
+    idle: Option<ExternalProcess>,
+}
+
+/// Plays one game through an XBoard engine. If the engine couldn't be set up every turn is
+/// resigned
+pub struct XBoardPlayer {
+    process: Option<ExternalProcess>,
+    features: XBoardFeatures,
+    chess960: bool,
+    color: ColorKind,
+    board: Board,
+    /// Moves the engine hasn't been sent yet, already written in its notation
+    pending: Vec<String>,
+    /// Whether the engine has left force mode and plays its color
+    started: bool,
+    clocks: ClockTracker,
+}
+
+impl Default for XBoardFeatures {
+    fn default() -> XBoardFeatures {
+        XBoardFeatures {
+            name: None,
+            variants: vec!["normal".to_owned()],
+            setboard: false,
+            usermove: false,
+            san: false,
+            draw: true,
+        }
+    }
+}
+
+/// Splits the arguments of a `feature` command into names and values. Values may be quoted to
+/// include spaces
+pub fn parse_features(s: &str) -> Vec<(String, String)> {
+    let mut features = Vec::new();
+    let mut rest = s.trim();
+    while let Some(equals) = rest.find('=') {
+        let name = rest[..equals].trim().to_owned();
+        rest = &rest[equals + 1..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            rest = quoted.get(end + 1..).unwrap_or("");
+            &quoted[..end]
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+        features.push((name, value.to_owned()));
+        rest = rest.trim_start();
+    }
+    features
+}
+
+/// Formats a time format as a `level` command, or `st` for a fixed time per move or unlimited
+/// time. `level` only has room for a single period, so later periods with different times are
+/// approximated by repeating the first
+pub fn level_command(format: &TimeFormat) -> String {
+    let level = |moves: u32, nanos: u64, increment_nanos: u64| {
+        let seconds = nanos / 1_000_000_000;
+        let increment = increment_nanos as f64 / 1e9;
+        format!(
+            "level {} {}:{:02} {}",
+            moves,
+            seconds / 60,
+            seconds % 60,
+            increment
+        )
+    };
+    match format {
+        TimeFormat::Timed {
+            initial_nanos,
+            increment_nanos,
+            delay_nanos,
+        } => level(0, *initial_nanos, increment_nanos + delay_nanos),
+        TimeFormat::Periods { periods } => match periods.first() {
+            Some(first) => level(
+                first.moves.unwrap_or(0),
+                first.nanos,
+                first.increment_nanos + first.delay_nanos,
+            ),
+            None => level(0, 0, 0),
+        },
+        TimeFormat::PerMove { nanos_per_move } => {
+            format!("st {}", *nanos_per_move as f64 / 1e9)
+        }
+        TimeFormat::Unlimited => format!("st {}", UNLIMITED_MOVE_SECONDS),
+    }
+}
+
+/// Sends `xboard` and `protover 2`, then collects the engine's features, accepting the ones that
+/// are understood
+fn handshake(process: &mut ExternalProcess) -> io::Result<XBoardFeatures> {
+    process.send("xboard")?;
+    process.send("protover 2")?;
+    let mut features = XBoardFeatures::default();
+    let mut deadline = Instant::now() + FEATURE_TIMEOUT;
+    let mut done = false;
+    loop {
+        let line = match process.next_line(Some(deadline)) {
+            Ok(line) => line,
+            // Version 1 engines don't send features at all
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => return Ok(features),
+            Err(err) => return Err(err),
+        };
+        let rest = match line.strip_prefix("feature ") {
+            Some(rest) => rest,
+            None => continue,
+        };
+        for (name, value) in parse_features(rest) {
+            let enabled = value == "1";
+            let known = match name.as_str() {
+                "myname" => {
+                    features.name = Some(value.clone());
+                    true
+                }
+                "variants" => {
+                    features.variants = value.split(',').map(str::to_owned).collect();
+                    true
+                }
+                "setboard" => {
+                    features.setboard = enabled;
+                    true
+                }
+                "usermove" => {
+                    features.usermove = enabled;
+                    true
+                }
+                "san" => {
+                    features.san = enabled;
+                    true
+                }
+                "draw" => {
+                    features.draw = enabled;
+                    true
+                }
+                "done" => {
+                    done = enabled;
+                    deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+                    true
+                }
+                // Features that change nothing for how the adapter talks to the engine
+                "ping" | "sigint" | "sigterm" | "reuse" | "analyze" | "colors" | "time"
+                | "debug" | "memory" | "smp" | "nps" | "pause" | "playother" | "ics" | "name" => {
+                    true
+                }
+                _ => false,
+            };
+            let reply = if known { "accepted" } else { "rejected" };
+            process.send(&format!("{} {}", reply, name))?;
+        }
+        if done {
+            return Ok(features);
+        }
+    }
+}
+
+impl XBoardAdapter {
+    /// Wraps the XBoard engine run by `command`, which is the path to the executable followed by
+    /// its arguments
+    pub fn spawn(command: &str) -> io::Result<XBoardAdapter> {
+        let command = command.to_owned();
+        XBoardAdapter::with_launcher(move || ExternalProcess::spawn(&command))
+    }
+
+    /// Wraps the XBoard engines created by `launch`, which is called once for every game. The
+    /// first engine is launched right away to learn its features
+    pub fn with_launcher<F>(mut launch: F) -> io::Result<XBoardAdapter>
+    where
+        F: FnMut() -> io::Result<ExternalProcess> + 'static,
+    {
+        let mut process = launch()?;
+        let features = handshake(&mut process)?;
+        let name = features
+            .name
+            .clone()
+            .unwrap_or_else(|| "XBoard engine".to_owned());
+        let info = EngineInfo {
+            description: format!("{} (XBoard)", name),
+            name,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            ..EngineInfo::default()
+        };
+        Ok(XBoardAdapter {
+            launch: Box::new(launch),
+            info,
+            features,
+            idle: Some(process),
+        })
+    }
+
+    fn supports_chess960(&self) -> bool {
+        self.features
+            .variants
+            .iter()
+            .any(|variant| variant == "fischerandom")
+    }
+
+    /// Sets up an engine to play from `game`'s start position, in force mode
+    fn start_game(&mut self, game: &GameInfo, chess960: bool) -> io::Result<ExternalProcess> {
+        let mut process = match self.idle.take() {
+            Some(process) => process,
+            None => {
+                let mut process = (self.launch)()?;
+                handshake(&mut process)?;
+                process
+            }
+        };
+        process.send("new")?;
+        if chess960 {
+            process.send("variant fischerandom")?;
+        }
+        process.send("force")?;
+        process.send("easy")?;
+        if chess960 || game.start != Board::start_position(Kind::Chess) {
+            if !self.features.setboard {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The engine can't be set up from a position without feature setboard",
+                ));
+            }
+            process.send(&format!("setboard {}", game.start.to_fen()))?;
+        }
+        process.send(&level_command(&game.time_format))?;
+        Ok(process)
+    }
+}
+
+impl Engine for XBoardAdapter {
+    fn info(&self) -> EngineInfo {
+        self.info.clone()
+    }
+
+    fn supported_games(&self) -> HashMap<Kind, SmallVec<[Variant; 2]>> {
+        let mut variants = SmallVec::new();
+        if self.supports_chess960() {
+            variants.push(Variant::Chess960);
+        }
+        let mut games = HashMap::new();
+        games.insert(Kind::Chess, variants);
+        games
+    }
+
+    fn new_player(&mut self, game: &GameInfo) -> Box<dyn Player> {
+        let chess960 = is_chess960(&game.start);
+        let process = match self.start_game(game, chess960) {
+            Ok(process) => Some(process),
+            Err(err) => {
+                eprintln!(
+                    "Failed to start an XBoard engine for game {}: {}",
+                    game.id, err
+                );
+                None
+            }
+        };
+        Box::new(XBoardPlayer {
+            process,
+            features: self.features.clone(),
+            chess960,
+            color: game.color,
+            board: game.start.clone(),
+            pending: Vec::new(),
+            started: false,
+            clocks: ClockTracker::new(game),
+        })
+    }
+}
+
+impl XBoardPlayer {
+    fn record_move(&mut self, m: Move) {
+        self.board.apply_move(m);
+    }
+
+    /// Sends the time left for the engine and its opponent, in centiseconds
+    fn send_clocks(&mut self) -> io::Result<()> {
+        let opponent = self.board.next_color(self.color);
+        let own = self.clocks.remaining(self.color);
+        let other = self.clocks.remaining(opponent);
+        let process = match self.process.as_mut() {
+            Some(process) => process,
+            None => return Ok(()),
+        };
+        if let Some(own) = own {
+            process.send(&format!("time {}", own / NANOS_PER_CENTI))?;
+        }
+        if let Some(other) = other {
+            process.send(&format!("otim {}", other / NANOS_PER_CENTI))?;
+        }
+        Ok(())
+    }
+
+    fn think(&mut self, turn: &Turn) -> io::Result<Action> {
+        self.clocks.start_turn(turn);
+        self.send_clocks()?;
+        let deadline = move_deadline(turn);
+        let usermove = self.features.usermove;
+        let started = self.started;
+        let pending: Vec<String> = self.pending.drain(..).collect();
+        let process = match self.process.as_mut() {
+            Some(process) => process,
+            None => return Ok(Action::Resign),
+        };
+        for m in pending {
+            if usermove {
+                process.send(&format!("usermove {}", m))?;
+            } else {
+                process.send(&m)?;
+            }
+        }
+        if !started {
+            process.send("go")?;
+            self.started = true;
+        }
+
+        let mut offer_draw = false;
+        loop {
+            let line = process.next_line(deadline)?;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("move") => {
+                    let m = words
+                        .next()
+                        .and_then(|m| parse_xboard_move(turn.board, m))
+                        .ok_or_else(|| {
+                            io::Error::new(io::ErrorKind::InvalidData, format!("Bad {}", line))
+                        })?;
+                    return Ok(if offer_draw {
+                        Action::MoveAndOfferDraw(m)
+                    } else {
+                        Action::Move(m)
+                    });
+                }
+                Some("offer") if words.next() == Some("draw") => offer_draw = true,
+                Some("resign") | Some("1-0") | Some("0-1") | Some("1/2-1/2") => {
+                    return Ok(Action::Resign)
+                }
+                Some("Illegal") | Some("Error") => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, line))
+                }
+                // Thinking output, comments and messages for the user
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Player for XBoardPlayer {
+    fn your_move(&mut self, turn: &Turn) -> Action {
+        let action = action_or_resign(self.think(turn), &mut self.process, "XBoard");
+        if let Action::Move(m) | Action::MoveAndOfferDraw(m) = action {
+            self.record_move(m);
+        }
+        self.clocks.moved(turn.board.to_move());
+        action
+    }
+
+    fn opponent_move(&mut self, _: &Board, m: Move, opponent: ColorKind) {
+        self.clocks.opponent_moved(opponent);
+        self.pending.push(xboard_move(
+            &self.board,
+            m,
+            self.chess960,
+            self.features.san,
+        ));
+        self.record_move(m);
+    }
+
+    fn draw_offer(&mut self, _: &Board, _: ColorKind) -> bool {
+        if !self.features.draw {
+            return false;
+        }
+        let process = match self.process.as_mut() {
+            Some(process) => process,
+            None => return false,
+        };
+        if process.send("draw").is_err() {
+            return false;
+        }
+        let deadline = Instant::now() + DRAW_TIMEOUT;
+        while let Ok(line) = process.next_line(Some(deadline)) {
+            if line.trim() == "offer draw" {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn game_over(&mut self, winner: Option<ColorKind>, cause: &GameEndCause) {
+        let result = match winner {
+            Some(ColorKind::WHITE) => "1-0",
+            Some(_) => "0-1",
+            None => "1/2-1/2",
+        };
+        if let Some(process) = self.process.as_mut() {
+            let _ = process.send(&format!("result {} {{{:?}}}", result, cause));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use giga_core::message::Protocol;
+    use giga_core::pgn;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc::{self, Sender};
+    use std::thread;
+
+    const MINUTE: u64 = 60_000_000_000;
+
+    /// An XBoard engine that plays the first legal move, accepts draws, and reports every command
+    /// it receives. If it `thinks_forever`, it prints thinking output instead and never moves
+    fn fake_engine(commands: Sender<String>, thinks_forever: bool) -> ExternalProcess {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let mut output = theirs.try_clone().unwrap();
+        thread::spawn(move || {
+            let mut board = Board::start_position(Kind::Chess);
+            let mut force = false;
+            for line in BufReader::new(theirs).lines() {
+                let line = line.unwrap();
+                let mut words = line.split_whitespace();
+                let mut reply = String::new();
+                match words.next() {
+                    Some("protover") => {
+                        reply = "feature done=0\nfeature myname=\"Fake Board\" \
+                                 variants=\"normal,fischerandom\" usermove=1 setboard=1 \
+                                 colors=0 frobnicate=1\nfeature done=1"
+                            .to_owned()
+                    }
+                    Some("new") => board = Board::start_position(Kind::Chess),
+                    Some("force") => force = true,
+                    Some("setboard") => {
+                        board = Board::from_fen(Kind::Chess, &words.collect::<Vec<_>>().join(" "))
+                            .unwrap()
+                    }
+                    Some("draw") => reply = "offer draw".to_owned(),
+                    Some("usermove") => {
+                        let m = board.parse_uci(words.next().unwrap()).unwrap();
+                        board.apply_move(m);
+                    }
+                    Some("go") => force = false,
+                    Some("quit") => break,
+                    _ => {}
+                }
+                let _ = commands.send(line.clone());
+                let thinks = matches!(line.split_whitespace().next(), Some("go" | "usermove"));
+                if thinks && !force && thinks_forever {
+                    let mut output = output.try_clone().unwrap();
+                    thread::spawn(move || {
+                        let mut depth = 1;
+                        while writeln!(output, "{} 0 0 0 e7e5", depth).is_ok() {
+                            depth += 1;
+                            thread::sleep(Duration::from_millis(20));
+                        }
+                    });
+                } else if thinks && !force {
+                    let m = board.legal_moves()[0];
+                    reply = format!("# thinking\nmove {}", board.to_uci(m));
+                    board.apply_move(m);
+                }
+                if !reply.is_empty() {
+                    writeln!(output, "{}", reply).unwrap();
+                }
+            }
+        });
+        ExternalProcess::from_streams(ours.try_clone().unwrap(), ours)
+    }
+
+    /// A game of standard chess where the adapter plays black
+    fn black_game(format: &TimeFormat) -> GameInfo {
+        let mut opponents = HashMap::new();
+        opponents.insert(ColorKind::WHITE, (EngineInfo::default(), format.clone()));
+        GameInfo {
+            id: 1,
+            start: Board::start_position(Kind::Chess),
+            variants: SmallVec::new(),
+            color: ColorKind::BLACK,
+            time_format: format.clone(),
+            opponents,
+            protocol: Protocol::legacy(),
+        }
+    }
+
+    #[test]
+    fn plays_through_xboard() {
+        let (sender, commands) = mpsc::channel();
+        let mut launches = vec![fake_engine(sender, false)];
+        let mut adapter =
+            XBoardAdapter::with_launcher(move || Ok(launches.pop().unwrap())).unwrap();
+        assert_eq!(adapter.info().name, "Fake Board");
+        assert!(adapter.supported_games()[&Kind::Chess].contains(&Variant::Chess960));
+
+        let format = TimeFormat::Timed {
+            initial_nanos: MINUTE,
+            increment_nanos: 500_000_000,
+            delay_nanos: 0,
+        };
+        let game = black_game(&format);
+        let mut player = adapter.new_player(&game);
+
+        let mut board = game.start.clone();
+        let e4 = board.parse_uci("e2e4").unwrap();
+        board.apply_move(e4);
+        player.opponent_move(&board, e4, ColorKind::WHITE);
+        assert!(player.draw_offer(&board, ColorKind::WHITE));
+
+        let move_start = Utc::now();
+        let turn = Turn {
+            board: &board,
+            move_start,
+            flag_instant: Some(move_start + chrono::Duration::seconds(30)),
+            time_format: format.clone(),
+            max_depth: None,
+            stop: &AtomicBool::new(false),
+        };
+        let expected = board.legal_moves()[0];
+        assert_eq!(player.your_move(&turn), Action::Move(expected));
+
+        let commands: Vec<String> = commands.try_iter().collect();
+        let start = commands.iter().position(|c| c == "new").unwrap();
+        assert_eq!(
+            &commands[start..],
+            [
+                "new",
+                "force",
+                "easy",
+                "level 0 1:00 0.5",
+                "draw",
+                "time 3000",
+                "otim 6000",
+                "usermove e2e4",
+                "go",
+            ]
+        );
+        assert!(commands.contains(&"rejected frobnicate".to_owned()));
+    }
+
+    #[test]
+    fn gives_up_after_the_flag() {
+        let (sender, _commands) = mpsc::channel();
+        let mut launches = vec![fake_engine(sender, true)];
+        let mut adapter =
+            XBoardAdapter::with_launcher(move || Ok(launches.pop().unwrap())).unwrap();
+        let format = TimeFormat::Timed {
+            initial_nanos: 1_000_000_000,
+            increment_nanos: 0,
+            delay_nanos: 0,
+        };
+        let game = black_game(&format);
+        let mut player = adapter.new_player(&game);
+
+        let mut board = game.start.clone();
+        let e4 = board.parse_uci("e2e4").unwrap();
+        board.apply_move(e4);
+        player.opponent_move(&board, e4, ColorKind::WHITE);
+        let move_start = Utc::now();
+        let turn = Turn {
+            board: &board,
+            move_start,
+            flag_instant: Some(move_start),
+            time_format: format,
+            max_depth: None,
+            stop: &AtomicBool::new(false),
+        };
+        // The thinking output keeps coming, but the engine is only waited on until its grace
+        // period ends
+        let waited = Instant::now();
+        assert_eq!(player.your_move(&turn), Action::Resign);
+        assert!(waited.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn features() {
+        assert_eq!(
+            parse_features("myname=\"Some Engine 1.0\" san=1  done=0"),
+            [
+                ("myname".to_owned(), "Some Engine 1.0".to_owned()),
+                ("san".to_owned(), "1".to_owned()),
+                ("done".to_owned(), "0".to_owned()),
+            ]
+        );
+        assert_eq!(level_command(&TimeFormat::Unlimited), "st 1");
+        let classical = pgn::parse_time_control("40/5400+30:1800+30").unwrap();
+        assert_eq!(level_command(&classical), "level 40 90:00 30");
+        let per_move = pgn::parse_time_control("2.5/move").unwrap();
+        assert_eq!(level_command(&per_move), "st 2.5");
+    }
+}
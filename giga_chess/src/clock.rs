@@ -0,0 +1,351 @@
+//! The clocks of a game being moderated. Time is measured with a monotonic `TimeSource`, so
+//! changes to the system clock can't make anyone lose on time, and tests can control it.
+//!
+//! A timed player's clock runs from the moment they are told it is their move until their move
+//! arrives. With a delay, the clock only starts ticking down once the delay has passed, so a move
+//! made within the delay costs no time at all. After every move the clock is topped up as the
+//! player's `TimeFormat` describes, with an increment, the time of a new period, or a fresh time
+//! per move. A player whose clock reaches zero before they move loses on time
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+
+use giga_core::game::{Clock, Clocks, ColorKind, TimeFormat};
+
+/// Where the clocks of a game get the current time from
+pub trait TimeSource: Send + Sync {
+    /// The current monotonic time, which is what clocks are measured with
+    fn now(&self) -> Instant;
+
+    /// The current wall clock time. Only read when a game starts, to turn monotonic instants into
+    /// the timestamps sent to players and written to game records
+    fn wall_clock(&self) -> DateTime<Utc>;
+}
+
+/// The operating system's clocks
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemTimeSource;
+
+/// A time source that stands still until it is told to advance. Clones share the same time
+#[derive(Clone)]
+pub struct MockTimeSource {
+    start: Instant,
+    wall_start: DateTime<Utc>,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+/// The clocks of every player in a game
+pub struct GameClock {
+    clocks: Clocks,
+    source: Arc<dyn TimeSource>,
+    /// A monotonic instant and the wall clock time it corresponds to
+    origin: (Instant, DateTime<Utc>),
+    /// The player whose clock is running, and when it started
+    running: Option<(ColorKind, Instant)>,
+}
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_clock(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+impl MockTimeSource {
+    /// Creates a time source whose wall clock reads `wall_start`
+    pub fn new(wall_start: DateTime<Utc>) -> MockTimeSource {
+        MockTimeSource {
+            start: Instant::now(),
+            wall_start,
+            elapsed: Arc::new(Mutex::new(Duration::from_secs(0))),
+        }
+    }
+
+    /// Moves time forward by `by`
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl TimeSource for MockTimeSource {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn wall_clock(&self) -> DateTime<Utc> {
+        self.wall_start + chrono::Duration::from_std(self.elapsed()).expect("Mock time overflowed")
+    }
+}
+
+impl fmt::Debug for MockTimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockTimeSource")
+            .field("wall_clock", &self.wall_clock())
+            .finish()
+    }
+}
+
+impl GameClock {
+    /// Creates stopped clocks with the initial time of each player. `time_formats` is indexed by
+    /// color id
+    pub fn new(time_formats: &[TimeFormat], source: Arc<dyn TimeSource>) -> GameClock {
+        let clocks = Clocks::new(
+            time_formats
+                .iter()
+                .map(|format| Clock::new(format.clone()))
+                .collect(),
+        );
+        let origin = (source.now(), source.wall_clock());
+        GameClock {
+            clocks,
+            source,
+            origin,
+            running: None,
+        }
+    }
+
+    /// The clocks as of the last move
+    pub fn clocks(&self) -> &Clocks {
+        &self.clocks
+    }
+
+    /// The clocks right now, including the time used so far by the player whose clock is running
+    pub fn current(&self) -> Clocks {
+        let mut clocks = self.clocks.clone();
+        if let Some((color, _)) = self.running {
+            let charged = self.charged(self.source.now());
+            if let Some(clock) = clocks.get_clock_mut(color) {
+                if let Some(remaining) = clock.nanos_on_clock.as_mut() {
+                    *remaining = remaining.saturating_sub(charged);
+                }
+            }
+        }
+        clocks
+    }
+
+    /// The current wall clock time
+    pub fn now(&self) -> DateTime<Utc> {
+        self.to_wall_clock(self.source.now())
+    }
+
+    /// Starts the clock of `color`, who was just told it is their move
+    pub fn start(&mut self, color: ColorKind) {
+        self.running = Some((color, self.source.now()));
+    }
+
+    /// Stops the running clock because its player moved, charging them for the time they used
+    /// beyond the delay and adding whatever their time format adds after the move
+    pub fn stop(&mut self) {
+        let (color, _) = match self.running {
+            Some(running) => running,
+            None => return,
+        };
+        let now = self.source.now();
+        let charged = self.charged(now);
+        let time = self.to_wall_clock(now);
+        self.running = None;
+        let clock = self
+            .clocks
+            .get_clock_mut(color)
+            .expect("Every player has a clock");
+        clock.times.push(time);
+        let move_number = clock.times.len() as u32;
+        if let Some(remaining) = clock.nanos_on_clock {
+            let remaining = remaining.saturating_sub(charged);
+            clock.nanos_on_clock = Some(clock.time_format.after_move(remaining, move_number));
+        }
+    }
+
+    /// The instant the running clock reaches zero. None if no clock is running or its player has
+    /// unlimited time
+    pub fn flag_instant(&self) -> Option<DateTime<Utc>> {
+        self.deadline().map(|deadline| self.to_wall_clock(deadline))
+    }
+
+    /// How long until the running clock reaches zero, which is no time at all if it already has.
+    /// None if no clock is running or its player has unlimited time
+    pub fn time_to_flag(&self) -> Option<Duration> {
+        self.deadline()
+            .map(|deadline| deadline.saturating_duration_since(self.source.now()))
+    }
+
+    /// The player whose clock is running, if it has reached zero
+    pub fn flagged(&self) -> Option<ColorKind> {
+        match (self.running, self.deadline()) {
+            (Some((color, _)), Some(deadline)) if self.source.now() >= deadline => Some(color),
+            _ => None,
+        }
+    }
+
+    /// The monotonic instant the running clock reaches zero
+    fn deadline(&self) -> Option<Instant> {
+        let (color, started) = self.running?;
+        let clock = self.clocks.get_clock(color)?;
+        let remaining = clock.nanos_on_clock?;
+        let delay = clock.time_format.delay_nanos(clock.times.len() as u32 + 1);
+        Some(started + Duration::from_nanos(remaining.saturating_add(delay)))
+    }
+
+    /// The nanoseconds the running player would be charged if they moved at `now`
+    fn charged(&self, now: Instant) -> u64 {
+        let (color, started) = match self.running {
+            Some(running) => running,
+            None => return 0,
+        };
+        let used = now.saturating_duration_since(started).as_nanos() as u64;
+        match self.clocks.get_clock(color) {
+            Some(clock) => {
+                used.saturating_sub(clock.time_format.delay_nanos(clock.times.len() as u32 + 1))
+            }
+            None => used,
+        }
+    }
+
+    fn to_wall_clock(&self, instant: Instant) -> DateTime<Utc> {
+        let (origin, wall_origin) = self.origin;
+        let elapsed = instant.saturating_duration_since(origin);
+        wall_origin + chrono::Duration::from_std(elapsed).expect("Games don't last for centuries")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use giga_core::game::TimePeriod;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn clock(time_format: TimeFormat) -> (GameClock, MockTimeSource) {
+        let source = MockTimeSource::new(Utc.timestamp_opt(0, 0).unwrap());
+        let clock = GameClock::new(
+            &[time_format.clone(), time_format],
+            Arc::new(source.clone()),
+        );
+        (clock, source)
+    }
+
+    fn remaining(clock: &GameClock, color: ColorKind) -> Option<u64> {
+        clock.clocks().get_clock(color).unwrap().nanos_on_clock
+    }
+
+    #[test]
+    fn delay_and_increment() {
+        let (mut clock, source) = clock(TimeFormat::Timed {
+            initial_nanos: 10 * SECOND,
+            increment_nanos: SECOND,
+            delay_nanos: 2 * SECOND,
+        });
+        clock.start(ColorKind::WHITE);
+        assert_eq!(clock.time_to_flag(), Some(Duration::from_secs(12)));
+        // Moving within the delay costs nothing
+        source.advance(Duration::from_secs(1));
+        clock.stop();
+        assert_eq!(remaining(&clock, ColorKind::WHITE), Some(11 * SECOND));
+
+        clock.start(ColorKind::BLACK);
+        source.advance(Duration::from_secs(5));
+        assert_eq!(
+            clock
+                .current()
+                .get_clock(ColorKind::BLACK)
+                .unwrap()
+                .nanos_on_clock,
+            Some(7 * SECOND)
+        );
+        clock.stop();
+        assert_eq!(remaining(&clock, ColorKind::BLACK), Some(8 * SECOND));
+        assert_eq!(
+            clock.clocks().get_clock(ColorKind::BLACK).unwrap().times,
+            vec![Utc.timestamp_opt(6, 0).unwrap()]
+        );
+    }
+
+    #[test]
+    fn flagging() {
+        let (mut clock, source) = clock(TimeFormat::Timed {
+            initial_nanos: 3 * SECOND,
+            increment_nanos: 0,
+            delay_nanos: SECOND,
+        });
+        assert_eq!(clock.flagged(), None);
+        clock.start(ColorKind::WHITE);
+        assert_eq!(clock.flag_instant(), Some(Utc.timestamp_opt(4, 0).unwrap()));
+        source.advance(Duration::from_millis(3999));
+        assert_eq!(clock.flagged(), None);
+        source.advance(Duration::from_millis(1));
+        assert_eq!(clock.flagged(), Some(ColorKind::WHITE));
+        assert_eq!(clock.time_to_flag(), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn unlimited() {
+        let (mut clock, source) = clock(TimeFormat::Unlimited);
+        clock.start(ColorKind::WHITE);
+        source.advance(Duration::from_secs(1_000_000));
+        assert_eq!(clock.flagged(), None);
+        assert_eq!(clock.flag_instant(), None);
+        clock.stop();
+        assert_eq!(remaining(&clock, ColorKind::WHITE), None);
+        assert_eq!(
+            clock
+                .clocks()
+                .get_clock(ColorKind::WHITE)
+                .unwrap()
+                .times
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn periods_and_time_per_move() {
+        let (mut clock, source) = clock(TimeFormat::Periods {
+            periods: vec![
+                TimePeriod {
+                    moves: Some(2),
+                    nanos: 10 * SECOND,
+                    increment_nanos: 0,
+                    delay_nanos: 0,
+                },
+                TimePeriod {
+                    moves: None,
+                    nanos: 5 * SECOND,
+                    increment_nanos: SECOND,
+                    delay_nanos: 0,
+                },
+            ],
+        });
+        for _ in 0..2 {
+            clock.start(ColorKind::WHITE);
+            source.advance(Duration::from_secs(4));
+            clock.stop();
+        }
+        // Two seconds were left after the second move, when the next period's time was added
+        assert_eq!(remaining(&clock, ColorKind::WHITE), Some(7 * SECOND));
+        clock.start(ColorKind::WHITE);
+        source.advance(Duration::from_secs(4));
+        clock.stop();
+        assert_eq!(remaining(&clock, ColorKind::WHITE), Some(4 * SECOND));
+
+        let (mut clock, source) = self::clock(TimeFormat::PerMove {
+            nanos_per_move: 5 * SECOND,
+        });
+        clock.start(ColorKind::BLACK);
+        source.advance(Duration::from_secs(1));
+        clock.stop();
+        // Unused time doesn't carry over
+        assert_eq!(remaining(&clock, ColorKind::BLACK), Some(5 * SECOND));
+        clock.start(ColorKind::BLACK);
+        assert_eq!(clock.time_to_flag(), Some(Duration::from_secs(5)));
+    }
+}
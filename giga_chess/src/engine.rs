@@ -0,0 +1,243 @@
+//! Engine processes and the stdin/stdout half of the protocol
+
+use std::collections::HashMap;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use smallvec::SmallVec;
+
+use giga_core::game::{Kind, Variant};
+use giga_core::message::{
+    encode, Encoder, EngineInfo, Feature, FrameError, In, MessageReader, Out, Protocol,
+    PROTOCOL_VERSION,
+};
+
+use crate::Error;
+
+/// How long an engine has to respond to `In::EngineInit`
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The protocol versions the moderator can speak. `LEGACY_PROTOCOL_VERSION` isn't one of them:
+/// engines speaking it expect board strings instead of FENs, and can't describe every time format
+/// or promotion, so they are refused rather than sent messages they would misread
+pub const PROTOCOL_VERSIONS: [u32; 1] = [PROTOCOL_VERSION];
+
+/// The optional features the moderator implements
+pub const FEATURES: [Feature; 1] = [Feature::DrawOffers];
+
+/// How long an engine has to exit after `In::EngineShutdown` before it is killed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A running engine that has completed the `EngineInit` handshake
+pub struct EngineProcess {
+    child: Child,
+    stdin: Encoder<ChildStdin>,
+    messages: Receiver<Result<Out, FrameError>>,
+    info: EngineInfo,
+    supported_games: HashMap<Kind, SmallVec<[Variant; 2]>>,
+    protocol: Protocol,
+}
+
+impl EngineProcess {
+    /// Launches an engine. `command` is the path to the engine executable followed by its
+    /// arguments, separated by whitespace.
+    /// Returns once the engine has replied to `In::EngineInit` with its info and a protocol version
+    /// has been agreed on. Engines that have no protocol version in common with the moderator are
+    /// sent an `In::InvalidRequest` explaining why and shut down
+    pub fn spawn(command: &str) -> Result<EngineProcess, Error> {
+        let mut parts = command.split_whitespace();
+        let program = parts
+            .next()
+            .ok_or_else(|| Error::Handshake("Empty engine command".to_owned()))?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            for message in MessageReader::new(stdout) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = EngineProcess {
+            child,
+            stdin: Encoder::new(stdin),
+            messages,
+            info: EngineInfo::default(),
+            supported_games: HashMap::new(),
+            protocol: Protocol::legacy(),
+        };
+        engine.send(&In::EngineInit {
+            protocol_versions: PROTOCOL_VERSIONS.to_vec(),
+            features: FEATURES.to_vec(),
+        })?;
+        // Invalid messages don't restart the clock, so an engine can't stall the handshake forever
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match engine.messages.recv_timeout(timeout) {
+                Ok(Ok(reply)) => {
+                    let request_json = String::from_utf8_lossy(&encode(&reply)).trim().to_owned();
+                    let Out::EngineInfo {
+                        info,
+                        supported_games,
+                        protocol_versions,
+                        features,
+                    } = reply;
+                    let protocol = Protocol::negotiate(
+                        &PROTOCOL_VERSIONS,
+                        &FEATURES,
+                        &protocol_versions,
+                        &features,
+                    );
+                    let protocol = match protocol {
+                        Some(protocol) => protocol,
+                        None => {
+                            let message = format!(
+                                "No protocol version in common. The engine supports {:?}, and the moderator supports {:?}",
+                                protocol_versions, PROTOCOL_VERSIONS
+                            );
+                            let _ = engine.send(&In::InvalidRequest {
+                                message: message.clone(),
+                                request_json,
+                                related_game: None,
+                            });
+                            engine.shutdown();
+                            return Err(Error::Handshake(format!("{}: {}", info.name, message)));
+                        }
+                    };
+                    engine.send(&In::ProtocolAccepted {
+                        protocol: protocol.clone(),
+                    })?;
+                    engine.info = info;
+                    engine.supported_games = supported_games;
+                    engine.protocol = protocol;
+                    return Ok(engine);
+                }
+                Ok(Err(err)) => match err.to_invalid_request(None) {
+                    Some(invalid_request) => engine.send(&invalid_request)?,
+                    None => return Err(Error::Handshake(err.to_string())),
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    let _ = engine.child.kill();
+                    return Err(Error::Handshake(format!(
+                        "{} did not send EngineInfo within {:?}",
+                        command, HANDSHAKE_TIMEOUT
+                    )));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::Handshake(format!(
+                        "{} exited before sending EngineInfo",
+                        command
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Sends a message to the engine on its stdin
+    pub fn send(&mut self, message: &In) -> Result<(), Error> {
+        self.stdin.send(message)?;
+        Ok(())
+    }
+
+    pub fn info(&self) -> &EngineInfo {
+        &self.info
+    }
+
+    /// The protocol version and features agreed on with this engine
+    pub fn protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+
+    /// Returns true if the engine advertised support for `kind` with all of `variants`. An empty
+    /// list of variants is the stock version of the game
+    pub fn supports(&self, kind: Kind, variants: &[Variant]) -> bool {
+        self.supported_games
+            .get(&kind)
+            .is_some_and(|supported| variants.iter().all(|variant| supported.contains(variant)))
+    }
+
+    /// Sends `In::EngineShutdown` and waits briefly for the engine to exit, killing it if it
+    /// doesn't
+    pub fn shutdown(mut self) {
+        let _ = self.send(&In::EngineShutdown);
+        let step = Duration::from_millis(10);
+        let mut waited = Duration::from_secs(0);
+        while waited < SHUTDOWN_TIMEOUT {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(step);
+            waited += step;
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Writes a shell script engine that answers `In::EngineInit` with `reply` and then exits on
+    /// `In::EngineShutdown`
+    fn script_engine(name: &str, reply: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "giga_chess-test-{}-{}.sh",
+            std::process::id(),
+            name
+        ));
+        fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\nread line\necho '{}'\nwhile read line; do\n  case \"$line\" in *EngineShutdown*) exit ;; esac\ndone\n",
+                reply
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    const INFO: &str = r#""info":{"name":"Script","version":"","description":"","author":"","repo":""},"supported_games":{"Chess":[]}"#;
+
+    #[test]
+    fn legacy_engines_are_refused() {
+        // Engines that don't list any versions speak the legacy protocol
+        let command = script_engine("legacy", &format!(r#"{{"type":"EngineInfo",{}}}"#, INFO));
+        match EngineProcess::spawn(&command) {
+            Err(Error::Handshake(message)) => assert!(message.contains("No protocol version")),
+            Err(err) => panic!("Unexpected error {}", err),
+            Ok(_) => panic!("A legacy engine was accepted"),
+        }
+        let _ = fs::remove_file(command);
+    }
+
+    #[test]
+    fn current_engines_are_accepted() {
+        let command = script_engine(
+            "current",
+            &format!(
+                r#"{{"type":"EngineInfo",{},"protocol_versions":[{}],"features":[]}}"#,
+                INFO, PROTOCOL_VERSION
+            ),
+        );
+        let engine = EngineProcess::spawn(&command).unwrap();
+        assert_eq!(engine.protocol().version, PROTOCOL_VERSION);
+        assert!(engine.supports(Kind::Chess, &[]));
+        engine.shutdown();
+        let _ = fs::remove_file(command);
+    }
+}
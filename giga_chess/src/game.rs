@@ -0,0 +1,497 @@
+//! The rules side of a game being moderated. `GameState` consumes the messages players send and
+//! produces the messages each player should receive, without doing any I/O itself
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::prelude::*;
+
+use giga_core::board::Board;
+use giga_core::game::{ColorKind, GameEndCause, TimeFormat, ID};
+use giga_core::history::PositionHistory;
+use giga_core::message::{EngineInfo, GameIn, GameOut};
+use giga_core::pgn::{GameRecord, GameResult};
+
+use crate::clock::{GameClock, TimeSource};
+
+/// A message that should be delivered to the player with the given color
+pub type Outgoing = (ColorKind, GameIn);
+
+/// A game in progress
+pub struct GameState {
+    id: ID,
+    board: Board,
+    history: PositionHistory,
+    record: GameRecord,
+    /// The players with a pending draw offer. An offer stands until another player moves
+    draw_offers: Vec<ColorKind>,
+    clock: GameClock,
+}
+
+impl GameState {
+    /// Creates a game starting from `start`. `players` and `time_formats` are indexed by color id.
+    /// The clocks are measured with `time_source`
+    pub fn new(
+        id: ID,
+        start: Board,
+        players: Vec<EngineInfo>,
+        time_formats: &[TimeFormat],
+        time_source: Arc<dyn TimeSource>,
+    ) -> GameState {
+        let clock = GameClock::new(time_formats, time_source);
+        let mut record = GameRecord::new(start.clone(), players, clock.now());
+        record.clocks = Some(clock.clocks().clone());
+        GameState {
+            id,
+            history: PositionHistory::new(&start),
+            board: start,
+            record,
+            draw_offers: Vec::new(),
+            clock,
+        }
+    }
+
+    pub fn id(&self) -> ID {
+        self.id
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn record(&self) -> &GameRecord {
+        &self.record
+    }
+
+    /// None while the game is in progress
+    pub fn result(&self) -> Option<&GameResult> {
+        self.record.result.as_ref()
+    }
+
+    pub fn to_move(&self) -> ColorKind {
+        self.board.to_move()
+    }
+
+    /// The instant the player to move runs out of time, or None if they have unlimited time
+    pub fn flag_instant(&self) -> Option<DateTime<Utc>> {
+        self.clock.flag_instant()
+    }
+
+    /// How long until the player to move runs out of time, or None if they have unlimited time
+    pub fn time_to_flag(&self) -> Option<Duration> {
+        self.clock.time_to_flag()
+    }
+
+    /// Starts the game, telling the first player to move
+    pub fn start(&mut self) -> Vec<Outgoing> {
+        if let Some(cause) = self.history.game_end(&self.board) {
+            return self.end(self.winner_by(cause.clone()), cause);
+        }
+        self.start_turn()
+    }
+
+    fn start_turn(&mut self) -> Vec<Outgoing> {
+        self.clock.start(self.to_move());
+        let flag_instant = self.flag_instant().unwrap_or(DateTime::<Utc>::MAX_UTC);
+        vec![(self.to_move(), GameIn::YourMove { flag_instant })]
+    }
+
+    /// Ends the game on time if the player to move has run out of time. It's a draw if the player
+    /// who would win couldn't checkmate
+    pub fn check_flag(&mut self) -> Vec<Outgoing> {
+        match self.clock.flagged() {
+            Some(loser) if self.result().is_none() => {
+                let winner = self.next(loser);
+                if self.cannot_checkmate(winner) {
+                    self.end(None, GameEndCause::Flag)
+                } else {
+                    self.end(Some(winner), GameEndCause::Flag)
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Handles a message sent by `color`, returning the messages that should be sent in response
+    pub fn handle(&mut self, color: ColorKind, message: GameOut) -> Vec<Outgoing> {
+        if self.result().is_some() {
+            return Vec::new();
+        }
+        match message {
+            GameOut::Move(raw) => {
+                let flagged = self.check_flag();
+                if !flagged.is_empty() {
+                    return flagged;
+                }
+                let m = match self.board.move_from_raw(&raw) {
+                    Some(m) if color == self.to_move() => m,
+                    _ => return self.end(Some(self.next(color)), GameEndCause::IllegalMove(raw)),
+                };
+                self.clock.stop();
+                self.record.clocks = Some(self.clock.clocks().clone());
+                self.board.apply_move(m);
+                self.history.push(&self.board);
+                self.record.moves.push(raw);
+                self.draw_offers.retain(|offer| *offer == color);
+
+                let mut outgoing: Vec<Outgoing> = self
+                    .others(color)
+                    .map(|opponent| {
+                        let message = GameIn::OpponentMove {
+                            opponent_move: raw,
+                            opponent: color,
+                        };
+                        (opponent, message)
+                    })
+                    .collect();
+                match self.history.game_end(&self.board) {
+                    Some(cause) => outgoing.extend(self.end(self.winner_by(cause.clone()), cause)),
+                    None => outgoing.extend(self.start_turn()),
+                }
+                outgoing
+            }
+            GameOut::Resign | GameOut::Err { .. } => {
+                self.end(Some(self.next(color)), GameEndCause::Resign)
+            }
+            GameOut::DrawOffer => {
+                // An offer from the player to move claims the draw if the rules allow it
+                if color == self.to_move() {
+                    if let Some(cause) = self.history.claimable_draw(&self.board) {
+                        return self.end(None, cause);
+                    }
+                }
+                if !self.draw_offers.contains(&color) {
+                    self.draw_offers.push(color);
+                }
+                if self.draw_offers.len() as u32 == self.board.kind().color_count() {
+                    return self.end(None, GameEndCause::DrawOffer);
+                }
+                self.others(color)
+                    .map(|opponent| (opponent, GameIn::OpponentDrawOffer { player: color }))
+                    .collect()
+            }
+            GameOut::RejectDrawOffer => {
+                self.draw_offers.clear();
+                Vec::new()
+            }
+            GameOut::GetClocks => vec![(color, GameIn::Clocks(self.clock.current()))],
+        }
+    }
+
+    /// Ends the game because `color` disconnected from its game socket, which is treated as
+    /// resigning
+    pub fn disconnected(&mut self, color: ColorKind) -> Vec<Outgoing> {
+        if self.result().is_some() {
+            return Vec::new();
+        }
+        self.end(Some(self.next(color)), GameEndCause::Resign)
+    }
+
+    fn end(&mut self, winner: Option<ColorKind>, cause: GameEndCause) -> Vec<Outgoing> {
+        // The time used by the player to move counts, so a flagged player ends with nothing left
+        self.record.clocks = Some(self.clock.current());
+        self.record.result = Some(GameResult {
+            winner,
+            cause: Some(cause.clone()),
+        });
+        (0..self.board.kind().color_count())
+            .map(|id| {
+                let message = GameIn::GameOver {
+                    winner,
+                    cause: cause.clone(),
+                };
+                (ColorKind::new(id), message)
+            })
+            .collect()
+    }
+
+    /// The winner of a game that ended on the board with the player to move unable to continue
+    fn winner_by(&self, cause: GameEndCause) -> Option<ColorKind> {
+        match cause {
+            GameEndCause::Checkmate | GameEndCause::RoyalCaptured => {
+                Some(self.next(self.to_move()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if `color` has too little material to checkmate, even with the help of the other
+    /// players. Only their royal pieces are kept, so that they can't block their own escape squares
+    fn cannot_checkmate(&self, color: ColorKind) -> bool {
+        let mut board = self.board.clone();
+        for (pos, piece) in self.board.pieces() {
+            if piece.color != color && !self.board.is_royal(piece.kind) {
+                board.set(pos, None);
+            }
+        }
+        board.insufficient_material()
+    }
+
+    /// The color that moves after `color`
+    fn next(&self, color: ColorKind) -> ColorKind {
+        ColorKind::new((color.id() + 1) % self.board.kind().color_count())
+    }
+
+    fn others(&self, color: ColorKind) -> impl Iterator<Item = ColorKind> {
+        (0..self.board.kind().color_count())
+            .map(ColorKind::new)
+            .filter(move |other| *other != color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockTimeSource;
+    use giga_core::game::{Kind, RawMove};
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn epoch() -> DateTime<Utc> {
+        Utc.timestamp_opt(0, 0).unwrap()
+    }
+
+    fn game(time_format: TimeFormat) -> GameState {
+        timed_game(time_format).0
+    }
+
+    fn timed_game(time_format: TimeFormat) -> (GameState, MockTimeSource) {
+        timed_game_from(Board::start_position(Kind::Chess), time_format)
+    }
+
+    fn timed_game_from(start: Board, time_format: TimeFormat) -> (GameState, MockTimeSource) {
+        let players = vec![EngineInfo::default(), EngineInfo::default()];
+        let source = MockTimeSource::new(epoch());
+        let game = GameState::new(
+            1,
+            start,
+            players,
+            &[time_format.clone(), time_format],
+            Arc::new(source.clone()),
+        );
+        (game, source)
+    }
+
+    fn raw(board: &Board, uci: &str) -> RawMove {
+        board.to_raw_move(board.parse_uci(uci).unwrap())
+    }
+
+    fn play(game: &mut GameState, uci: &str) -> Vec<Outgoing> {
+        let raw = raw(game.board(), uci);
+        game.handle(game.to_move(), GameOut::Move(raw))
+    }
+
+    fn game_over(outgoing: &[Outgoing]) -> Option<(Option<ColorKind>, GameEndCause)> {
+        outgoing.iter().find_map(|(_, message)| match message {
+            GameIn::GameOver { winner, cause } => Some((*winner, cause.clone())),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn fools_mate() {
+        let mut game = game(TimeFormat::Unlimited);
+        let outgoing = game.start();
+        assert!(matches!(
+            outgoing.as_slice(),
+            [(ColorKind::WHITE, GameIn::YourMove { .. })]
+        ));
+        for uci in ["f2f3", "e7e5", "g2g4"].iter() {
+            let outgoing = play(&mut game, uci);
+            assert_eq!(outgoing.len(), 2);
+            assert_eq!(game_over(&outgoing), None);
+        }
+        let outgoing = play(&mut game, "d8h4");
+        assert_eq!(
+            game_over(&outgoing),
+            Some((Some(ColorKind::BLACK), GameEndCause::Checkmate))
+        );
+        assert_eq!(game.record().moves.len(), 4);
+    }
+
+    #[test]
+    fn illegal_moves() {
+        let mut game = game(TimeFormat::Unlimited);
+        game.start();
+        // Black moving out of turn
+        let raw = raw(&Board::start_position(Kind::Chess), "e2e4");
+        let outgoing = game.handle(ColorKind::BLACK, GameOut::Move(raw));
+        assert_eq!(
+            game_over(&outgoing),
+            Some((Some(ColorKind::WHITE), GameEndCause::IllegalMove(raw)))
+        );
+
+        let mut game = self::game(TimeFormat::Unlimited);
+        game.start();
+        let raw = RawMove::new(
+            game.board().parse_square("e2").unwrap(),
+            game.board().parse_square("e5").unwrap(),
+        );
+        let outgoing = game.handle(ColorKind::WHITE, GameOut::Move(raw));
+        assert_eq!(
+            game_over(&outgoing),
+            Some((Some(ColorKind::BLACK), GameEndCause::IllegalMove(raw)))
+        );
+        // Nothing happens after the game is over
+        assert!(game.handle(ColorKind::BLACK, GameOut::Resign).is_empty());
+    }
+
+    #[test]
+    fn draw_offers() {
+        let mut game = game(TimeFormat::Unlimited);
+        game.start();
+        let outgoing = game.handle(ColorKind::WHITE, GameOut::DrawOffer);
+        assert!(matches!(
+            outgoing.as_slice(),
+            [(
+                ColorKind::BLACK,
+                GameIn::OpponentDrawOffer {
+                    player: ColorKind::WHITE
+                }
+            )]
+        ));
+        // An offer stands after the player offering moves, but is declined by the opponent moving
+        play(&mut game, "e2e4");
+        play(&mut game, "e7e5");
+        let outgoing = game.handle(ColorKind::BLACK, GameOut::DrawOffer);
+        assert_eq!(game_over(&outgoing), None);
+        play(&mut game, "g1f3");
+        play(&mut game, "b8c6");
+        let outgoing = game.handle(ColorKind::WHITE, GameOut::DrawOffer);
+        assert_eq!(game_over(&outgoing), None);
+        let outgoing = game.handle(ColorKind::BLACK, GameOut::DrawOffer);
+        assert_eq!(game_over(&outgoing), Some((None, GameEndCause::DrawOffer)));
+    }
+
+    #[test]
+    fn repetition() {
+        let mut game = game(TimeFormat::Unlimited);
+        game.start();
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        for uci in shuffle.iter().chain(shuffle.iter()) {
+            play(&mut game, uci);
+        }
+        // The position has occurred three times, so white's offer claims the draw
+        let outgoing = game.handle(ColorKind::WHITE, GameOut::DrawOffer);
+        assert_eq!(
+            game_over(&outgoing),
+            Some((None, GameEndCause::ThreefoldRepetition))
+        );
+
+        let mut game = self::game(TimeFormat::Unlimited);
+        game.start();
+        for _ in 0..4 {
+            for uci in shuffle.iter() {
+                play(&mut game, uci);
+            }
+        }
+        assert_eq!(
+            game.result().and_then(|result| result.cause.clone()),
+            Some(GameEndCause::FivefoldRepetition)
+        );
+    }
+
+    #[test]
+    fn clocks() {
+        let (mut game, source) = timed_game(TimeFormat::Timed {
+            initial_nanos: 10 * SECOND,
+            increment_nanos: SECOND,
+            delay_nanos: 0,
+        });
+        game.start();
+        assert_eq!(
+            game.flag_instant(),
+            Some(epoch() + chrono::Duration::seconds(10))
+        );
+
+        source.advance(Duration::from_secs(3));
+        let outgoing = play(&mut game, "e2e4");
+        let clock = game
+            .record()
+            .clocks
+            .as_ref()
+            .unwrap()
+            .get_clock(ColorKind::WHITE)
+            .unwrap();
+        assert_eq!(clock.nanos_on_clock, Some(8 * SECOND));
+        assert_eq!(clock.times, vec![epoch() + chrono::Duration::seconds(3)]);
+        match outgoing.last() {
+            Some((ColorKind::BLACK, GameIn::YourMove { flag_instant })) => {
+                assert_eq!(*flag_instant, epoch() + chrono::Duration::seconds(13))
+            }
+            _ => panic!("Expected black to move"),
+        }
+
+        source.advance(Duration::from_secs(9));
+        assert!(game.check_flag().is_empty());
+        assert_eq!(game.time_to_flag(), Some(Duration::from_secs(1)));
+        // Moves sent after flagging lose on time
+        source.advance(Duration::from_secs(2));
+        let outgoing = play(&mut game, "e7e5");
+        assert_eq!(
+            game_over(&outgoing),
+            Some((Some(ColorKind::WHITE), GameEndCause::Flag))
+        );
+        let clock = game
+            .record()
+            .clocks
+            .as_ref()
+            .unwrap()
+            .get_clock(ColorKind::BLACK)
+            .unwrap();
+        assert_eq!(clock.nanos_on_clock, Some(0));
+    }
+
+    #[test]
+    fn flag_without_moving() {
+        let (mut game, source) = timed_game(TimeFormat::Timed {
+            initial_nanos: SECOND,
+            increment_nanos: 0,
+            delay_nanos: SECOND,
+        });
+        game.start();
+        source.advance(Duration::from_millis(1500));
+        let outgoing = game.handle(ColorKind::WHITE, GameOut::GetClocks);
+        match outgoing.as_slice() {
+            [(ColorKind::WHITE, GameIn::Clocks(clocks))] => assert_eq!(
+                clocks.get_clock(ColorKind::WHITE).unwrap().nanos_on_clock,
+                Some(SECOND / 2)
+            ),
+            _ => panic!("Expected the clocks"),
+        }
+        assert!(game.check_flag().is_empty());
+        source.advance(Duration::from_millis(500));
+        assert_eq!(
+            game_over(&game.check_flag()),
+            Some((Some(ColorKind::BLACK), GameEndCause::Flag))
+        );
+    }
+
+    #[test]
+    fn flag_against_insufficient_material() {
+        let time_format = TimeFormat::Timed {
+            initial_nanos: SECOND,
+            increment_nanos: 0,
+            delay_nanos: 0,
+        };
+        // Black can't mate with a lone knight, however many pieces white has
+        let start = Board::from_fen(Kind::Chess, "4k3/8/8/3n4/8/8/PPP5/R3K3 w - - 0 1").unwrap();
+        let (mut game, source) = timed_game_from(start.clone(), time_format.clone());
+        game.start();
+        source.advance(Duration::from_secs(2));
+        assert_eq!(
+            game_over(&game.check_flag()),
+            Some((None, GameEndCause::Flag))
+        );
+
+        // White can
+        let start = Board::from_fen(Kind::Chess, "4k3/8/8/3n4/8/8/PPP5/R3K3 b - - 0 1").unwrap();
+        let (mut game, source) = timed_game_from(start, time_format);
+        game.start();
+        source.advance(Duration::from_secs(2));
+        assert_eq!(
+            game_over(&game.check_flag()),
+            Some((Some(ColorKind::WHITE), GameEndCause::Flag))
+        );
+    }
+}
@@ -1,3 +1,45 @@
+//! The GigaChess moderator. Launches engine processes, hosts games between them over unix sockets
+//! using the messages described in `giga_core::message`, and enforces the rules and clocks
+
+use std::fmt;
+use std::io;
+
+pub mod clock;
+pub mod engine;
+pub mod game;
+pub mod moderator;
+
+/// The ways running engines and games can fail
+#[derive(Debug)]
+pub enum Error {
+    /// Communicating with an engine process or game socket failed
+    Io(io::Error),
+
+    /// An engine didn't send the expected message in time, or sent something unexpected
+    Handshake(String),
+
+    /// An engine doesn't support the kind of game it was asked to play
+    Unsupported(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Handshake(message) => write!(f, "Engine handshake failed: {}", message),
+            Error::Unsupported(message) => write!(f, "Unsupported game: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -0,0 +1,222 @@
+//! Plays games between two engines and prints them as PGN.
+//!
+//! Usage: giga_chess <white engine> <black engine> [options]
+//!
+//! Engines are given as a command, which is split on whitespace into the executable and its
+//! arguments. Options:
+//!   --kind <kind>       The game to play: chess, contrasting-chess or the name of a game loaded
+//!                       with --variant-file. Defaults to chess
+//!   --variant-file <path>
+//!                       Load a fairy game from a TOML or JSON definition, see
+//!                       `giga_core::fairy`. May be repeated. Engines playing it have to load the
+//!                       same definition
+//!   --fen <fen>         Start each game from this position instead of the standard start
+//!   --chess960 <index>  Play Chess960 from the start position with this index (0-959), or
+//!                       `random` to pick a new position for every pair of games. Both engines
+//!                       play each position once with either color
+//!   --no-castling       Play without castling
+//!   --time <control>    The time control as a PGN TimeControl value, eg. 300+2, 10/move or
+//!                       40/5400+30:1800+30. Defaults to - (unlimited)
+//!   --games <n>         The number of games to play, alternating colors. Defaults to 1
+//!   --pgn <path>        Append games to this file instead of printing them
+
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use giga_chess::engine::EngineProcess;
+use giga_chess::moderator::Moderator;
+use giga_core::board::{chess960_index_from_seed, Board, CHESS960_POSITIONS};
+use giga_core::fairy;
+use giga_core::game::{Kind, TimeFormat, Variant};
+use giga_core::pgn;
+
+struct Options {
+    engines: Vec<String>,
+    kind: String,
+    variant_files: Vec<String>,
+    fen: Option<String>,
+    chess960: Option<Chess960>,
+    no_castling: bool,
+    time_format: TimeFormat,
+    games: u32,
+    pgn_path: Option<String>,
+}
+
+/// How the Chess960 start position of each game is chosen
+#[derive(Copy, Clone)]
+enum Chess960 {
+    Index(u32),
+    /// A position picked by a seed chosen when the moderator starts
+    Random(u64),
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        engines: Vec::new(),
+        kind: Kind::Chess.to_string(),
+        variant_files: Vec::new(),
+        fen: None,
+        chess960: None,
+        no_castling: false,
+        time_format: TimeFormat::Unlimited,
+        games: 1,
+        pgn_path: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--kind" => options.kind = value()?,
+            "--variant-file" => options.variant_files.push(value()?),
+            "--fen" => options.fen = Some(value()?),
+            "--chess960" => {
+                let index = value()?;
+                options.chess960 = Some(match index.as_str() {
+                    "random" => Chess960::Random(random_seed()),
+                    _ => match index.parse() {
+                        Ok(index) if index < CHESS960_POSITIONS => Chess960::Index(index),
+                        _ => return Err(format!("Invalid Chess960 position: {}", index)),
+                    },
+                });
+            }
+            "--no-castling" => options.no_castling = true,
+            "--time" => {
+                let time_control = value()?;
+                options.time_format = pgn::parse_time_control(&time_control)
+                    .ok_or(format!("Invalid time control: {}", time_control))?;
+            }
+            "--games" => {
+                let games = value()?;
+                options.games = games
+                    .parse()
+                    .map_err(|_| format!("Invalid game count: {}", games))?;
+            }
+            "--pgn" => options.pgn_path = Some(value()?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => options.engines.push(arg),
+        }
+    }
+    if options.engines.len() != 2 {
+        return Err("Expected exactly two engines".to_owned());
+    }
+    if options.fen.is_some() && options.chess960.is_some() {
+        return Err("--fen and --chess960 can't be used together".to_owned());
+    }
+    Ok(options)
+}
+
+/// The variants the games are played with
+fn variants(options: &Options) -> Vec<Variant> {
+    let mut variants = Vec::new();
+    if options.chess960.is_some() {
+        variants.push(Variant::Chess960);
+    }
+    if options.no_castling {
+        variants.push(Variant::NoCastling);
+    }
+    variants
+}
+
+/// A seed for picking Chess960 positions that differs between runs
+fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64)
+        ^ process::id() as u64
+}
+
+/// The start position of a game, taking the Chess960 option into account. Games are played in
+/// pairs from the same position so that each engine gets to play both colors
+fn start_position(options: &Options, kind: Kind, game: u32) -> Result<Board, Box<dyn Error>> {
+    let index = match options.chess960 {
+        Some(Chess960::Index(index)) => index,
+        Some(Chess960::Random(seed)) => {
+            chess960_index_from_seed(seed.wrapping_add((game / 2) as u64))
+        }
+        None => {
+            return Ok(match &options.fen {
+                Some(fen) => Board::from_fen(kind, fen)?,
+                None => Board::start_position(kind),
+            })
+        }
+    };
+    if !kind.supports_variant(&Variant::Chess960) {
+        return Err(format!("{} can't be played as Chess960", kind).into());
+    }
+    Ok(Board::chess960(index).unwrap())
+}
+
+fn run(options: Options) -> Result<(), Box<dyn Error>> {
+    // Fairy games have to be registered before their names can be parsed as a kind
+    for path in &options.variant_files {
+        fairy::load(path)?;
+    }
+    let kind: Kind = options.kind.parse()?;
+    // Checks the start position before any engines are spawned
+    start_position(&options, kind, 0)?;
+    let mut first = EngineProcess::spawn(&options.engines[0])?;
+    let mut second = EngineProcess::spawn(&options.engines[1])?;
+    let mut moderator = Moderator::new()?;
+
+    let mut score = [0.0, 0.0];
+    for game in 0..options.games {
+        let (white, black, flipped) = if game % 2 == 0 {
+            (&mut first, &mut second, false)
+        } else {
+            (&mut second, &mut first, true)
+        };
+        let record = moderator.play_game(
+            &mut [white, black],
+            start_position(&options, kind, game)?,
+            &variants(&options),
+            options.time_format.clone(),
+        )?;
+
+        let points = match record.result.as_ref().and_then(|result| result.winner) {
+            Some(winner) if winner.id() == 0 => [1.0, 0.0],
+            Some(_) => [0.0, 1.0],
+            None => [0.5, 0.5],
+        };
+        let (white_index, black_index) = if flipped { (1, 0) } else { (0, 1) };
+        score[white_index] += points[0];
+        score[black_index] += points[1];
+
+        let pgn = record.to_pgn()?;
+        match &options.pgn_path {
+            Some(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{}", pgn)?;
+            }
+            None => println!("{}", pgn),
+        }
+        eprintln!(
+            "Game {}: {} {} - {} {}",
+            game + 1,
+            first.info().name,
+            score[0],
+            score[1],
+            second.info().name
+        );
+    }
+
+    first.shutdown();
+    second.shutdown();
+    Ok(())
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("Usage: giga_chess <white engine> <black engine> [--kind <kind>] [--variant-file <path>] [--fen <fen>] [--chess960 <index>] [--no-castling] [--time <control>] [--games <n>] [--pgn <path>]");
+            process::exit(2);
+        }
+    };
+    if let Err(err) = run(options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
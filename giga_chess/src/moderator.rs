@@ -0,0 +1,278 @@
+//! Hosts games between engine processes over per-game unix sockets
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use giga_core::board::Board;
+use giga_core::game::{ColorKind, TimeFormat, Variant, ID};
+use giga_core::message::{
+    encode, Encoder, Feature, FrameError, GameIn, GameOut, In, MessageReader,
+};
+use giga_core::pgn::GameRecord;
+
+use crate::clock::{SystemTimeSource, TimeSource};
+use crate::engine::EngineProcess;
+use crate::game::{GameState, Outgoing};
+use crate::Error;
+
+/// How long an engine has to connect to its game socket after receiving `In::GameStart`
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something that happened on a game socket
+enum Event {
+    Message(ColorKind, GameOut),
+    /// A message that couldn't be decoded
+    Invalid {
+        color: ColorKind,
+        error: FrameError,
+    },
+    Disconnected(ColorKind),
+}
+
+/// A player's connection to a game
+struct Seat {
+    socket: Encoder<UnixStream>,
+    /// Whether the player negotiated `Feature::DrawOffers`
+    draw_offers: bool,
+}
+
+/// Runs games between engines
+pub struct Moderator {
+    /// The directory game sockets are created in
+    socket_dir: PathBuf,
+    next_game_id: ID,
+    /// What the clocks of every game are measured with
+    time_source: Arc<dyn TimeSource>,
+}
+
+impl Moderator {
+    /// Creates a moderator that places its game sockets in a directory unique to this process
+    /// under the system temporary directory
+    pub fn new() -> Result<Moderator, Error> {
+        let socket_dir = std::env::temp_dir().join(format!("giga_chess-{}", process::id()));
+        Moderator::with_socket_dir(socket_dir)
+    }
+
+    pub fn with_socket_dir(socket_dir: PathBuf) -> Result<Moderator, Error> {
+        fs::create_dir_all(&socket_dir)?;
+        Ok(Moderator {
+            socket_dir,
+            next_game_id: 0,
+            time_source: Arc::new(SystemTimeSource),
+        })
+    }
+
+    /// Measures the clocks of games started from now on with `time_source` instead of the system
+    /// clock
+    pub fn set_time_source(&mut self, time_source: Arc<dyn TimeSource>) {
+        self.time_source = time_source;
+    }
+
+    /// Plays a game from `start` between `engines`, which are indexed by color id, with the rules
+    /// of `variants` applied. Every engine must have advertised support for the combination of
+    /// variants. Returns the finished game once it is over
+    pub fn play_game(
+        &mut self,
+        engines: &mut [&mut EngineProcess],
+        mut start: Board,
+        variants: &[Variant],
+        time_format: TimeFormat,
+    ) -> Result<GameRecord, Error> {
+        let kind = start.kind();
+        if engines.len() as u32 != kind.color_count() {
+            return Err(Error::Unsupported(format!(
+                "{:?} needs {} players",
+                kind,
+                kind.color_count()
+            )));
+        }
+        if !kind.supports_variants(variants) {
+            return Err(Error::Unsupported(format!(
+                "{:?} can't be played with {:?}",
+                kind, variants
+            )));
+        }
+        if let Some(engine) = engines
+            .iter()
+            .find(|engine| !engine.supports(kind, variants))
+        {
+            return Err(Error::Unsupported(format!(
+                "{} does not support {:?} with {:?}",
+                engine.info().name,
+                kind,
+                variants
+            )));
+        }
+        start.apply_variants(variants);
+
+        let id = self.next_game_id;
+        self.next_game_id += 1;
+        let players: Vec<_> = engines.iter().map(|engine| engine.info().clone()).collect();
+        let time_formats = vec![time_format; engines.len()];
+
+        let (sender, events) = mpsc::channel();
+        let mut seats = Vec::new();
+        let mut paths = Vec::new();
+        for (i, engine) in engines.iter_mut().enumerate() {
+            let color = ColorKind::new(i as u32);
+            let path = self.socket_dir.join(format!("game-{}-{}.sock", id, i));
+            let _ = fs::remove_file(&path);
+            let listener = UnixListener::bind(&path)?;
+            paths.push(path.clone());
+
+            let opponents = players
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, info)| {
+                    (
+                        ColorKind::new(j as u32),
+                        (info.clone(), time_formats[j].clone()),
+                    )
+                })
+                .collect::<HashMap<_, _>>();
+            engine.send(&In::GameStart {
+                variant: kind,
+                variants: variants.iter().copied().collect(),
+                board: start.to_fen(),
+                game_listen_path: path.to_string_lossy().into_owned(),
+                game_id: id,
+                playing_as: color,
+                time_format: time_formats[i].clone(),
+                opponents,
+            })?;
+
+            let stream = accept(&listener, CONNECT_TIMEOUT).map_err(|err| {
+                Error::Handshake(format!(
+                    "{} did not connect to {}: {}",
+                    engine.info().name,
+                    path.display(),
+                    err
+                ))
+            })?;
+            spawn_reader(color, stream.try_clone()?, sender.clone());
+            seats.push(Seat {
+                socket: Encoder::new(stream),
+                draw_offers: engine.protocol().supports(Feature::DrawOffers),
+            });
+        }
+        drop(sender);
+
+        let mut game = GameState::new(id, start, players, &time_formats, self.time_source.clone());
+        let outgoing = game.start();
+        deliver(&mut seats, outgoing);
+        while game.result().is_none() {
+            let timeout = game
+                .time_to_flag()
+                .unwrap_or_else(|| Duration::from_secs(u32::MAX as u64));
+            let outgoing = match events.recv_timeout(timeout) {
+                Ok(Event::Message(color, message)) => {
+                    let draw_message =
+                        matches!(message, GameOut::DrawOffer | GameOut::RejectDrawOffer);
+                    if draw_message && !seats[color.id() as usize].draw_offers {
+                        // An engine that can't be told is about to disconnect, which ends the
+                        // game normally
+                        let _ = engines[color.id() as usize].send(&In::InvalidRequest {
+                            message: "Draw offers were not negotiated".to_owned(),
+                            request_json: String::from_utf8_lossy(&encode(&message))
+                                .trim()
+                                .to_owned(),
+                            related_game: Some(id),
+                        });
+                        Vec::new()
+                    } else {
+                        game.handle(color, message)
+                    }
+                }
+                Ok(Event::Invalid { color, error }) => {
+                    if let Some(invalid_request) = error.to_invalid_request(Some(id)) {
+                        let _ = engines[color.id() as usize].send(&invalid_request);
+                    }
+                    Vec::new()
+                }
+                Ok(Event::Disconnected(color)) => game.disconnected(color),
+                Err(RecvTimeoutError::Timeout) => game.check_flag(),
+                Err(RecvTimeoutError::Disconnected) => game.disconnected(game.to_move()),
+            };
+            deliver(&mut seats, outgoing);
+        }
+
+        for engine in engines.iter_mut() {
+            // The engine may have already exited, which doesn't change the outcome of the game
+            let _ = engine.send(&In::GameEnd { game_id: id });
+        }
+        for path in paths {
+            let _ = fs::remove_file(path);
+        }
+        Ok(game.record().clone())
+    }
+}
+
+impl Drop for Moderator {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.socket_dir);
+    }
+}
+
+/// Waits for a single connection on `listener`, giving up after `timeout`
+fn accept(listener: &UnixListener, timeout: Duration) -> io::Result<UnixStream> {
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Reads `GameOut` messages from a game socket on a new thread
+fn spawn_reader(color: ColorKind, stream: UnixStream, sender: Sender<Event>) {
+    thread::spawn(move || {
+        for message in MessageReader::new(stream) {
+            let event = match message {
+                Ok(message) => Event::Message(color, message),
+                Err(FrameError::Io(_)) => break,
+                Err(error) => Event::Invalid { color, error },
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send(Event::Disconnected(color));
+    });
+}
+
+/// Sends messages to players. Write errors are ignored, as a player that has gone away will be
+/// noticed by its reader
+fn deliver(seats: &mut [Seat], outgoing: Vec<Outgoing>) {
+    for (color, message) in outgoing {
+        let seat = match seats.get_mut(color.id() as usize) {
+            Some(seat) => seat,
+            None => continue,
+        };
+        if let GameIn::OpponentDrawOffer { .. } = message {
+            if !seat.draw_offers {
+                continue;
+            }
+        }
+        let _ = seat.socket.send(&message);
+    }
+}
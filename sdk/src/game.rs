@@ -0,0 +1,223 @@
+//! The game socket side of the protocol
+
+use std::io;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::AtomicBool;
+
+use chrono::prelude::*;
+
+use giga_core::message::{Encoder, Feature, FrameError, GameIn, GameOut, MessageReader};
+
+use crate::{Action, GameInfo, Player, Turn};
+
+/// Connects to a game socket and plays the game until it is over
+pub fn play(path: &str, info: GameInfo, mut player: Box<dyn Player>) -> io::Result<()> {
+    let stream = UnixStream::connect(path)?;
+    let mut writer = Encoder::new(stream.try_clone()?);
+    let draw_offers = info.protocol.supports(Feature::DrawOffers);
+    let mut board = info.start;
+    // Games over the protocol are only limited by the clock
+    let stop = AtomicBool::new(false);
+
+    for message in MessageReader::new(stream) {
+        let message: GameIn = match message {
+            Ok(message) => message,
+            Err(FrameError::Io(err)) => return Err(err),
+            Err(err) => {
+                eprintln!("Failed to parse message from moderator: {}", err);
+                continue;
+            }
+        };
+        match message {
+            GameIn::OpponentMove {
+                opponent_move,
+                opponent,
+            } => match board.move_from_raw(&opponent_move) {
+                Some(m) => {
+                    board.apply_move(m);
+                    player.opponent_move(&board, m, opponent);
+                }
+                None => {
+                    let message = format!(
+                        "The moderator sent an illegal move {:?} in position {}",
+                        opponent_move,
+                        board.to_fen()
+                    );
+                    return send(&mut writer, &GameOut::Err { message });
+                }
+            },
+            GameIn::YourMove { flag_instant } => {
+                let turn = Turn {
+                    board: &board,
+                    move_start: Utc::now(),
+                    flag_instant: if flag_instant == DateTime::<Utc>::MAX_UTC {
+                        None
+                    } else {
+                        Some(flag_instant)
+                    },
+                    time_format: info.time_format.clone(),
+                    max_depth: None,
+                    stop: &stop,
+                };
+                let m = match player.your_move(&turn) {
+                    Action::Move(m) => m,
+                    Action::MoveAndOfferDraw(m) => {
+                        if draw_offers {
+                            send(&mut writer, &GameOut::DrawOffer)?;
+                        }
+                        m
+                    }
+                    Action::Resign => {
+                        send(&mut writer, &GameOut::Resign)?;
+                        continue;
+                    }
+                };
+                send(&mut writer, &GameOut::Move(board.to_raw_move(m)))?;
+                board.apply_move(m);
+            }
+            GameIn::OpponentDrawOffer { player: offering } => {
+                let reply = if player.draw_offer(&board, offering) {
+                    GameOut::DrawOffer
+                } else {
+                    GameOut::RejectDrawOffer
+                };
+                send(&mut writer, &reply)?;
+            }
+            GameIn::GameOver { winner, cause } => {
+                player.game_over(winner, &cause);
+                break;
+            }
+            GameIn::Clocks(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn send(writer: &mut Encoder<UnixStream>, message: &GameOut) -> io::Result<()> {
+    writer.send(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_with, Engine};
+    use giga_core::board::Board;
+    use giga_core::game::{ColorKind, GameEndCause, Kind, TimeFormat};
+    use giga_core::message::{encode, EngineInfo, In, Out, Protocol, PROTOCOL_VERSION};
+    use smallvec::SmallVec;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+    use std::sync::mpsc::{self, Sender};
+
+    /// Plays the first legal move, and reports how the game ended
+    struct FirstMove(Sender<(Option<ColorKind>, GameEndCause)>);
+
+    impl Engine for FirstMove {
+        fn info(&self) -> EngineInfo {
+            EngineInfo {
+                name: "First move".to_owned(),
+                ..EngineInfo::default()
+            }
+        }
+
+        fn new_player(&mut self, _: &GameInfo) -> Box<dyn Player> {
+            Box::new(FirstMovePlayer(self.0.clone()))
+        }
+    }
+
+    struct FirstMovePlayer(Sender<(Option<ColorKind>, GameEndCause)>);
+
+    impl Player for FirstMovePlayer {
+        fn your_move(&mut self, turn: &Turn) -> Action {
+            assert_eq!(turn.flag_instant, None);
+            Action::Move(turn.board.legal_moves()[0])
+        }
+
+        fn game_over(&mut self, winner: Option<ColorKind>, cause: &GameEndCause) {
+            self.0.send((winner, cause.clone())).unwrap();
+        }
+    }
+
+    #[test]
+    fn plays_a_game() {
+        let path = std::env::temp_dir().join(format!("giga_sdk-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let start = Board::from_fen(Kind::Chess, "4k3/8/8/8/8/8/8/4K2R b K - 0 1").unwrap();
+        let mut input = encode(&In::EngineInit {
+            protocol_versions: vec![PROTOCOL_VERSION],
+            features: vec![Feature::DrawOffers],
+        });
+        input.extend(encode(&In::ProtocolAccepted {
+            protocol: Protocol {
+                version: PROTOCOL_VERSION,
+                features: vec![Feature::DrawOffers],
+            },
+        }));
+        input.extend(encode(&In::GameStart {
+            variant: Kind::Chess,
+            variants: SmallVec::new(),
+            board: start.to_fen(),
+            game_listen_path: path.to_string_lossy().into_owned(),
+            game_id: 0,
+            playing_as: ColorKind::WHITE,
+            time_format: TimeFormat::Unlimited,
+            opponents: HashMap::new(),
+        }));
+        input.extend(encode(&In::EngineShutdown));
+        let (sender, results) = mpsc::channel();
+        let mut output = Vec::new();
+        run_with(FirstMove(sender), &input[..], &mut output).unwrap();
+        match serde_json::from_slice(&output).unwrap() {
+            Out::EngineInfo {
+                info,
+                protocol_versions,
+                ..
+            } => {
+                assert_eq!(info.name, "First move");
+                assert_eq!(protocol_versions, [PROTOCOL_VERSION]);
+            }
+        }
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut moderator = stream.try_clone().unwrap();
+        let mut replies = MessageReader::<_, GameOut>::new(stream);
+        let opponent_move = start.to_raw_move(start.parse_uci("e8d8").unwrap());
+        let mut after = start.clone();
+        after.apply_move(start.parse_uci("e8d8").unwrap());
+        for message in [
+            GameIn::OpponentMove {
+                opponent_move,
+                opponent: ColorKind::BLACK,
+            },
+            GameIn::YourMove {
+                flag_instant: DateTime::<Utc>::MAX_UTC,
+            },
+        ]
+        .iter()
+        {
+            moderator.write_all(&encode(message)).unwrap();
+        }
+        match replies.next().unwrap().unwrap() {
+            GameOut::Move(m) => assert_eq!(after.move_from_raw(&m), Some(after.legal_moves()[0])),
+            _ => panic!("Expected a move"),
+        }
+
+        moderator
+            .write_all(&encode(&GameIn::OpponentDrawOffer {
+                player: ColorKind::BLACK,
+            }))
+            .unwrap();
+        assert!(matches!(replies.next(), Some(Ok(GameOut::RejectDrawOffer))));
+
+        let game_over = GameIn::GameOver {
+            winner: None,
+            cause: GameEndCause::DrawOffer,
+        };
+        moderator.write_all(&encode(&game_over)).unwrap();
+        assert_eq!(results.recv().unwrap(), (None, GameEndCause::DrawOffer));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,226 @@
+//! Everything needed to write a GigaChess engine.
+//! Implement `Engine` and `Player`, then call `run` from `main`. The SDK answers the moderator's
+//! handshake, connects to the socket of each game, keeps track of the board, and calls the
+//! player whenever something happens in their game. Each game runs on its own thread
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::atomic::AtomicBool;
+use std::thread;
+
+use chrono::prelude::*;
+use smallvec::SmallVec;
+
+use giga_core::board::{Board, Move};
+use giga_core::game::{ColorKind, GameEndCause, Kind, TimeFormat, Variant, ID};
+use giga_core::message::{
+    Encoder, EngineInfo, Feature, FrameError, In, MessageReader, Out, Protocol, PROTOCOL_VERSION,
+};
+
+mod game;
+mod session;
+#[cfg(test)]
+mod testing;
+pub mod uci;
+pub mod xboard;
+
+/// An engine, which creates a player for every game the moderator starts
+pub trait Engine {
+    fn info(&self) -> EngineInfo;
+
+    /// The games this engine can play, mapped to the variants it supports for each game.
+    /// Defaults to the stock version of chess
+    fn supported_games(&self) -> HashMap<Kind, SmallVec<[Variant; 2]>> {
+        let mut games = HashMap::new();
+        games.insert(Kind::Chess, SmallVec::new());
+        games
+    }
+
+    /// The optional protocol features this engine supports. Defaults to draw offers
+    fn features(&self) -> Vec<Feature> {
+        vec![Feature::DrawOffers]
+    }
+
+    /// Creates the player that will play `game`
+    fn new_player(&mut self, game: &GameInfo) -> Box<dyn Player>;
+}
+
+/// Plays a single game. Every method is called on the game's thread, so a player may block
+/// while it thinks
+pub trait Player: Send {
+    /// It is this player's move. Time spent in this method counts against this player's clock
+    fn your_move(&mut self, turn: &Turn) -> Action;
+
+    /// An opponent made `m`. `board` is the position after the move
+    fn opponent_move(&mut self, board: &Board, m: Move, opponent: ColorKind) {
+        let _ = (board, m, opponent);
+    }
+
+    /// An opponent offered a draw. Return true to accept it, or false to reject it.
+    /// By default draw offers are rejected
+    fn draw_offer(&mut self, board: &Board, player: ColorKind) -> bool {
+        let _ = (board, player);
+        false
+    }
+
+    /// The game is over. `winner` is None for draws
+    fn game_over(&mut self, winner: Option<ColorKind>, cause: &GameEndCause) {
+        let _ = (winner, cause);
+    }
+}
+
+/// What a player does on their move
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Move(Move),
+
+    /// Offers a draw and then makes a move. The offer stands until an opponent moves
+    MoveAndOfferDraw(Move),
+
+    Resign,
+}
+
+/// Information about a game that is starting
+#[derive(Clone, Debug)]
+pub struct GameInfo {
+    pub id: ID,
+
+    /// The position the game starts from, which already follows `variants`
+    pub start: Board,
+
+    /// The variants in play, from the ones this engine supports for the game kind. Empty for the
+    /// stock game, or when playing over a text protocol that only describes positions
+    pub variants: SmallVec<[Variant; 2]>,
+
+    /// The color this player is playing as
+    pub color: ColorKind,
+
+    pub time_format: TimeFormat,
+
+    /// Each opponent's information and time format
+    pub opponents: HashMap<ColorKind, (EngineInfo, TimeFormat)>,
+
+    /// The protocol agreed on with the moderator. Draw offers are only sent and received if
+    /// `Feature::DrawOffers` was agreed on
+    pub protocol: Protocol,
+}
+
+/// Everything a player needs to choose their move
+#[derive(Clone, Debug)]
+pub struct Turn<'a> {
+    /// The current position
+    pub board: &'a Board,
+
+    /// The instant this player was told to move
+    pub move_start: DateTime<Utc>,
+
+    /// The instant this player will flag if no move is made. None if this player has unlimited
+    /// time
+    pub flag_instant: Option<DateTime<Utc>>,
+
+    pub time_format: TimeFormat,
+
+    /// The deepest the player should search in plies, if the search is limited by depth. Players
+    /// that don't search by depth may ignore it
+    pub max_depth: Option<u32>,
+
+    /// Set when the player should stop thinking and move as soon as possible
+    pub stop: &'a AtomicBool,
+}
+
+/// Runs `engine`, talking to the moderator over stdin and stdout. Returns once the moderator
+/// shuts the engine down or closes stdin
+pub fn run<E: Engine>(engine: E) -> io::Result<()> {
+    let stdin = io::stdin();
+    run_with(engine, stdin.lock(), io::stdout())
+}
+
+/// Runs `engine`, reading messages from the moderator from `input` and sending replies to
+/// `output`
+pub fn run_with<E: Engine, R: Read, W: Write>(
+    mut engine: E,
+    input: R,
+    output: W,
+) -> io::Result<()> {
+    let mut output = Encoder::new(output);
+    // Moderators that don't negotiate speak the legacy protocol
+    let mut protocol = Protocol::legacy();
+    for message in MessageReader::new(input) {
+        let message: In = match message {
+            Ok(message) => message,
+            Err(FrameError::Io(err)) => return Err(err),
+            Err(err) => {
+                eprintln!("Failed to parse message from moderator: {}", err);
+                continue;
+            }
+        };
+        match message {
+            In::EngineInit { .. } => {
+                let reply = Out::EngineInfo {
+                    info: engine.info(),
+                    supported_games: engine.supported_games(),
+                    protocol_versions: vec![PROTOCOL_VERSION],
+                    features: engine.features(),
+                };
+                output.send(&reply)?;
+            }
+            In::ProtocolAccepted { protocol: accepted } => protocol = accepted,
+            In::GameStart {
+                variant,
+                variants,
+                board,
+                game_listen_path,
+                game_id,
+                playing_as,
+                time_format,
+                opponents,
+            } => {
+                let supported = engine
+                    .supported_games()
+                    .get(&variant)
+                    .is_some_and(|supported| variants.iter().all(|v| supported.contains(v)));
+                if !supported {
+                    eprintln!(
+                        "Game {} is {} with {:?}, which this engine doesn't support",
+                        game_id, variant, variants
+                    );
+                    continue;
+                }
+                let mut start = match Board::from_fen(variant, &board) {
+                    Ok(start) => start,
+                    Err(err) => {
+                        eprintln!("Game {} has an invalid start position: {}", game_id, err);
+                        continue;
+                    }
+                };
+                start.apply_variants(&variants);
+                let info = GameInfo {
+                    id: game_id,
+                    start,
+                    variants,
+                    color: playing_as,
+                    time_format,
+                    opponents,
+                    protocol: protocol.clone(),
+                };
+                let player = engine.new_player(&info);
+                thread::spawn(move || {
+                    if let Err(err) = game::play(&game_listen_path, info, player) {
+                        eprintln!("Game {} failed: {}", game_id, err);
+                    }
+                });
+            }
+            In::GameEnd { .. } => {}
+            In::EngineShutdown => break,
+            In::InvalidRequest {
+                message,
+                request_json,
+                ..
+            } => eprintln!(
+                "The moderator rejected a message: {}: {}",
+                message, request_json
+            ),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+//! Players for text protocols that describe positions rather than games.
+//! A player is kept for as long as each new position continues its game with moves by its
+//! opponents, and a new player is created otherwise
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use smallvec::SmallVec;
+
+use giga_core::board::{Board, Move};
+use giga_core::game::{ColorKind, Kind, TimeFormat, Variant, ID};
+use giga_core::message::{EngineInfo, Protocol};
+
+use crate::{Engine, GameInfo, Player};
+
+/// A player along with the game it has been following
+pub(crate) struct Game {
+    pub player: Box<dyn Player>,
+    pub color: ColorKind,
+    pub start: Board,
+    /// The moves the player knows about, including its own
+    pub moves: Vec<Move>,
+}
+
+/// A player thinking on another thread
+pub(crate) struct Search<T> {
+    pub handle: JoinHandle<(Game, T)>,
+    pub stop: Arc<AtomicBool>,
+}
+
+impl<T: Send + 'static> Search<T> {
+    /// Runs `think` on another thread, passing it the flag that tells the player to stop
+    pub fn spawn<F>(think: F) -> Search<T>
+    where
+        F: FnOnce(&AtomicBool) -> (Game, T) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        Search {
+            handle: thread::spawn(move || think(&thread_stop)),
+            stop,
+        }
+    }
+
+    /// Tells the player to move as soon as possible
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Writes a line to a front-end's output and flushes it
+pub(crate) fn send<W: Write>(output: &Mutex<W>, line: &str) -> io::Result<()> {
+    let mut output = output.lock().unwrap();
+    writeln!(output, "{}", line)?;
+    output.flush()
+}
+
+/// The current position, and the player following it
+pub(crate) struct Session<E> {
+    pub engine: E,
+    pub start: Board,
+    pub moves: Vec<Move>,
+    pub game: Option<Game>,
+    protocol: Protocol,
+    next_id: ID,
+}
+
+impl<E: Engine> Session<E> {
+    /// Starts from the standard chess position. `protocol` is passed on to every player
+    pub fn new(engine: E, protocol: Protocol) -> Session<E> {
+        Session {
+            engine,
+            start: Board::start_position(Kind::Chess),
+            moves: Vec::new(),
+            game: None,
+            protocol,
+            next_id: 0,
+        }
+    }
+
+    /// Returns true if the engine plays Chess960
+    pub fn supports_chess960(&self) -> bool {
+        self.engine
+            .supported_games()
+            .get(&Kind::Chess)
+            .is_some_and(|variants| variants.contains(&Variant::Chess960))
+    }
+
+    /// The board after every move of the current position
+    pub fn board(&self) -> Board {
+        let mut board = self.start.clone();
+        for m in self.moves.iter() {
+            board.apply_move(*m);
+        }
+        board
+    }
+
+    /// Returns the player for `color`, telling it about any moves its opponents have made since
+    /// it last moved. Creates a new player if the position doesn't continue its game. `formats` is
+    /// the time format of every color, indexed by color id
+    pub fn take_game(&mut self, color: ColorKind, formats: &[TimeFormat]) -> Game {
+        if let Some(mut game) = self.game.take() {
+            if game.color == color
+                && game.start == self.start
+                && self.moves.starts_with(&game.moves)
+            {
+                let mut board = game.start.clone();
+                for m in game.moves.iter() {
+                    board.apply_move(*m);
+                }
+                let mut seen = Vec::new();
+                for m in self.moves[game.moves.len()..].iter() {
+                    let mover = board.to_move();
+                    board.apply_move(*m);
+                    seen.push((board.clone(), *m, mover));
+                }
+                if seen.iter().all(|(_, _, mover)| *mover != color) {
+                    for (board, m, mover) in seen {
+                        game.player.opponent_move(&board, m, mover);
+                        game.moves.push(m);
+                    }
+                    return game;
+                }
+            }
+        }
+
+        let opponents = (0..Kind::Chess.color_count())
+            .map(ColorKind::new)
+            .filter(|opponent| *opponent != color)
+            .map(|opponent| {
+                let format = formats[opponent.id() as usize].clone();
+                (opponent, (EngineInfo::default(), format))
+            })
+            .collect::<HashMap<_, _>>();
+        let info = GameInfo {
+            id: self.next_id,
+            start: self.board(),
+            variants: SmallVec::new(),
+            color,
+            time_format: formats[color.id() as usize].clone(),
+            opponents,
+            protocol: self.protocol.clone(),
+        };
+        self.next_id += 1;
+        Game {
+            player: self.engine.new_player(&info),
+            color,
+            start: self.start.clone(),
+            moves: self.moves.clone(),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Fixtures shared by the tests of the text protocol front-ends
+
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+use giga_core::board::{Board, Move};
+use giga_core::game::{ColorKind, GameEndCause};
+use giga_core::message::EngineInfo;
+
+use crate::{Action, Engine, GameInfo, Player, Turn};
+
+/// Writes into a buffer that the test can read after the front-end is done
+#[derive(Clone, Default)]
+pub struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Shared {
+    /// Everything written so far, split into lines
+    pub fn lines(&self) -> Vec<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .lines()
+            .map(|line| line.unwrap())
+            .collect()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Plays the first legal move and accepts draws, recording everything that happens
+pub struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl Recorder {
+    fn record(&self, event: String) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+impl Engine for Recorder {
+    fn info(&self) -> EngineInfo {
+        EngineInfo {
+            name: "Recorder".to_owned(),
+            version: "1.0".to_owned(),
+            author: "Nobody".to_owned(),
+            ..EngineInfo::default()
+        }
+    }
+
+    fn new_player(&mut self, game: &GameInfo) -> Box<dyn Player> {
+        self.record(format!("new {} {:?}", game.color.id(), game.time_format));
+        Box::new(Recorder(Arc::clone(&self.0)))
+    }
+}
+
+impl Player for Recorder {
+    fn your_move(&mut self, turn: &Turn) -> Action {
+        let millis = turn
+            .flag_instant
+            .map(|flag_instant| (flag_instant - turn.move_start).num_milliseconds());
+        self.record(format!("turn {:?} {:?}", millis, turn.max_depth));
+        Action::Move(turn.board.legal_moves()[0])
+    }
+
+    fn opponent_move(&mut self, board: &Board, m: Move, _: ColorKind) {
+        self.record(format!("opponent {}", board.square_name(m.dst)));
+    }
+
+    fn draw_offer(&mut self, _: &Board, _: ColorKind) -> bool {
+        true
+    }
+
+    fn game_over(&mut self, winner: Option<ColorKind>, cause: &GameEndCause) {
+        self.record(format!("over {:?} {:?}", winner.map(|w| w.id()), cause));
+    }
+}
+
+/// Runs a `Recorder` through a front-end on `input`, returning the lines it wrote and the events
+/// it recorded
+pub fn run<F>(front_end: F, input: &str) -> (Vec<String>, Vec<String>)
+where
+    F: FnOnce(Recorder, &[u8], Shared) -> io::Result<()>,
+{
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let output = Shared::default();
+    front_end(
+        Recorder(Arc::clone(&events)),
+        input.as_bytes(),
+        output.clone(),
+    )
+    .unwrap();
+    let events = events.lock().unwrap().clone();
+    (output.lines(), events)
+}
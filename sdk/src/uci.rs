@@ -0,0 +1,461 @@
+//! Runs an engine as a Universal Chess Interface engine, so that it can be used by chess GUIs and
+//! tournament managers.
+//! UCI has no notion of games, only positions, so a player is kept for as long as each position
+//! continues the previous one with moves by its opponents, and a new player is created otherwise.
+//! The clock arguments of `go` become the turn's flag instant, `depth` becomes its maximum depth,
+//! and `stop` sets its stop flag. UCI can't resign, so resigning is reported as the null move 0000
+
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+
+use giga_core::board::{Board, Move, MoveKind};
+use giga_core::game::{ColorKind, Kind, TimeFormat, TimePeriod};
+use giga_core::message::{Protocol, PROTOCOL_VERSION};
+
+use crate::session::{send, Search, Session};
+use crate::{Action, Engine, Turn};
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+/// The option GUIs set to play Chess960
+const CHESS960_OPTION: &str = "UCI_Chess960";
+
+/// Runs `engine` as a UCI engine over stdin and stdout. Returns once the GUI sends `quit` or
+/// closes stdin
+pub fn run_uci<E: Engine>(engine: E) -> io::Result<()> {
+    let stdin = io::stdin();
+    run_uci_with(engine, stdin.lock(), io::stdout())
+}
+
+/// Runs `engine` as a UCI engine, reading commands from `input` and writing replies to `output`
+pub fn run_uci_with<E, R, W>(engine: E, input: R, output: W) -> io::Result<()>
+where
+    E: Engine,
+    R: BufRead,
+    W: Write + Send + 'static,
+{
+    // UCI has no way to offer draws
+    let protocol = Protocol {
+        version: PROTOCOL_VERSION,
+        features: Vec::new(),
+    };
+    let mut frontend = Frontend {
+        session: Session::new(engine, protocol),
+        output: Arc::new(Mutex::new(output)),
+        search: None,
+        chess960: false,
+    };
+    for line in input.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => continue,
+        };
+        let args: Vec<&str> = words.collect();
+        match command {
+            "uci" => frontend.uci()?,
+            "isready" => frontend.send("readyok")?,
+            "ucinewgame" => {
+                frontend.finish_search()?;
+                frontend.session.game = None;
+            }
+            "position" => frontend.position(&args)?,
+            "go" => frontend.go(&args)?,
+            "stop" => frontend.finish_search()?,
+            "setoption" => frontend.set_option(&args)?,
+            "quit" => break,
+            // Pondering isn't supported, and there is nothing to debug or register
+            "ponderhit" | "debug" | "register" => {}
+            _ => frontend.send(&format!("info string Unknown command {}", command))?,
+        }
+    }
+    frontend.finish_search()
+}
+
+/// Writes a move in UCI notation. In Chess960 mode castling is always written as the king moving
+/// onto its rook
+pub fn uci_move(board: &Board, m: Move, chess960: bool) -> String {
+    match m.kind {
+        MoveKind::Castle { rook_src } if chess960 => {
+            format!(
+                "{}{}",
+                board.square_name(m.src),
+                board.square_name(rook_src)
+            )
+        }
+        _ => board.to_uci(m).to_string(),
+    }
+}
+
+/// The limits given to `go`. Times are in milliseconds
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct GoParams {
+    wtime: Option<u64>,
+    btime: Option<u64>,
+    winc: Option<u64>,
+    binc: Option<u64>,
+    movetime: Option<u64>,
+    /// The moves left until the next time control
+    movestogo: Option<u32>,
+    depth: Option<u32>,
+    infinite: bool,
+}
+
+struct Frontend<E, W> {
+    /// The position set by the last `position` command
+    session: Session<E>,
+    output: Arc<Mutex<W>>,
+    /// Finishes with the bestmove line if it was held back until `stop`
+    search: Option<Search<Option<String>>>,
+    chess960: bool,
+}
+
+impl GoParams {
+    fn parse(args: &[&str]) -> GoParams {
+        let mut params = GoParams::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // Negative clocks, which some GUIs send once a player has flagged, mean no time is left
+            let mut value = || {
+                args.next()
+                    .and_then(|value| value.parse::<i64>().ok())
+                    .map(|value| value.max(0) as u64)
+            };
+            match *arg {
+                "wtime" => params.wtime = value(),
+                "btime" => params.btime = value(),
+                "winc" => params.winc = value(),
+                "binc" => params.binc = value(),
+                "movetime" => params.movetime = value(),
+                "movestogo" => params.movestogo = value().map(|moves| moves as u32),
+                "depth" => params.depth = value().map(|depth| depth as u32),
+                "infinite" => params.infinite = true,
+                "nodes" | "mate" => {
+                    value();
+                }
+                // searchmoves and ponder aren't supported
+                _ => {}
+            }
+        }
+        params
+    }
+
+    /// The time format and milliseconds left for `color`
+    fn clock(&self, color: ColorKind) -> (TimeFormat, Option<u64>) {
+        let (time, inc) = if color == ColorKind::WHITE {
+            (self.wtime, self.winc)
+        } else {
+            (self.btime, self.binc)
+        };
+        let increment_nanos = inc.unwrap_or(0) * NANOS_PER_MILLI;
+        match (time, self.movestogo) {
+            // What happens after the next time control isn't known, so it is assumed to repeat
+            (Some(time), Some(moves)) if moves > 0 => {
+                let period = TimePeriod {
+                    moves: Some(moves),
+                    nanos: time * NANOS_PER_MILLI,
+                    increment_nanos,
+                    delay_nanos: 0,
+                };
+                let format = TimeFormat::Periods {
+                    periods: vec![period],
+                };
+                (format, Some(time))
+            }
+            (Some(time), _) => {
+                let format = TimeFormat::Timed {
+                    initial_nanos: time * NANOS_PER_MILLI,
+                    increment_nanos,
+                    delay_nanos: 0,
+                };
+                (format, Some(time))
+            }
+            (None, _) => (TimeFormat::Unlimited, None),
+        }
+    }
+}
+
+impl<E: Engine, W: Write + Send + 'static> Frontend<E, W> {
+    fn send(&self, line: &str) -> io::Result<()> {
+        send(&self.output, line)
+    }
+
+    fn uci(&mut self) -> io::Result<()> {
+        let info = self.session.engine.info();
+        if info.version.is_empty() {
+            self.send(&format!("id name {}", info.name))?;
+        } else {
+            self.send(&format!("id name {} {}", info.name, info.version))?;
+        }
+        if !info.author.is_empty() {
+            self.send(&format!("id author {}", info.author))?;
+        }
+        if self.session.supports_chess960() {
+            self.send(&format!(
+                "option name {} type check default false",
+                CHESS960_OPTION
+            ))?;
+        }
+        self.send("uciok")
+    }
+
+    fn set_option(&mut self, args: &[&str]) -> io::Result<()> {
+        // setoption name <name> [value <value>], where both may contain spaces
+        let value_at = args.iter().position(|arg| *arg == "value");
+        let name = args[..value_at.unwrap_or(args.len())]
+            .iter()
+            .skip_while(|arg| **arg == "name")
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let value = value_at
+            .map(|i| args[i + 1..].join(" "))
+            .unwrap_or_default();
+        if name.eq_ignore_ascii_case(CHESS960_OPTION) && self.session.supports_chess960() {
+            self.chess960 = value.eq_ignore_ascii_case("true");
+            Ok(())
+        } else {
+            self.send(&format!("info string Unknown option {}", name))
+        }
+    }
+
+    fn position(&mut self, args: &[&str]) -> io::Result<()> {
+        self.finish_search()?;
+        let moves_at = args.iter().position(|arg| *arg == "moves");
+        let (setup, moves) = match moves_at {
+            Some(i) => (&args[..i], &args[i + 1..]),
+            None => (args, &[][..]),
+        };
+        let start = match setup.split_first() {
+            Some((&"startpos", _)) => Board::start_position(Kind::Chess),
+            Some((&"fen", fen)) => match Board::from_fen(Kind::Chess, &fen.join(" ")) {
+                Ok(board) => board,
+                Err(err) => return self.send(&format!("info string Invalid fen: {}", err)),
+            },
+            _ => return self.send("info string Expected startpos or fen"),
+        };
+        let mut board = start.clone();
+        let mut parsed = Vec::with_capacity(moves.len());
+        for uci in moves {
+            match board.parse_uci(uci) {
+                Ok(m) => {
+                    board.apply_move(m);
+                    parsed.push(m);
+                }
+                Err(err) => return self.send(&format!("info string Invalid move: {}", err)),
+            }
+        }
+        self.session.start = start;
+        self.session.moves = parsed;
+        Ok(())
+    }
+
+    fn go(&mut self, args: &[&str]) -> io::Result<()> {
+        self.finish_search()?;
+        let params = GoParams::parse(args);
+        let board = self.session.board();
+        let color = board.to_move();
+        let formats: Vec<TimeFormat> = (0..Kind::Chess.color_count())
+            .map(|id| params.clock(ColorKind::new(id)).0)
+            .collect();
+        let mut game = self.session.take_game(color, &formats);
+
+        let move_start = Utc::now();
+        let (time_format, time) = match params.movetime {
+            Some(movetime) => (
+                TimeFormat::PerMove {
+                    nanos_per_move: movetime * NANOS_PER_MILLI,
+                },
+                Some(movetime),
+            ),
+            None if params.infinite => (TimeFormat::Unlimited, None),
+            None => params.clock(color),
+        };
+        let flag_instant =
+            time.map(|time| move_start + chrono::Duration::milliseconds(time as i64));
+        let output = Arc::clone(&self.output);
+        let chess960 = self.chess960;
+        let infinite = params.infinite;
+        let max_depth = params.depth;
+
+        self.search = Some(Search::spawn(move |stop| {
+            let turn = Turn {
+                board: &board,
+                move_start,
+                flag_instant,
+                time_format,
+                max_depth,
+                stop,
+            };
+            let line = match game.player.your_move(&turn) {
+                Action::Move(m) | Action::MoveAndOfferDraw(m) => {
+                    game.moves.push(m);
+                    format!("bestmove {}", uci_move(&board, m, chess960))
+                }
+                Action::Resign => "bestmove 0000".to_owned(),
+            };
+            // An infinite search must not report its move before it is stopped
+            if infinite {
+                return (game, Some(line));
+            }
+            if let Err(err) = send(&output, &line) {
+                eprintln!("Failed to send bestmove: {}", err);
+            }
+            (game, None)
+        }));
+        Ok(())
+    }
+
+    /// Stops the current search, if any, and waits for its move
+    fn finish_search(&mut self) -> io::Result<()> {
+        let search = match self.search.take() {
+            Some(search) => search,
+            None => return Ok(()),
+        };
+        search.stop();
+        match search.handle.join() {
+            Ok((game, line)) => {
+                self.session.game = Some(game);
+                match line {
+                    Some(line) => self.send(&line),
+                    None => Ok(()),
+                }
+            }
+            // The player panicked, so the GUI is still waiting for a move
+            Err(_) => self.send("bestmove 0000"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn run(input: &str) -> (Vec<String>, Vec<String>) {
+        testing::run(
+            |engine, input, output| run_uci_with(engine, input, output),
+            input,
+        )
+    }
+
+    #[test]
+    fn handshake() {
+        let (output, _) = run("uci\nisready\nsetoption name Hash value 16\nquit\n");
+        assert_eq!(
+            output,
+            [
+                "id name Recorder 1.0",
+                "id author Nobody",
+                "uciok",
+                "readyok",
+                "info string Unknown option Hash",
+            ]
+        );
+    }
+
+    #[test]
+    fn searches() {
+        let start = Board::start_position(Kind::Chess);
+        let first = start.to_uci(start.legal_moves()[0]).to_string();
+        let mut board = start.clone();
+        board.apply_move(start.legal_moves()[0]);
+        let reply = board.to_uci(board.legal_moves()[0]).to_string();
+        board.apply_move(board.legal_moves()[0]);
+        let second = board.to_uci(board.legal_moves()[0]).to_string();
+        let endgame = Board::from_fen(Kind::Chess, "4k3/8/8/8/8/8/8/4K2R b K - 0 1").unwrap();
+        let infinite = endgame.to_uci(endgame.legal_moves()[0]).to_string();
+
+        let input = format!(
+            "ucinewgame\n\
+             position startpos\n\
+             go wtime 60000 btime 30000 winc 1000 binc 0\n\
+             position startpos moves {} {}\n\
+             go depth 3\n\
+             position fen 4k3/8/8/8/8/8/8/4K2R b K - 0 1\n\
+             go infinite\n\
+             stop\n\
+             position startpos\n\
+             go movetime 500\n",
+            first, reply
+        );
+        let (output, events) = run(&input);
+        assert_eq!(
+            output,
+            [
+                format!("bestmove {}", first),
+                format!("bestmove {}", second),
+                format!("bestmove {}", infinite),
+                format!("bestmove {}", first),
+            ]
+        );
+        let reply_square = &reply[2..4];
+        let timed = TimeFormat::Timed {
+            initial_nanos: 60_000 * NANOS_PER_MILLI,
+            increment_nanos: 1000 * NANOS_PER_MILLI,
+            delay_nanos: 0,
+        };
+        assert_eq!(
+            events,
+            [
+                format!("new 0 {:?}", timed),
+                "turn Some(60000) None".to_owned(),
+                // The same player continues the game
+                format!("opponent {}", reply_square),
+                "turn None Some(3)".to_owned(),
+                "new 1 Unlimited".to_owned(),
+                "turn None None".to_owned(),
+                "new 0 Unlimited".to_owned(),
+                "turn Some(500) None".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn params() {
+        let params = GoParams::parse(&[
+            "wtime",
+            "100",
+            "btime",
+            "-5",
+            "movestogo",
+            "3",
+            "depth",
+            "7",
+        ]);
+        assert_eq!(
+            params,
+            GoParams {
+                wtime: Some(100),
+                btime: Some(0),
+                movestogo: Some(3),
+                depth: Some(7),
+                ..GoParams::default()
+            }
+        );
+        assert_eq!(
+            params.clock(ColorKind::WHITE),
+            (
+                TimeFormat::Periods {
+                    periods: vec![TimePeriod {
+                        moves: Some(3),
+                        nanos: 100 * NANOS_PER_MILLI,
+                        increment_nanos: 0,
+                        delay_nanos: 0,
+                    }]
+                },
+                Some(100)
+            )
+        );
+        let board = Board::from_fen(Kind::Chess, "4k3/8/8/8/8/8/8/1K1R4 w D - 0 1").unwrap();
+        let castle = board.parse_uci("b1d1").unwrap();
+        assert_eq!(uci_move(&board, castle, true), "b1d1");
+        assert_eq!(
+            uci_move(&board, castle, false),
+            board.to_uci(castle).to_string()
+        );
+    }
+}
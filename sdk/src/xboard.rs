@@ -0,0 +1,651 @@
+//! Runs an engine as a Chess Engine Communication Protocol (XBoard) engine, for GUIs and variant
+//! tools that don't speak UCI.
+//! `level` and `st` set the time format, and `time` gives the engine's clock before each move.
+//! With moves per session in `level`, the base time is added again after every session.
+//! Variant `fischerandom` is played as Chess960, with castling written as O-O and O-O-O. Commands
+//! keep being read while the engine thinks. A draw offered meanwhile is answered once it has chosen
+//! its move, and a `ping` is answered after the move
+
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+
+use giga_core::board::{Board, Move, MoveKind};
+use giga_core::game::{ColorKind, GameEndCause, Kind, TimeFormat, TimePeriod};
+use giga_core::message::{Feature, Protocol, PROTOCOL_VERSION};
+
+use crate::session::{send, Search, Session};
+use crate::{Action, Engine, Turn};
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+/// The XBoard name of Chess960
+const CHESS960_VARIANT: &str = "fischerandom";
+
+/// Runs `engine` as an XBoard engine over stdin and stdout. Returns once the GUI sends `quit` or
+/// closes stdin
+pub fn run_xboard<E: Engine>(engine: E) -> io::Result<()> {
+    let stdin = io::stdin();
+    run_xboard_with(engine, stdin.lock(), io::stdout())
+}
+
+/// Runs `engine` as an XBoard engine, reading commands from `input` and writing replies to
+/// `output`
+pub fn run_xboard_with<E, R, W>(engine: E, input: R, output: W) -> io::Result<()>
+where
+    E: Engine,
+    R: BufRead,
+    W: Write + Send + 'static,
+{
+    let protocol = Protocol {
+        version: PROTOCOL_VERSION,
+        features: vec![Feature::DrawOffers],
+    };
+    let mut frontend = Frontend {
+        session: Session::new(engine, protocol),
+        output: Arc::new(Mutex::new(output)),
+        pending: None,
+        chess960: false,
+        force: false,
+        engine_color: ColorKind::BLACK,
+        clock: XBoardClock::default(),
+    };
+    for line in input.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => continue,
+        };
+        let args: Vec<&str> = words.collect();
+        match command {
+            "?" => frontend.move_now(),
+            "draw" => frontend.draw()?,
+            // These end whatever the engine was thinking about without making its move
+            "new" | "force" | "setboard" | "undo" | "remove" | "result" | "quit" => {
+                frontend.finish_search(true);
+                match command {
+                    "new" => frontend.new_game(),
+                    "force" => frontend.force = true,
+                    "setboard" => frontend.set_board(&args)?,
+                    "undo" => frontend.undo(1),
+                    "remove" => frontend.undo(2),
+                    "result" => frontend.result(&args),
+                    _ => break,
+                }
+            }
+            // These need the engine's move, so wait for it to be chosen
+            "go" | "playother" | "usermove" => {
+                frontend.finish_search(false);
+                frontend.command(command, &args, &line)?;
+            }
+            _ => frontend.command(command, &args, &line)?,
+        }
+    }
+    frontend.finish_search(true);
+    Ok(())
+}
+
+/// Writes a move the way XBoard expects. Chess960 castling is written as O-O or O-O-O, and other
+/// moves in SAN if `san` is set, or in coordinate notation otherwise
+pub fn xboard_move(board: &Board, m: Move, chess960: bool, san: bool) -> String {
+    match m.kind {
+        MoveKind::Castle { rook_src } if chess960 => {
+            if board.file_rank(rook_src).0 > board.file_rank(m.src).0 {
+                "O-O".to_owned()
+            } else {
+                "O-O-O".to_owned()
+            }
+        }
+        _ if san => board.san(m).to_string(),
+        _ => board.to_uci(m).to_string(),
+    }
+}
+
+/// Reads a move in coordinate notation or SAN, which XBoard programs may use interchangeably
+pub fn parse_xboard_move(board: &Board, s: &str) -> Option<Move> {
+    board.parse_uci(s).or_else(|_| board.parse_san(s)).ok()
+}
+
+/// The time settings from `level`, `st`, `sd`, `time` and `otim`. Times are in milliseconds
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct XBoardClock {
+    /// The moves per session, base time and increment from `level`. Zero moves per session means
+    /// the base time lasts the whole game
+    level: Option<(u32, u64, u64)>,
+    /// The exact time per move from `st`
+    move_time: Option<u64>,
+    depth: Option<u32>,
+    /// The engine's clock from `time`
+    own: Option<u64>,
+}
+
+/// A search for the engine's move, along with what the GUI sent while it ran
+struct Pending {
+    /// Finishes with the move that was sent, if any
+    search: Search<Option<Move>>,
+    /// Set with the output locked, so that an aborted search never sends its move
+    aborted: Arc<AtomicBool>,
+    /// Set if the opponent offered a draw during the search
+    draw_offered: Arc<AtomicBool>,
+    /// Lines to send after the move, such as pongs. Taken with the output locked once the move has
+    /// been sent, or the search aborted
+    queued: Arc<Mutex<Option<Vec<String>>>>,
+}
+
+struct Frontend<E, W> {
+    session: Session<E>,
+    output: Arc<Mutex<W>>,
+    pending: Option<Pending>,
+    chess960: bool,
+    /// In force mode the engine only keeps track of the moves it is sent
+    force: bool,
+    engine_color: ColorKind,
+    clock: XBoardClock,
+}
+
+/// Parses the base time of `level`, which is either minutes or minutes:seconds, in milliseconds
+fn parse_base(s: &str) -> Option<u64> {
+    let mut parts = s.splitn(2, ':');
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: u64 = match parts.next() {
+        Some(seconds) => seconds.parse().ok()?,
+        None => 0,
+    };
+    Some((minutes * 60 + seconds) * 1000)
+}
+
+/// Parses seconds, which may have a fractional part, in milliseconds
+fn parse_seconds(s: &str) -> Option<u64> {
+    let seconds: f64 = s.parse().ok()?;
+    if seconds < 0.0 {
+        return None;
+    }
+    Some((seconds * 1000.0).round() as u64)
+}
+
+impl XBoardClock {
+    /// Sets the time control from the arguments of a `level` command
+    fn set_level(&mut self, args: &[&str]) -> Option<()> {
+        match args {
+            [moves_per_session, base, increment] => {
+                self.level = Some((
+                    moves_per_session.parse().ok()?,
+                    parse_base(base)?,
+                    parse_seconds(increment)?,
+                ));
+                self.move_time = None;
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    /// The time format of the engine and its opponent
+    fn formats(&self) -> (TimeFormat, TimeFormat) {
+        let opponent = match self.level {
+            Some((0, base, increment)) => TimeFormat::Timed {
+                initial_nanos: base * NANOS_PER_MILLI,
+                increment_nanos: increment * NANOS_PER_MILLI,
+                delay_nanos: 0,
+            },
+            Some((moves, base, increment)) => TimeFormat::Periods {
+                periods: vec![TimePeriod {
+                    moves: Some(moves),
+                    nanos: base * NANOS_PER_MILLI,
+                    increment_nanos: increment * NANOS_PER_MILLI,
+                    delay_nanos: 0,
+                }],
+            },
+            None => TimeFormat::Unlimited,
+        };
+        let own = match self.move_time {
+            Some(move_time) => TimeFormat::PerMove {
+                nanos_per_move: move_time * NANOS_PER_MILLI,
+            },
+            None => opponent.clone(),
+        };
+        (own, opponent)
+    }
+
+    /// The milliseconds the engine has for its move, or None if it has unlimited time
+    fn time_left(&self) -> Option<u64> {
+        self.move_time
+            .or(self.own)
+            .or_else(|| self.level.map(|(_, base, _)| base))
+    }
+}
+
+impl<E: Engine, W: Write + Send + 'static> Frontend<E, W> {
+    fn send(&self, line: &str) -> io::Result<()> {
+        send(&self.output, line)
+    }
+
+    /// Sends a line once the engine's move has been sent, or right away if it isn't thinking
+    fn send_after_move(&self, line: String) -> io::Result<()> {
+        let mut output = self.output.lock().unwrap();
+        if let Some(pending) = self.pending.as_ref() {
+            if let Some(queued) = pending.queued.lock().unwrap().as_mut() {
+                queued.push(line);
+                return Ok(());
+            }
+        }
+        writeln!(output, "{}", line)?;
+        output.flush()
+    }
+
+    fn command(&mut self, command: &str, args: &[&str], line: &str) -> io::Result<()> {
+        let invalid = || format!("Error (invalid arguments): {}", line);
+        match command {
+            "protover" => self.features()?,
+            "variant" => match args {
+                ["normal"] => self.chess960 = false,
+                [CHESS960_VARIANT] if self.session.supports_chess960() => self.chess960 = true,
+                _ => self.send(&format!("Error (unsupported variant): {}", line))?,
+            },
+            "go" => {
+                self.force = false;
+                self.engine_color = self.session.board().to_move();
+                self.think();
+            }
+            "playother" => {
+                self.force = false;
+                let board = self.session.board();
+                self.engine_color = board.next_color(board.to_move());
+            }
+            "level" => {
+                if self.clock.set_level(args).is_none() {
+                    self.send(&invalid())?;
+                }
+            }
+            "st" => match args.first().and_then(|s| parse_seconds(s)) {
+                Some(move_time) => self.clock.move_time = Some(move_time),
+                None => self.send(&invalid())?,
+            },
+            "sd" => match args.first().and_then(|s| s.parse().ok()) {
+                Some(depth) => self.clock.depth = Some(depth),
+                None => self.send(&invalid())?,
+            },
+            "time" => match args.first().and_then(|s| s.parse::<i64>().ok()) {
+                // Centiseconds, which may be negative once the engine has flagged
+                Some(centis) => self.clock.own = Some(centis.max(0) as u64 * 10),
+                None => self.send(&invalid())?,
+            },
+            "ping" => {
+                let id = args.first().copied().unwrap_or("");
+                self.send_after_move(format!("pong {}", id))?;
+            }
+            "usermove" => match args.first() {
+                Some(m) => self.user_move(m)?,
+                None => self.send(&invalid())?,
+            },
+            // The opponent's clock isn't needed as players only think about their own
+            "otim" | "xboard" | "accepted" | "rejected" | "hard" | "easy" | "post" | "nopost"
+            | "computer" | "random" | "name" | "rating" | "ics" => {}
+            _ => self.send(&format!("Error (unknown command): {}", command))?,
+        }
+        Ok(())
+    }
+
+    fn features(&self) -> io::Result<()> {
+        let info = self.session.engine.info();
+        let name = if info.version.is_empty() {
+            info.name
+        } else {
+            format!("{} {}", info.name, info.version)
+        };
+        let variants = if self.session.supports_chess960() {
+            format!("normal,{}", CHESS960_VARIANT)
+        } else {
+            "normal".to_owned()
+        };
+        self.send(&format!(
+            "feature myname=\"{}\" variants=\"{}\" setboard=1 usermove=1 ping=1 draw=1 \
+             sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 done=1",
+            name, variants
+        ))
+    }
+
+    fn new_game(&mut self) {
+        self.session.start = Board::start_position(Kind::Chess);
+        self.session.moves.clear();
+        self.session.game = None;
+        self.chess960 = false;
+        self.force = false;
+        self.engine_color = ColorKind::BLACK;
+        self.clock.own = None;
+        self.clock.depth = None;
+    }
+
+    fn set_board(&mut self, args: &[&str]) -> io::Result<()> {
+        match Board::from_fen(Kind::Chess, &args.join(" ")) {
+            Ok(board) => {
+                self.session.start = board;
+                self.session.moves.clear();
+                Ok(())
+            }
+            Err(err) => self.send(&format!("tellusererror Illegal position: {}", err)),
+        }
+    }
+
+    fn undo(&mut self, plies: usize) {
+        let len = self.session.moves.len().saturating_sub(plies);
+        self.session.moves.truncate(len);
+    }
+
+    fn user_move(&mut self, s: &str) -> io::Result<()> {
+        let board = self.session.board();
+        let m = match parse_xboard_move(&board, s) {
+            Some(m) => m,
+            None => return self.send(&format!("Illegal move: {}", s)),
+        };
+        self.session.moves.push(m);
+        let board = self.session.board();
+        if !self.force && board.game_end().is_none() && board.to_move() == self.engine_color {
+            self.think();
+        }
+        Ok(())
+    }
+
+    /// Ends the game of the player following it, if any
+    fn result(&mut self, args: &[&str]) {
+        self.force = true;
+        let mut game = match self.session.game.take() {
+            Some(game) => game,
+            None => return,
+        };
+        let board = self.session.board();
+        let (winner, cause) = match args.first().copied() {
+            Some("1-0") => (Some(ColorKind::WHITE), GameEndCause::Resign),
+            Some("0-1") => (Some(ColorKind::BLACK), GameEndCause::Resign),
+            Some("1/2-1/2") => (None, GameEndCause::DrawOffer),
+            // An unfinished game
+            _ => return,
+        };
+        // The comment after the result is free text, so the cause is only known if the final
+        // position shows it
+        let cause = board.game_end().unwrap_or(cause);
+        game.player.game_over(winner, &cause);
+    }
+
+    fn move_now(&self) {
+        if let Some(pending) = self.pending.as_ref() {
+            pending.search.stop();
+        }
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        if let Some(pending) = self.pending.as_ref() {
+            let _output = self.output.lock().unwrap();
+            if pending.queued.lock().unwrap().is_some() {
+                pending.draw_offered.store(true, Ordering::SeqCst);
+                return Ok(());
+            }
+        }
+        // The engine has already moved, so it answers as the opponent to move
+        self.finish_search(false);
+        let board = self.session.board();
+        let opponent = board.next_color(self.engine_color);
+        let accepted = match self.session.game.as_mut() {
+            Some(game) => game.player.draw_offer(&board, opponent),
+            None => false,
+        };
+        if accepted {
+            self.send("offer draw")?;
+        }
+        Ok(())
+    }
+
+    /// Starts thinking about the engine's move on another thread. The move is sent as soon as it
+    /// is chosen
+    fn think(&mut self) {
+        let board = self.session.board();
+        let color = board.to_move();
+        let (own, opponent) = self.clock.formats();
+        let formats: Vec<TimeFormat> = (0..Kind::Chess.color_count())
+            .map(|id| {
+                if id == color.id() {
+                    own.clone()
+                } else {
+                    opponent.clone()
+                }
+            })
+            .collect();
+        let mut game = self.session.take_game(color, &formats);
+
+        let move_start = Utc::now();
+        let flag_instant = self
+            .clock
+            .time_left()
+            .map(|time| move_start + chrono::Duration::milliseconds(time as i64));
+        let aborted = Arc::new(AtomicBool::new(false));
+        let draw_offered = Arc::new(AtomicBool::new(false));
+        let queued = Arc::new(Mutex::new(Some(Vec::new())));
+        let pending = Pending {
+            search: Search::spawn({
+                let aborted = Arc::clone(&aborted);
+                let draw_offered = Arc::clone(&draw_offered);
+                let queued = Arc::clone(&queued);
+                let output = Arc::clone(&self.output);
+                let chess960 = self.chess960;
+                let max_depth = self.clock.depth;
+                move |stop| {
+                    let turn = Turn {
+                        board: &board,
+                        move_start,
+                        flag_instant,
+                        time_format: own,
+                        max_depth,
+                        stop,
+                    };
+                    let action = game.player.your_move(&turn);
+                    // Draw offers are only made with the output locked
+                    let mut output = output.lock().unwrap();
+                    if aborted.load(Ordering::SeqCst) {
+                        return (game, None);
+                    }
+                    let accept_draw = draw_offered.load(Ordering::SeqCst)
+                        && game.player.draw_offer(&board, board.next_color(color));
+
+                    let mut lines = Vec::new();
+                    if accept_draw || matches!(action, Action::MoveAndOfferDraw(_)) {
+                        lines.push("offer draw".to_owned());
+                    }
+                    let m = match action {
+                        Action::Move(m) | Action::MoveAndOfferDraw(m) => {
+                            lines.push(format!("move {}", xboard_move(&board, m, chess960, false)));
+                            Some(m)
+                        }
+                        Action::Resign => {
+                            lines.push("resign".to_owned());
+                            None
+                        }
+                    };
+                    if let Some(m) = m {
+                        game.moves.push(m);
+                    }
+                    lines.extend(queued.lock().unwrap().take().unwrap_or_default());
+                    for line in lines {
+                        if let Err(err) = writeln!(output, "{}", line) {
+                            eprintln!("Failed to send move: {}", err);
+                        }
+                    }
+                    let _ = output.flush();
+                    (game, m)
+                }
+            }),
+            aborted,
+            draw_offered,
+            queued,
+        };
+        self.pending = Some(pending);
+    }
+
+    /// Waits for the current search, if any, and plays its move. An aborted search is stopped
+    /// right away and its move is discarded
+    fn finish_search(&mut self, abort: bool) {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        if abort {
+            let mut output = self.output.lock().unwrap();
+            pending.aborted.store(true, Ordering::SeqCst);
+            pending.search.stop();
+            for line in pending.queued.lock().unwrap().take().unwrap_or_default() {
+                if let Err(err) = writeln!(output, "{}", line) {
+                    eprintln!("Failed to send queued line: {}", err);
+                }
+            }
+            let _ = output.flush();
+        }
+        if let Ok((game, m)) = pending.search.handle.join() {
+            if let Some(m) = m {
+                self.session.moves.push(m);
+            }
+            self.session.game = Some(game);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Shared};
+    use crate::{GameInfo, Player};
+    use giga_core::message::EngineInfo;
+    use std::thread;
+
+    fn run(input: &str) -> (Vec<String>, Vec<String>) {
+        testing::run(
+            |engine, input, output| run_xboard_with(engine, input, output),
+            input,
+        )
+    }
+
+    fn first_move(board: &Board) -> (Board, String) {
+        let m = board.legal_moves()[0];
+        let mut after = board.clone();
+        after.apply_move(m);
+        (after, board.to_uci(m).to_string())
+    }
+
+    #[test]
+    fn plays_a_game() {
+        let start = Board::start_position(Kind::Chess);
+        let mut board = start.clone();
+        board.apply_move(board.parse_uci("e2e4").unwrap());
+        let (mut board, reply) = first_move(&board);
+        board.apply_move(board.parse_uci("d2d4").unwrap());
+        let (_, second) = first_move(&board);
+
+        // Pongs wait for the engine's move, and playother waits for it to be chosen so that the
+        // draw offer comes after it
+        let input = "xboard\nprotover 2\nnew\nlevel 40 5 2\ntime 30000\notim 30000\n\
+                     usermove e2e4\nping 1\nusermove d2d4\nping 2\nplayother\ndraw\n\
+                     usermove e4e4\nresult 1/2-1/2 {Draw agreed}\nquit\n";
+        let (output, events) = run(input);
+        assert_eq!(
+            output,
+            [
+                "feature myname=\"Recorder 1.0\" variants=\"normal\" setboard=1 usermove=1 ping=1 \
+                 draw=1 sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 done=1"
+                    .to_owned(),
+                format!("move {}", reply),
+                "pong 1".to_owned(),
+                format!("move {}", second),
+                "pong 2".to_owned(),
+                "offer draw".to_owned(),
+                "Illegal move: e4e4".to_owned(),
+            ]
+        );
+        let format = TimeFormat::Periods {
+            periods: vec![TimePeriod {
+                moves: Some(40),
+                nanos: 300_000 * NANOS_PER_MILLI,
+                increment_nanos: 2000 * NANOS_PER_MILLI,
+                delay_nanos: 0,
+            }],
+        };
+        assert_eq!(
+            events,
+            [
+                format!("new 1 {:?}", format),
+                "turn Some(300000) None".to_owned(),
+                "opponent d4".to_owned(),
+                // time wasn't sent again, so the last value is used
+                "turn Some(300000) None".to_owned(),
+                "over None DrawOffer".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn go_and_force() {
+        let input = "new\nforce\nusermove e2e4\nsd 4\nst 5\ngo\nping 1\nplayother\nforce\n\
+                     usermove d2d4\nvariant fischerandom\nquit\n";
+        let (output, events) = run(input);
+        assert_eq!(output.len(), 3);
+        assert!(output[0].starts_with("move "));
+        assert_eq!(output[1], "pong 1");
+        assert_eq!(
+            output[2],
+            "Error (unsupported variant): variant fischerandom"
+        );
+        assert_eq!(events[1], "turn Some(5000) Some(4)");
+    }
+
+    /// Thinks until it is told to move now
+    struct Waiter;
+
+    impl Engine for Waiter {
+        fn info(&self) -> EngineInfo {
+            EngineInfo::default()
+        }
+
+        fn new_player(&mut self, _: &GameInfo) -> Box<dyn Player> {
+            Box::new(Waiter)
+        }
+    }
+
+    impl Player for Waiter {
+        fn your_move(&mut self, turn: &Turn) -> Action {
+            while !turn.stop.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+            Action::Move(turn.board.legal_moves()[0])
+        }
+    }
+
+    #[test]
+    fn reads_commands_while_thinking() {
+        let output = Shared::default();
+        let input = "new\nusermove e2e4\nping 1\n?\nplayother\nquit\n";
+        run_xboard_with(Waiter, input.as_bytes(), output.clone()).unwrap();
+        let mut board = Board::start_position(Kind::Chess);
+        board.apply_move(board.parse_uci("e2e4").unwrap());
+        let (_, reply) = first_move(&board);
+        assert_eq!(
+            output.lines(),
+            [format!("move {}", reply), "pong 1".to_owned()]
+        );
+    }
+
+    #[test]
+    fn notation() {
+        let board = Board::from_fen(Kind::Chess, "4k3/8/8/8/8/8/8/1K1R4 w D - 0 1").unwrap();
+        let castle = board.parse_uci("b1d1").unwrap();
+        assert_eq!(xboard_move(&board, castle, true, false), "O-O");
+        assert_eq!(parse_xboard_move(&board, "O-O"), Some(castle));
+
+        let board = Board::start_position(Kind::Chess);
+        let knight = board.parse_uci("g1f3").unwrap();
+        assert_eq!(xboard_move(&board, knight, false, true), "Nf3");
+        assert_eq!(xboard_move(&board, knight, false, false), "g1f3");
+        assert_eq!(parse_xboard_move(&board, "Nf3"), Some(knight));
+        assert_eq!(parse_base("0:30"), Some(30_000));
+        assert_eq!(parse_seconds("0.5"), Some(500));
+    }
+}